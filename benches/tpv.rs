@@ -0,0 +1,159 @@
+//! Deserializing TPVs, compared with the untagged enum `TpvResponse` used to
+//! be. Run with `cargo bench`.
+extern crate chrono;
+#[macro_use]
+extern crate criterion;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate unbounded_gpsd;
+
+use criterion::{black_box, Criterion};
+use unbounded_gpsd::types::{Response, TpvResponse};
+
+/// The TPV types as they were, deserialized by trying each variant in turn.
+mod old {
+    use chrono::{DateTime, Utc};
+    use serde_json::{Map, Value};
+    use unbounded_gpsd::types::{FixMode, SkyResponse, TpvDetails};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    #[allow(dead_code)]
+    pub enum TpvResponse {
+        Fix3D {
+            device: Option<String>,
+            time: DateTime<Utc>,
+            mode: FixMode,
+            #[serde(rename = "ept")]
+            time_err: f64,
+            lat: f64,
+            #[serde(rename = "epy")]
+            lat_err: Option<f64>,
+            lon: f64,
+            #[serde(rename = "epx")]
+            lon_err: Option<f64>,
+            alt: f64,
+            #[serde(rename = "epv")]
+            alt_err: Option<f64>,
+            track: Option<f64>,
+            #[serde(rename = "epd")]
+            track_err: Option<f64>,
+            speed: f64,
+            #[serde(rename = "eps")]
+            speed_err: Option<f64>,
+            climb: f64,
+            #[serde(rename = "epc")]
+            climb_err: Option<f64>,
+            #[serde(flatten)]
+            details: TpvDetails,
+            #[serde(flatten)]
+            extra: Map<String, Value>
+        },
+        Fix2D {
+            device: Option<String>,
+            time: DateTime<Utc>,
+            mode: FixMode,
+            #[serde(rename = "ept")]
+            time_err: f64,
+            lat: f64,
+            #[serde(rename = "epy")]
+            lat_err: Option<f64>,
+            lon: f64,
+            #[serde(rename = "epx")]
+            lon_err: Option<f64>,
+            track: Option<f64>,
+            #[serde(rename = "epd")]
+            track_err: Option<f64>,
+            speed: f64,
+            #[serde(rename = "eps")]
+            speed_err: Option<f64>,
+            #[serde(flatten)]
+            details: TpvDetails,
+            #[serde(flatten)]
+            extra: Map<String, Value>
+        },
+        LatLonOnly {
+            device: Option<String>,
+            time: DateTime<Utc>,
+            mode: FixMode,
+            #[serde(rename = "ept")]
+            time_err: f64,
+            lat: f64,
+            #[serde(rename = "epy")]
+            lat_err: Option<f64>,
+            lon: f64,
+            #[serde(rename = "epx")]
+            lon_err: Option<f64>,
+            alt: Option<f64>,
+            #[serde(rename = "epv")]
+            alt_err: Option<f64>,
+            track: Option<f64>,
+            #[serde(rename = "epd")]
+            track_err: Option<f64>,
+            speed: Option<f64>,
+            #[serde(rename = "eps")]
+            speed_err: Option<f64>,
+            climb: Option<f64>,
+            #[serde(rename = "epc")]
+            climb_err: Option<f64>,
+            #[serde(flatten)]
+            details: TpvDetails,
+            #[serde(flatten)]
+            extra: Map<String, Value>
+        },
+        NoFix {
+            device: Option<String>,
+            time: DateTime<Utc>,
+            mode: FixMode,
+            #[serde(flatten)]
+            details: TpvDetails,
+            #[serde(flatten)]
+            extra: Map<String, Value>
+        },
+        Nothing {
+            device: Option<String>,
+            time: Option<DateTime<Utc>>,
+            mode: Option<FixMode>,
+            #[serde(flatten)]
+            details: TpvDetails,
+            #[serde(flatten)]
+            extra: Map<String, Value>
+        }
+    }
+    #[derive(Deserialize)]
+    #[serde(tag = "class")]
+    #[allow(dead_code, clippy::large_enum_variant)]
+    pub enum Response {
+        #[serde(rename = "TPV")]
+        Tpv(TpvResponse),
+        #[serde(rename = "SKY")]
+        Sky(SkyResponse)
+    }
+}
+
+const FIX_3D: &str = r#"{"class":"TPV","device":"/dev/ttyACM0","status":2,"mode":3,"time":"2019-10-04T08:51:34.000Z","leapseconds":18,"ept":0.005,"lat":59.345015,"lon":18.054897,"altHAE":101.4,"altMSL":78.2,"alt":78.2,"epx":21.5,"epy":33.1,"epv":51.75,"track":320.93,"magtrack":326.1,"magvar":5.2,"speed":0.075,"climb":0.7,"eps":66.2,"epc":103.5,"ecefx":3100142.49,"ecefy":1011368.56,"ecefz":5463720.12,"ecefvx":0.01,"ecefvy":-0.02,"ecefvz":0.03,"ecefpAcc":12.5,"ecefvAcc":0.07,"velN":0.06,"velE":-0.04,"velD":-0.7,"geoidSep":23.2,"eph":39.5,"sep":64.3}"#;
+const NO_FIX: &str = r#"{"class":"TPV","device":"/dev/ttyACM0","mode":1,"time":"2019-10-04T08:51:34.000Z","ept":0.005}"#;
+
+fn deserialize(c: &mut Criterion) {
+    for &(name, line) in [("3D fix", FIX_3D), ("no fix", NO_FIX)].iter() {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("untagged Response", |b| {
+            b.iter(|| serde_json::from_str::<old::Response>(black_box(line)).unwrap())
+        });
+        group.bench_function("Response", |b| {
+            b.iter(|| serde_json::from_str::<Response>(black_box(line)).unwrap())
+        });
+        group.bench_function("untagged TpvResponse", |b| {
+            b.iter(|| serde_json::from_str::<old::TpvResponse>(black_box(line)).unwrap())
+        });
+        group.bench_function("TpvResponse", |b| {
+            b.iter(|| serde_json::from_str::<TpvResponse>(black_box(line)).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, deserialize);
+criterion_main!(benches);
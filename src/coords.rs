@@ -0,0 +1,280 @@
+//! Conversion between geodetic, ECEF, and local tangent plane coordinates.
+//!
+//! - Geodetic coordinates are latitude, longitude and height, as in a
+//!   `Position`.
+//! - ECEF (earth-centred, earth-fixed) coordinates are meters along axes
+//!   through the centre of the WGS-84 ellipsoid, the same frame as gpsd's
+//!   `ecefx`/`ecefy`/`ecefz` fields.
+//! - ENU (east, north, up) and NED (north, east, down) coordinates are meters
+//!   in a flat frame tangent to the ellipsoid at some origin, set up with a
+//!   `LocalFrame`.
+//!
+//! ECEF coordinates are relative to the ellipsoid, so heights going in and out
+//! of them are heights above the ellipsoid (HAE), not above mean sea level.
+//! `TpvResponse::ecef` takes care of this for fixes.
+use geo::{WGS84_A, WGS84_B, WGS84_F};
+use types::{Position, TpvResponse, Velocity};
+
+/// First eccentricity squared of the WGS-84 ellipsoid.
+const E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// A point (or velocity) in ECEF coordinates, in meters (or meters per second).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Ecef {
+    /// Towards latitude 0, longitude 0.
+    pub x: f64,
+    /// Towards latitude 0, longitude 90 east.
+    pub y: f64,
+    /// Towards the north pole.
+    pub z: f64
+}
+/// A point (or velocity) in a local east-north-up frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Enu {
+    /// Meters east.
+    pub east: f64,
+    /// Meters north.
+    pub north: f64,
+    /// Meters up.
+    pub up: f64
+}
+/// A point (or velocity) in a local north-east-down frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Ned {
+    /// Meters north.
+    pub north: f64,
+    /// Meters east.
+    pub east: f64,
+    /// Meters down.
+    pub down: f64
+}
+impl From<Enu> for Ned {
+    fn from(enu: Enu) -> Ned {
+        Ned { north: enu.north, east: enu.east, down: -enu.up }
+    }
+}
+impl From<Ned> for Enu {
+    fn from(ned: Ned) -> Enu {
+        Enu { east: ned.east, north: ned.north, up: -ned.down }
+    }
+}
+impl Ecef {
+    /// Convert from latitude and longitude (in degrees) and height above the
+    /// ellipsoid (in meters).
+    pub fn from_geodetic(lat: f64, lon: f64, hae: f64) -> Ecef {
+        let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+        // prime vertical radius of curvature
+        let n = WGS84_A / (1.0 - E2 * sin_lat * sin_lat).sqrt();
+        Ecef {
+            x: (n + hae) * cos_lat * cos_lon,
+            y: (n + hae) * cos_lat * sin_lon,
+            z: (n * (1.0 - E2) + hae) * sin_lat
+        }
+    }
+    /// Convert to latitude, longitude and height above the ellipsoid, using
+    /// Zhu's closed-form method.
+    ///
+    /// The altitude of the position returned is always present.
+    pub fn to_geodetic(&self) -> Position {
+        let (a, b) = (WGS84_A, WGS84_B);
+        let ep2 = (a * a - b * b) / (b * b);
+        let p = (self.x * self.x + self.y * self.y).sqrt();
+        let z = self.z;
+        // Zhu's method breaks down near the polar axis, but there the simple
+        // fixed-point iteration converges very quickly.
+        if p < 1.0 {
+            let mut lat = z.atan2(p * (1.0 - E2));
+            let mut h = 0.0;
+            for _ in 0..5 {
+                let sin_lat = lat.sin();
+                let n = a / (1.0 - E2 * sin_lat * sin_lat).sqrt();
+                h = z / sin_lat - n * (1.0 - E2);
+                lat = z.atan2(p * (1.0 - E2 * n / (n + h)));
+            }
+            return Position {
+                lat: lat.to_degrees(),
+                lon: self.y.atan2(self.x).to_degrees(),
+                alt: Some(h)
+            };
+        }
+        let f = 54.0 * b * b * z * z;
+        let g = p * p + (1.0 - E2) * z * z - E2 * (a * a - b * b);
+        let c = E2 * E2 * f * p * p / (g * g * g);
+        let s = (1.0 + c + (c * c + 2.0 * c).sqrt()).cbrt();
+        let k = s + 1.0 + 1.0 / s;
+        let big_p = f / (3.0 * k * k * g * g);
+        let q = (1.0 + 2.0 * E2 * E2 * big_p).sqrt();
+        let r0 = -big_p * E2 * p / (1.0 + q) +
+            (a * a / 2.0 * (1.0 + 1.0 / q) - big_p * (1.0 - E2) * z * z / (q * (1.0 + q)) - big_p * p * p / 2.0).sqrt();
+        let u = ((p - E2 * r0).powi(2) + z * z).sqrt();
+        let v = ((p - E2 * r0).powi(2) + (1.0 - E2) * z * z).sqrt();
+        let z0 = b * b * z / (a * v);
+        Position {
+            lat: (z + ep2 * z0).atan2(p).to_degrees(),
+            lon: self.y.atan2(self.x).to_degrees(),
+            alt: Some(u * (1.0 - b * b / (a * v)))
+        }
+    }
+}
+/// A local tangent plane, for converting positions into ENU or NED
+/// coordinates (in meters) relative to an origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalFrame {
+    origin: Position,
+    origin_ecef: Ecef,
+    sin_lat: f64,
+    cos_lat: f64,
+    sin_lon: f64,
+    cos_lon: f64
+}
+impl LocalFrame {
+    /// Make a frame centred on `origin`, whose altitude is taken as a height
+    /// above the ellipsoid (0 if missing).
+    pub fn new(origin: Position) -> LocalFrame {
+        let hae = origin.alt.unwrap_or(0.0);
+        let origin = Position { alt: Some(hae), ..origin };
+        let (sin_lat, cos_lat) = origin.lat.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = origin.lon.to_radians().sin_cos();
+        LocalFrame {
+            origin,
+            origin_ecef: Ecef::from_geodetic(origin.lat, origin.lon, hae),
+            sin_lat, cos_lat, sin_lon, cos_lon
+        }
+    }
+    /// The origin of the frame. Its altitude is always present.
+    pub fn origin(&self) -> Position {
+        self.origin
+    }
+    /// Rotate an ECEF vector (such as a velocity) into this frame, without
+    /// translating it.
+    pub fn rotate_to_enu(&self, v: Ecef) -> Enu {
+        Enu {
+            east: -self.sin_lon * v.x + self.cos_lon * v.y,
+            north: -self.sin_lat * self.cos_lon * v.x - self.sin_lat * self.sin_lon * v.y + self.cos_lat * v.z,
+            up: self.cos_lat * self.cos_lon * v.x + self.cos_lat * self.sin_lon * v.y + self.sin_lat * v.z
+        }
+    }
+    /// Rotate a vector in this frame (such as a velocity) into ECEF, without
+    /// translating it.
+    pub fn rotate_to_ecef(&self, v: Enu) -> Ecef {
+        Ecef {
+            x: -self.sin_lon * v.east - self.sin_lat * self.cos_lon * v.north + self.cos_lat * self.cos_lon * v.up,
+            y: self.cos_lon * v.east - self.sin_lat * self.sin_lon * v.north + self.cos_lat * self.sin_lon * v.up,
+            z: self.cos_lat * v.north + self.sin_lat * v.up
+        }
+    }
+    /// Convert an ECEF point to ENU coordinates in this frame.
+    pub fn ecef_to_enu(&self, p: Ecef) -> Enu {
+        self.rotate_to_enu(Ecef {
+            x: p.x - self.origin_ecef.x,
+            y: p.y - self.origin_ecef.y,
+            z: p.z - self.origin_ecef.z
+        })
+    }
+    /// Convert ENU coordinates in this frame to an ECEF point.
+    pub fn enu_to_ecef(&self, p: Enu) -> Ecef {
+        let v = self.rotate_to_ecef(p);
+        Ecef {
+            x: v.x + self.origin_ecef.x,
+            y: v.y + self.origin_ecef.y,
+            z: v.z + self.origin_ecef.z
+        }
+    }
+    /// Convert a position to ENU coordinates in this frame. Its altitude is
+    /// taken as a height above the ellipsoid; if it's missing, the origin's is
+    /// used.
+    pub fn position_to_enu(&self, pos: &Position) -> Enu {
+        let hae = pos.alt.or(self.origin.alt).unwrap_or(0.0);
+        self.ecef_to_enu(Ecef::from_geodetic(pos.lat, pos.lon, hae))
+    }
+    /// Convert a position to NED coordinates in this frame.
+    pub fn position_to_ned(&self, pos: &Position) -> Ned {
+        self.position_to_enu(pos).into()
+    }
+    /// Convert ENU coordinates in this frame back to a position, with its
+    /// height above the ellipsoid.
+    pub fn enu_to_position(&self, enu: Enu) -> Position {
+        self.enu_to_ecef(enu).to_geodetic()
+    }
+    /// Convert NED coordinates in this frame back to a position, with its
+    /// height above the ellipsoid.
+    pub fn ned_to_position(&self, ned: Ned) -> Position {
+        self.enu_to_position(ned.into())
+    }
+}
+impl Velocity {
+    /// This velocity in east-north-up components, in meters per second. A
+    /// missing climb rate is taken as 0.
+    ///
+    /// Returns `None` if there's no track, unless the speed is 0.
+    pub fn to_enu(&self) -> Option<Enu> {
+        let track = match self.track {
+            Some(t) => t,
+            None if self.speed == 0.0 => 0.0,
+            None => return None
+        };
+        let (sin, cos) = track.to_radians().sin_cos();
+        Some(Enu {
+            east: self.speed * sin,
+            north: self.speed * cos,
+            up: self.climb.unwrap_or(0.0)
+        })
+    }
+    /// This velocity in north-east-down components, in meters per second.
+    pub fn to_ned(&self) -> Option<Ned> {
+        self.to_enu().map(Ned::from)
+    }
+    /// Make a velocity from east-north-up components, in meters per second.
+    pub fn from_enu(enu: Enu) -> Velocity {
+        let speed = enu.east.hypot(enu.north);
+        let track = enu.east.atan2(enu.north).to_degrees();
+        Velocity {
+            speed,
+            track: Some(if track < 0.0 { track + 360.0 } else { track }),
+            climb: Some(enu.up)
+        }
+    }
+}
+impl TpvResponse {
+    /// Height above the ellipsoid, in meters: `altHAE` if gpsd sent it, or
+    /// otherwise worked out from `altMSL` (or `alt`) and `geoidSep`. If there's
+    /// no geoid separation, the altitude is used as it is.
+    pub fn hae(&self) -> Option<f64> {
+        let details = self.details();
+        if let Some(hae) = details.alt_hae {
+            return Some(hae);
+        }
+        let msl = details.alt_msl.or_else(|| self.alt())?;
+        Some(msl + details.geoid_sep.unwrap_or(0.0))
+    }
+    /// ECEF position: gpsd's `ecefx`/`ecefy`/`ecefz` if it sent them, or
+    /// otherwise converted from the latitude, longitude and `hae()`.
+    pub fn ecef(&self) -> Option<Ecef> {
+        let details = self.details();
+        if let (Some(x), Some(y), Some(z)) = (details.ecef_x, details.ecef_y, details.ecef_z) {
+            return Some(Ecef { x, y, z });
+        }
+        Some(Ecef::from_geodetic(self.lat()?, self.lon()?, self.hae()?))
+    }
+    /// ECEF velocity: gpsd's `ecefvx`/`ecefvy`/`ecefvz`, if it sent them.
+    pub fn ecef_velocity(&self) -> Option<Ecef> {
+        let details = self.details();
+        Some(Ecef { x: details.ecef_vx?, y: details.ecef_vy?, z: details.ecef_vz? })
+    }
+    /// Position in ENU coordinates relative to `frame`, using `hae()` for the
+    /// height.
+    pub fn to_enu(&self, frame: &LocalFrame) -> Option<Enu> {
+        let pos = Position { lat: self.lat()?, lon: self.lon()?, alt: self.hae() };
+        Some(frame.position_to_enu(&pos))
+    }
+    /// Velocity in ENU components: from gpsd's `velN`/`velE`/`velD` if it
+    /// sent them, or otherwise from the track, speed and climb.
+    pub fn enu_velocity(&self) -> Option<Enu> {
+        let details = self.details();
+        if let (Some(n), Some(e), Some(d)) = (details.vel_n, details.vel_e, details.vel_d) {
+            return Some(Ned { north: n, east: e, down: d }.into());
+        }
+        self.velocity()?.to_enu()
+    }
+}
@@ -0,0 +1,305 @@
+//! Writing TPVs and SKYs as CSV (or TSV), for spreadsheets and the like.
+//!
+//! `CsvWriter` writes a row for each TPV, whatever its variant, with the
+//! columns chosen when it's made. A `Column` is either a TPV field, by the
+//! name gpsd uses for it (`lat`, `epx`, `altHAE`, and so on), or a summary of
+//! the SKY for the same epoch: satellite counts, mean signal-to-noise ratio,
+//! or a DOP. Cells for fields a TPV doesn't have are left blank.
+//!
+//! gpsd sends a TPV and (sometimes) a SKY for each epoch, in either order, so
+//! a TPV's row isn't written until the next epoch starts (or `finish` is
+//! called), in case its SKY is still to come. Newer versions of gpsd don't
+//! send a SKY every epoch; rows use the most recent one.
+//!
+//! Times are written as RFC 3339, or as seconds since the Unix epoch (see
+//! `set_time_format`). Cells are quoted as in RFC 4180 when they need to be,
+//! whatever the delimiter.
+use std::fmt;
+use std::io::{self, Write};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{self, Map, Value};
+use types::{Response, SkyResponse, TpvResponse};
+
+/// A summary of a SKY, for a `Column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkyColumn {
+    /// Number of satellites seen (`nSat`).
+    Seen,
+    /// Number of satellites used in the fix (`uSat`).
+    Used,
+    /// Mean signal-to-noise ratio of the satellites seen with a signal, in
+    /// dB-Hz (`snr`).
+    MeanSnr,
+    /// Mean signal-to-noise ratio of the satellites used, in dB-Hz
+    /// (`snrUsed`).
+    MeanSnrUsed,
+    /// Longitudinal DOP (`xdop`).
+    Xdop,
+    /// Latitudinal DOP (`ydop`).
+    Ydop,
+    /// Vertical DOP (`vdop`).
+    Vdop,
+    /// Time DOP (`tdop`).
+    Tdop,
+    /// Horizontal DOP (`hdop`).
+    Hdop,
+    /// Geometric DOP (`gdop`).
+    Gdop,
+    /// Position (spherical) DOP (`pdop`).
+    Pdop
+}
+const SKY_COLUMNS: &[SkyColumn] = &[
+    SkyColumn::Seen, SkyColumn::Used, SkyColumn::MeanSnr, SkyColumn::MeanSnrUsed,
+    SkyColumn::Xdop, SkyColumn::Ydop, SkyColumn::Vdop, SkyColumn::Tdop,
+    SkyColumn::Hdop, SkyColumn::Gdop, SkyColumn::Pdop
+];
+/// Mean of some signal strengths, ignoring satellites without a signal. This
+/// stays an `f32`, like the strengths, so it's written as briefly.
+fn mean_snr<'a, I: Iterator<Item = &'a Option<f32>>>(snrs: I) -> Option<f32> {
+    let (sum, n) = snrs.filter_map(|&s| s)
+        .filter(|&s| s > 0.0)
+        .fold((0.0f32, 0), |(sum, n), s| (sum + s, n + 1));
+    if n > 0 { Some(sum / n as f32) } else { None }
+}
+impl SkyColumn {
+    /// The name of the column, used in the header.
+    pub fn name(&self) -> &'static str {
+        use self::SkyColumn::*;
+        match *self {
+            Seen => "nSat",
+            Used => "uSat",
+            MeanSnr => "snr",
+            MeanSnrUsed => "snrUsed",
+            Xdop => "xdop",
+            Ydop => "ydop",
+            Vdop => "vdop",
+            Tdop => "tdop",
+            Hdop => "hdop",
+            Gdop => "gdop",
+            Pdop => "pdop"
+        }
+    }
+    /// Look up a column by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        SKY_COLUMNS.iter().cloned().find(|c| c.name() == name)
+    }
+    /// The value of this column for a SKY, if it has one.
+    fn value(&self, sky: &SkyResponse) -> Option<String> {
+        use self::SkyColumn::*;
+        let sats = sky.satellites.as_ref().map_or(&[][..], |s| &s[..]);
+        let count = |n: Option<u32>, used_only: bool| {
+            if n.is_some() || sats.is_empty() {
+                return n;
+            }
+            Some(sats.iter().filter(|s| s.used || !used_only).count() as u32)
+        };
+        let dop = |d: Option<f32>| d.map(|d| d.to_string());
+        match *self {
+            Seen => count(sky.n_sat, false).map(|n| n.to_string()),
+            Used => count(sky.u_sat, true).map(|n| n.to_string()),
+            MeanSnr => mean_snr(sats.iter().map(|s| &s.signal_strength)).map(|s| s.to_string()),
+            MeanSnrUsed => mean_snr(sats.iter().filter(|s| s.used).map(|s| &s.signal_strength)).map(|s| s.to_string()),
+            Xdop => dop(sky.xdop),
+            Ydop => dop(sky.ydop),
+            Vdop => dop(sky.vdop),
+            Tdop => dop(sky.tdop),
+            Hdop => dop(sky.hdop),
+            Gdop => dop(sky.gdop),
+            Pdop => dop(sky.pdop)
+        }
+    }
+}
+/// A column of a `CsvWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// A TPV field, by the name gpsd uses for it on the wire. The `time`
+    /// field is written in the writer's `TimeFormat`.
+    Tpv(String),
+    /// A summary of the SKY for the same epoch.
+    Sky(SkyColumn)
+}
+impl<'a> From<&'a str> for Column {
+    /// A column by name: one of the `SkyColumn` names, or otherwise a TPV
+    /// field.
+    fn from(name: &'a str) -> Column {
+        match SkyColumn::from_name(name) {
+            Some(c) => Column::Sky(c),
+            None => Column::Tpv(name.to_owned())
+        }
+    }
+}
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Column::Tpv(ref name) => write!(f, "{}", name),
+            Column::Sky(c) => write!(f, "{}", c.name())
+        }
+    }
+}
+/// Make a list of columns from their names, separated by commas or
+/// whitespace (for example, `"time,lat,lon,uSat,hdop"`).
+pub fn parse_columns(names: &str) -> Vec<Column> {
+    names.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .map(Column::from)
+        .collect()
+}
+/// How a `CsvWriter` writes times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// RFC 3339, in UTC, like gpsd sends them (`2004-12-14T10:44:27.591Z`).
+    Rfc3339,
+    /// Seconds since the Unix epoch, with as many decimal places as needed
+    /// (`1103021067.591`).
+    UnixEpoch
+}
+/// A cell for a field of a serialized TPV.
+fn cell(v: &Value) -> String {
+    match *v {
+        Value::Null => String::new(),
+        Value::String(ref s) => s.clone(),
+        ref v => v.to_string()
+    }
+}
+/// Writes TPVs (and SKYs) as rows of CSV.
+#[derive(Debug)]
+pub struct CsvWriter<W: Write> {
+    out: W,
+    columns: Vec<Column>,
+    delimiter: char,
+    time_format: TimeFormat,
+    header: bool,
+    /// Whether anything's been written yet.
+    started: bool,
+    sky: Option<SkyResponse>,
+    /// A TPV whose row is waiting for the end of its epoch.
+    pending: Option<TpvResponse>
+}
+impl<W: Write> CsvWriter<W> {
+    /// Make a writer that writes CSV to `out`, with these columns.
+    pub fn new(out: W, columns: Vec<Column>) -> Self {
+        CsvWriter {
+            out, columns,
+            delimiter: ',',
+            time_format: TimeFormat::Rfc3339,
+            header: true,
+            started: false,
+            sky: None,
+            pending: None
+        }
+    }
+    /// Separate cells with `delimiter` (`,` by default). Use `\t` for TSV.
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+    /// Write times in this format (RFC 3339 by default).
+    pub fn set_time_format(&mut self, format: TimeFormat) {
+        self.time_format = format;
+    }
+    /// Whether to start with a row of column names (true by default). Turn
+    /// this off to add rows to an existing file.
+    pub fn set_header(&mut self, header: bool) {
+        self.header = header;
+    }
+    /// Write a response. TPVs become rows, once their epoch is over, and SKYs
+    /// fill in the SKY columns. Anything else is ignored.
+    pub fn handle(&mut self, resp: &Response) -> io::Result<()> {
+        match *resp {
+            Response::Tpv(ref tpv) => {
+                self.end_epoch()?;
+                self.pending = Some(tpv.clone());
+            },
+            Response::Sky(ref sky) => {
+                // A SKY from a different epoch means the TPV's is over.
+                let pending_time = self.pending.as_ref().and_then(|t| t.time());
+                if sky.time.is_some() && pending_time.is_some() && sky.time != pending_time {
+                    self.end_epoch()?;
+                }
+                self.set_sky(sky);
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+    /// Use this SKY for the SKY columns of the following rows.
+    pub fn set_sky(&mut self, sky: &SkyResponse) {
+        self.sky = Some(sky.clone());
+    }
+    /// Write out the row for the TPV passed to `handle`, if it hasn't been
+    /// written yet.
+    pub fn end_epoch(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some(tpv) => self.write_tpv(&tpv),
+            None => Ok(())
+        }
+    }
+    fn format_time(&self, time: DateTime<Utc>) -> String {
+        match self.time_format {
+            TimeFormat::Rfc3339 => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            TimeFormat::UnixEpoch => {
+                let (secs, nanos) = (time.timestamp(), time.timestamp_subsec_nanos());
+                if nanos == 0 {
+                    secs.to_string()
+                }
+                else {
+                    format!("{}.{:09}", secs, nanos).trim_end_matches('0').to_owned()
+                }
+            }
+        }
+    }
+    fn write_record(&mut self, cells: &[String]) -> io::Result<()> {
+        let delimiter = self.delimiter;
+        let mut line = String::new();
+        for (i, c) in cells.iter().enumerate() {
+            if i > 0 {
+                line.push(delimiter);
+            }
+            if c.contains([delimiter, '"', '\n', '\r']) {
+                line.push('"');
+                line.push_str(&c.replace('"', "\"\""));
+                line.push('"');
+            }
+            else {
+                line.push_str(c);
+            }
+        }
+        writeln!(self.out, "{}", line)
+    }
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            if self.header {
+                let names: Vec<String> = self.columns.iter().map(|c| c.to_string()).collect();
+                self.write_record(&names)?;
+            }
+        }
+        Ok(())
+    }
+    /// Write a row for a TPV straight away, using the most recent SKY.
+    pub fn write_tpv(&mut self, tpv: &TpvResponse) -> io::Result<()> {
+        self.start()?;
+        let fields = match serde_json::to_value(tpv) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new()
+        };
+        let cells: Vec<String> = self.columns.iter().map(|c| match *c {
+            Column::Tpv(ref name) if name == "time" => tpv.time().map(|t| self.format_time(t)).unwrap_or_default(),
+            Column::Tpv(ref name) => fields.get(name).map(cell).unwrap_or_default(),
+            Column::Sky(c) => self.sky.as_ref().and_then(|s| c.value(s)).unwrap_or_default()
+        }).collect();
+        self.write_record(&cells)?;
+        self.out.flush()
+    }
+    /// The writer the CSV is going to.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+    /// Write out the last row (and the header, if nothing else has been
+    /// written), and return the writer it went to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_epoch()?;
+        self.start()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,130 @@
+//! Error handling.
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use serde_json;
+
+/// An error encountered while talking to gpsd.
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error on the connection to gpsd.
+    Io(io::Error),
+    /// gpsd closed the connection.
+    ConnectionClosed,
+    /// Timed out waiting for gpsd (see `GpsdConnection::set_read_timeout`).
+    Timeout,
+    /// gpsd sent a line that couldn't be deserialized.
+    MalformedJson {
+        /// The offending line.
+        line: String,
+        /// What went wrong.
+        source: serde_json::Error
+    },
+    /// gpsd sent a JSON object with a class this library doesn't know about.
+    UnknownClass {
+        /// The class of the object.
+        class: String,
+        /// The offending line.
+        line: String
+    },
+    /// gpsd sent an NMEA sentence that couldn't be parsed, in pseudo-NMEA mode.
+    MalformedNmea(String),
+    /// A GPX file being read (see `gpx::GpxReader`) couldn't be parsed.
+    MalformedGpx(String),
+    /// gpsd rejected a command.
+    Command {
+        /// The request that was sent, e.g. `?WATCH={"enable":true}`.
+        request: String,
+        /// What gpsd said was wrong with it.
+        error: CommandError
+    },
+    /// gpsd speaks a version of the protocol this library doesn't support.
+    VersionMismatch {
+        /// Major protocol version reported by gpsd.
+        major: u32,
+        /// Minor protocol version reported by gpsd.
+        minor: u32
+    }
+}
+/// The reason gpsd gave for rejecting a command, classified from the message
+/// in its ERROR reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// gpsd didn't recognise the request (for example, an older daemon that
+    /// doesn't support it).
+    UnrecognizedRequest(String),
+    /// gpsd couldn't configure the device: it might not exist, not be
+    /// writable, or be in use by other clients.
+    DeviceConfig(String),
+    /// The JSON attached to the request was invalid.
+    InvalidJson(String),
+    /// Some other error.
+    Other(String)
+}
+impl CommandError {
+    /// Classify the message from an ERROR reply.
+    pub fn from_message(message: String) -> Self {
+        if message.starts_with("Unrecognized request") {
+            CommandError::UnrecognizedRequest(message)
+        }
+        else if message.starts_with("Invalid ") {
+            CommandError::InvalidJson(message)
+        }
+        else if message.contains("DEVICE configuration") || message.starts_with("No such device") ||
+            message.starts_with("Can't open") || message.contains("cannot change control bits") {
+            CommandError::DeviceConfig(message)
+        }
+        else {
+            CommandError::Other(message)
+        }
+    }
+    /// The message gpsd sent.
+    pub fn message(&self) -> &str {
+        match *self {
+            CommandError::UnrecognizedRequest(ref m) | CommandError::DeviceConfig(ref m) |
+            CommandError::InvalidJson(ref m) | CommandError::Other(ref m) => m
+        }
+    }
+}
+/// A `Result` whose error type is `Error`.
+pub type GpsdResult<T> = Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match *self {
+            Io(ref e) => write!(f, "I/O error: {}", e),
+            ConnectionClosed => write!(f, "gpsd connection closed"),
+            Timeout => write!(f, "timed out waiting for gpsd"),
+            MalformedJson { ref line, ref source } => {
+                write!(f, "failed to deserialize text '{}': {}", line.trim_end(), source)
+            },
+            UnknownClass { ref class, .. } => write!(f, "unknown response class '{}'", class),
+            MalformedNmea(ref s) => write!(f, "malformed NMEA sentence '{}'", s.trim_end()),
+            MalformedGpx(ref s) => write!(f, "malformed GPX: {}", s),
+            Command { ref request, ref error } => {
+                write!(f, "gpsd rejected '{}': {}", request, error.message())
+            },
+            VersionMismatch { major, minor } => {
+                write!(f, "unsupported gpsd protocol version {}.{}", major, minor)
+            }
+        }
+    }
+}
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::MalformedJson { ref source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(e)
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Distances and bearings between positions on the WGS-84 ellipsoid.
+//!
+//! The main calculations use Vincenty's formulae, which are accurate to well
+//! under a millimetre on the ellipsoid. The inverse formula (distance and
+//! bearing between two points) fails to converge for points that are nearly
+//! antipodal; there, `inverse` instead searches for the initial bearing whose
+//! geodesic reaches the second point, following Karney's approach, using the
+//! same series as Vincenty.
+//!
+//! The haversine functions treat the Earth as a sphere. They're quicker, and
+//! accurate enough for many purposes (again, to about 0.5%).
+//!
+//! All of these work on latitude and longitude only; altitude is ignored.
+//! They're also available as methods on `Position`, so you can write
+//! `fix.distance_to(&other)` for positions extracted from a `TpvResponse`.
+use std::f64::consts::PI;
+use types::Position;
+
+/// WGS-84 semi-major axis (equatorial radius), in meters.
+pub const WGS84_A: f64 = 6_378_137.0;
+/// WGS-84 flattening.
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// WGS-84 semi-minor axis (polar radius), in meters.
+pub const WGS84_B: f64 = WGS84_A * (1.0 - WGS84_F);
+/// Mean radius of the Earth (IUGG), in meters, as used for the haversine
+/// functions.
+pub const MEAN_RADIUS: f64 = 6_371_008.8;
+
+/// Give up on Vincenty's formulae after this many iterations.
+const MAX_ITERATIONS: usize = 200;
+
+/// The geodesic (shortest path) between two points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geodesic {
+    /// Length of the path, in meters.
+    pub distance: f64,
+    /// Bearing at the start of the path, in degrees from true north.
+    pub initial_bearing: f64,
+    /// Bearing at the end of the path, in degrees from true north.
+    pub final_bearing: f64
+}
+/// Where you end up after travelling along a geodesic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Destination {
+    /// Latitude in degrees.
+    pub lat: f64,
+    /// Longitude in degrees, between -180 and 180.
+    pub lon: f64,
+    /// Bearing on arrival, in degrees from true north.
+    pub final_bearing: f64
+}
+/// Normalize a bearing in degrees to [0, 360).
+fn normalize_bearing(deg: f64) -> f64 {
+    let ret = deg % 360.0;
+    if ret < 0.0 { ret + 360.0 } else { ret }
+}
+/// Normalize a longitude in degrees to [-180, 180).
+pub(crate) fn normalize_lon(deg: f64) -> f64 {
+    let ret = (deg + 180.0) % 360.0;
+    if ret < 0.0 { ret + 180.0 } else { ret - 180.0 }
+}
+/// Distance and bearings between two points, using Vincenty's inverse
+/// formula, or `antipodal_inverse` for nearly antipodal points, where that
+/// fails to converge.
+pub fn inverse(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Geodesic {
+    vincenty_inverse(lat1, lon1, lat2, lon2)
+        .unwrap_or_else(|| antipodal_inverse(lat1, lon1, lat2, lon2))
+}
+/// The inverse problem, solved by bisecting on the initial bearing: the
+/// geodesic leaving the first point on each bearing is followed to the second
+/// point's latitude, until it gets there at the right longitude. This always
+/// converges, but is slower than Vincenty's formula.
+///
+/// The points are first arranged (as in Karney's "Algorithms for geodesics",
+/// 2013) so that the first is the further from the equator and in the southern
+/// hemisphere, and the second is east of it; the longitude the geodesic reaches
+/// then increases with the initial bearing.
+pub fn antipodal_inverse(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Geodesic {
+    let f = WGS84_F;
+    let swap = lat1.abs() < lat2.abs();
+    let (lat1, lon1, lat2, lon2) = if swap { (lat2, lon2, lat1, lon1) } else { (lat1, lon1, lat2, lon2) };
+    let flip_lat = lat1 > 0.0;
+    let (lat1, lat2) = if flip_lat { (-lat1, -lat2) } else { (lat1, lat2) };
+    let l = normalize_lon(lon2 - lon1).to_radians();
+    let flip_lon = l < 0.0;
+    let l = l.abs();
+    let u1 = ((1.0 - f) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * lat2.to_radians().tan()).atan();
+    // -0.0 on the equator, so a geodesic heading south starts at σ = -π
+    let (sin_u1, cos_u1) = (-u1.sin().abs(), u1.cos());
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    // the longitude reached for an initial bearing, and what's needed to get
+    // the distance and final bearing from it
+    let follow = |alpha1: f64| {
+        let (sin_alpha1, cos_alpha1) = alpha1.sin_cos();
+        let sin_alpha0 = sin_alpha1 * cos_u1;
+        let cos_sq_alpha0 = 1.0 - sin_alpha0 * sin_alpha0;
+        // cos α2 cos β2, heading north at the second point
+        let x2 = (cos_alpha1 * cos_alpha1 * cos_u1 * cos_u1 + cos_u2 * cos_u2 - cos_u1 * cos_u1).max(0.0).sqrt();
+        let sigma1 = sin_u1.atan2(cos_alpha1 * cos_u1);
+        let sigma2 = sin_u2.atan2(x2);
+        let omega1 = (sin_alpha0 * sin_u1).atan2(cos_alpha1 * cos_u1);
+        let omega2 = (sin_alpha0 * sin_u2).atan2(x2);
+        let (sigma, two_sigma_m) = (sigma2 - sigma1, sigma1 + sigma2);
+        let cos_2sigma_m = two_sigma_m.cos();
+        let (sin_sigma, cos_sigma) = sigma.sin_cos();
+        let c = f / 16.0 * cos_sq_alpha0 * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha0));
+        let lon = (omega2 - omega1) - (1.0 - c) * f * sin_alpha0 * (sigma + c * sin_sigma *
+            (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+        (lon, sin_alpha0, cos_sq_alpha0, x2, sigma, cos_2sigma_m)
+    };
+    let (mut lo, mut hi) = (0.0, PI);
+    for _ in 0..MAX_ITERATIONS {
+        let mid = (lo + hi) / 2.0;
+        if mid <= lo || mid >= hi {
+            break;
+        }
+        if follow(mid).0 < l { lo = mid } else { hi = mid }
+    }
+    let alpha1 = (lo + hi) / 2.0;
+    let (_, sin_alpha0, cos_sq_alpha0, x2, sigma, cos_2sigma_m) = follow(alpha1);
+    let (a_coeff, b_coeff) = series_coefficients(cos_sq_alpha0);
+    let (sin_sigma, cos_sigma) = sigma.sin_cos();
+    let distance = WGS84_B * a_coeff * (sigma - delta_sigma(b_coeff, sin_sigma, cos_sigma, cos_2sigma_m));
+    let alpha2 = sin_alpha0.atan2(x2);
+
+    // and back again
+    let (mut alpha1, mut alpha2) = (alpha1.to_degrees(), alpha2.to_degrees());
+    if flip_lon {
+        alpha1 = -alpha1;
+        alpha2 = -alpha2;
+    }
+    if flip_lat {
+        alpha1 = 180.0 - alpha1;
+        alpha2 = 180.0 - alpha2;
+    }
+    if swap {
+        let initial = alpha2 + 180.0;
+        alpha2 = alpha1 + 180.0;
+        alpha1 = initial;
+    }
+    Geodesic {
+        distance,
+        initial_bearing: normalize_bearing(alpha1),
+        final_bearing: normalize_bearing(alpha2)
+    }
+}
+/// Vincenty's inverse formula. Returns `None` if it fails to converge, which
+/// happens for nearly antipodal points.
+pub fn vincenty_inverse(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<Geodesic> {
+    let f = WGS84_F;
+    let l = normalize_lon(lon2 - lon1).to_radians();
+    let u1 = ((1.0 - f) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    let mut converged = false;
+    let (mut sin_sigma, mut cos_sigma, mut sigma) = (0.0, 0.0, 0.0);
+    let (mut cos_sq_alpha, mut cos_2sigma_m) = (0.0, 0.0);
+    let (mut sin_lambda, mut cos_lambda) = (0.0, 0.0);
+    for _ in 0..MAX_ITERATIONS {
+        let sc = lambda.sin_cos();
+        sin_lambda = sc.0;
+        cos_lambda = sc.1;
+        let x = cos_u2 * sin_lambda;
+        let y = cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda;
+        sin_sigma = (x * x + y * y).sqrt();
+        if sin_sigma == 0.0 {
+            // coincident points
+            return Some(Geodesic { distance: 0.0, initial_bearing: 0.0, final_bearing: 0.0 });
+        }
+        cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        // on the equator, cos_sq_alpha is zero and this term doesn't matter
+        cos_2sigma_m = if cos_sq_alpha != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        }
+        else {
+            0.0
+        };
+        let c = f / 16.0 * cos_sq_alpha * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha));
+        let prev = lambda;
+        lambda = l + (1.0 - c) * f * sin_alpha * (sigma + c * sin_sigma *
+            (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+        if lambda.abs() > PI + 1e-9 {
+            return None;
+        }
+        if (lambda - prev).abs() < 1e-12 {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        return None;
+    }
+    let (a_coeff, b_coeff) = series_coefficients(cos_sq_alpha);
+    let delta_sigma = delta_sigma(b_coeff, sin_sigma, cos_sigma, cos_2sigma_m);
+    let distance = WGS84_B * a_coeff * (sigma - delta_sigma);
+    let alpha1 = (cos_u2 * sin_lambda).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+    let alpha2 = (cos_u1 * sin_lambda).atan2(-sin_u1 * cos_u2 + cos_u1 * sin_u2 * cos_lambda);
+    Some(Geodesic {
+        distance,
+        initial_bearing: normalize_bearing(alpha1.to_degrees()),
+        final_bearing: normalize_bearing(alpha2.to_degrees())
+    })
+}
+/// The `A` and `B` coefficients of Vincenty's series, from cos²α.
+fn series_coefficients(cos_sq_alpha: f64) -> (f64, f64) {
+    let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - WGS84_B * WGS84_B) / (WGS84_B * WGS84_B);
+    let a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+    let b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+    (a, b)
+}
+fn delta_sigma(b: f64, sin_sigma: f64, cos_sigma: f64, cos_2sigma_m: f64) -> f64 {
+    b * sin_sigma * (cos_2sigma_m + b / 4.0 * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m) -
+        b / 6.0 * cos_2sigma_m * (-3.0 + 4.0 * sin_sigma * sin_sigma) * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)))
+}
+/// Where you end up after travelling `distance` meters from a point, starting
+/// on `bearing` (degrees from true north), using Vincenty's direct formula.
+pub fn direct(lat: f64, lon: f64, bearing: f64, distance: f64) -> Destination {
+    let f = WGS84_F;
+    let (sin_alpha1, cos_alpha1) = bearing.to_radians().sin_cos();
+    let tan_u1 = (1.0 - f) * lat.to_radians().tan();
+    let cos_u1 = 1.0 / (1.0 + tan_u1 * tan_u1).sqrt();
+    let sin_u1 = tan_u1 * cos_u1;
+    let sigma1 = tan_u1.atan2(cos_alpha1);
+    let sin_alpha = cos_u1 * sin_alpha1;
+    let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+    let (a_coeff, b_coeff) = series_coefficients(cos_sq_alpha);
+
+    let mut sigma = distance / (WGS84_B * a_coeff);
+    let (mut sin_sigma, mut cos_sigma, mut cos_2sigma_m);
+    let mut iterations = 0;
+    loop {
+        cos_2sigma_m = (2.0 * sigma1 + sigma).cos();
+        let sc = sigma.sin_cos();
+        sin_sigma = sc.0;
+        cos_sigma = sc.1;
+        let prev = sigma;
+        sigma = distance / (WGS84_B * a_coeff) + delta_sigma(b_coeff, sin_sigma, cos_sigma, cos_2sigma_m);
+        iterations += 1;
+        // the direct formula always converges, and quickly
+        if (sigma - prev).abs() < 1e-12 || iterations >= MAX_ITERATIONS {
+            break;
+        }
+    }
+    let x = sin_u1 * sin_sigma - cos_u1 * cos_sigma * cos_alpha1;
+    let lat2 = (sin_u1 * cos_sigma + cos_u1 * sin_sigma * cos_alpha1)
+        .atan2((1.0 - f) * (sin_alpha * sin_alpha + x * x).sqrt());
+    let lambda = (sin_sigma * sin_alpha1).atan2(cos_u1 * cos_sigma - sin_u1 * sin_sigma * cos_alpha1);
+    let c = f / 16.0 * cos_sq_alpha * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha));
+    let l = lambda - (1.0 - c) * f * sin_alpha * (sigma + c * sin_sigma *
+        (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+    Destination {
+        lat: lat2.to_degrees(),
+        lon: normalize_lon(lon + l.to_degrees()),
+        final_bearing: normalize_bearing(sin_alpha.atan2(-x).to_degrees())
+    }
+}
+/// Great-circle distance between two points in meters, treating the Earth as
+/// a sphere of radius `MEAN_RADIUS`.
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let h = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * MEAN_RADIUS * h.sqrt().min(1.0).asin()
+}
+/// Initial great-circle bearing from one point to another, in degrees from
+/// true north, treating the Earth as a sphere.
+pub fn spherical_bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lambda = (lon2 - lon1).to_radians();
+    let y = d_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lambda.cos();
+    normalize_bearing(y.atan2(x).to_degrees())
+}
+impl Position {
+    /// The geodesic from this position to `other`.
+    pub fn geodesic_to(&self, other: &Position) -> Geodesic {
+        inverse(self.lat, self.lon, other.lat, other.lon)
+    }
+    /// Distance to `other` along the WGS-84 ellipsoid, in meters.
+    pub fn distance_to(&self, other: &Position) -> f64 {
+        self.geodesic_to(other).distance
+    }
+    /// Initial bearing to `other`, in degrees from true north.
+    pub fn bearing_to(&self, other: &Position) -> f64 {
+        self.geodesic_to(other).initial_bearing
+    }
+    /// Distance to `other` in meters, treating the Earth as a sphere. Quicker
+    /// than `distance_to`, but less accurate.
+    pub fn haversine_distance_to(&self, other: &Position) -> f64 {
+        haversine(self.lat, self.lon, other.lat, other.lon)
+    }
+    /// The position reached by travelling `distance` meters from here,
+    /// starting on `bearing` (degrees from true north). The altitude is kept
+    /// as it is.
+    pub fn destination(&self, bearing: f64, distance: f64) -> Position {
+        let dest = direct(self.lat, self.lon, bearing, distance);
+        Position { lat: dest.lat, lon: dest.lon, alt: self.alt }
+    }
+}
@@ -0,0 +1,269 @@
+//! Geofencing: noticing when a receiver enters or leaves an area.
+//!
+//! A `Geofence` holds any number of fences (circles or polygons), and is fed
+//! `Response`s from a `GpsdConnection`. Each TPV with a position is checked
+//! against every fence, and `GeofenceEvent`s come out when a device enters a
+//! fence, leaves it, or has stayed inside for the fence's dwell time.
+//!
+//! A fix near a boundary could easily be on either side of it, and without
+//! some care the device would flap in and out as the fix wanders. To avoid
+//! this, gpsd's 95% error estimates (`lat_err`/`lon_err`, or `epy`/`epx` on
+//! the wire) are used as hysteresis: a device is only taken to have entered a
+//! fence once the whole error circle is inside it, and to have left once the
+//! whole circle is outside. Fixes in between don't change anything. An extra
+//! margin can be added on top with `set_margin`.
+//!
+//! Circles are measured with geodesic distances on the WGS-84 ellipsoid, so
+//! they can be any size. Polygons have straight edges in a plane tangent to
+//! the ellipsoid at their centre (see `coords::LocalFrame`); that's accurate
+//! for fences up to a few tens of kilometres across, which is what
+//! geofences usually are. Both work across the antimeridian and near the
+//! poles.
+//!
+//! Devices are tracked separately, by the `device` path of their TPVs, so one
+//! `Geofence` can watch several receivers.
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{self, DateTime, Utc};
+use coords::{Ecef, LocalFrame};
+use types::{Position, Response, TpvResponse};
+
+/// Identifies one of the fences in a `Geofence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FenceId(pub usize);
+
+/// What happened to a device, with respect to a fence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeofenceEventKind {
+    /// The device is now inside the fence.
+    Enter,
+    /// The device is no longer inside the fence.
+    Exit,
+    /// The device has been inside the fence for its dwell time.
+    Dwell
+}
+/// Something that happened to a device, with respect to a fence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeofenceEvent {
+    /// The fence concerned.
+    pub fence: FenceId,
+    /// What happened.
+    pub kind: GeofenceEventKind,
+    /// The device that it happened to, if the TPV said.
+    pub device: Option<String>,
+    /// The position that triggered the event.
+    pub position: Position,
+    /// The time of the fix that triggered the event, if it had one.
+    pub time: Option<DateTime<Utc>>
+}
+/// The shape of a fence.
+#[derive(Debug, Clone)]
+enum Shape {
+    Circle {
+        centre: Position,
+        radius: f64
+    },
+    Polygon {
+        frame: LocalFrame,
+        /// Vertices, as (east, north) in `frame`.
+        vertices: Vec<(f64, f64)>,
+        /// Distance from the centre to the furthest vertex.
+        radius: f64
+    }
+}
+#[derive(Debug, Clone)]
+struct Fence {
+    name: String,
+    shape: Shape,
+    dwell: Option<Duration>
+}
+/// What we know about a device, with respect to one fence.
+#[derive(Debug, Clone, Copy, Default)]
+struct FenceState {
+    inside: Option<bool>,
+    /// When the device entered the fence.
+    entered: Option<DateTime<Utc>>,
+    /// Whether a dwell event has been sent for this visit.
+    dwelt: bool
+}
+/// A set of fences, and the state of each device with respect to them.
+#[derive(Debug, Clone, Default)]
+pub struct Geofence {
+    fences: Vec<Fence>,
+    margin: f64,
+    devices: HashMap<Option<String>, Vec<FenceState>>
+}
+impl Shape {
+    /// How far inside the shape a position is, in meters. This is negative if
+    /// it's outside.
+    fn depth(&self, pos: &Position) -> f64 {
+        match *self {
+            Shape::Circle { ref centre, radius } => radius - centre.distance_to(pos),
+            Shape::Polygon { ref frame, ref vertices, radius } => {
+                let flat = Position { alt: None, ..*pos };
+                let dist = frame.origin().distance_to(&flat);
+                // Far away, the tangent plane is no good (the other side of
+                // the world projects onto it too), but we know the position
+                // is outside by at least this much.
+                if dist > 2.0 * radius + 1000.0 {
+                    return radius - dist;
+                }
+                let enu = frame.position_to_enu(&flat);
+                let (x, y) = (enu.east, enu.north);
+                let mut inside = false;
+                let mut nearest = f64::INFINITY;
+                for (i, &(x1, y1)) in vertices.iter().enumerate() {
+                    let (x2, y2) = vertices[(i + 1) % vertices.len()];
+                    if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+                        inside = !inside;
+                    }
+                    nearest = nearest.min(segment_distance((x, y), (x1, y1), (x2, y2)));
+                }
+                if inside { nearest } else { -nearest }
+            }
+        }
+    }
+}
+/// Distance from `p` to the line segment from `a` to `b`.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    }
+    else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+    };
+    (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+}
+impl Geofence {
+    /// Make a new, empty, set of fences.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a circular fence, `radius` meters around `centre`.
+    pub fn add_circle<N: Into<String>>(&mut self, name: N, centre: Position, radius: f64) -> FenceId {
+        self.add(name.into(), Shape::Circle { centre, radius })
+    }
+    /// Add a polygonal fence with the given vertices, in order (either way
+    /// round). The last vertex is joined back to the first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are fewer than three vertices.
+    pub fn add_polygon<N: Into<String>>(&mut self, name: N, vertices: &[Position]) -> FenceId {
+        assert!(vertices.len() >= 3, "a polygon needs at least three vertices");
+        // Average in ECEF, so that polygons across the antimeridian get a
+        // sensible centre.
+        let mut sum = Ecef { x: 0.0, y: 0.0, z: 0.0 };
+        for v in vertices {
+            let e = Ecef::from_geodetic(v.lat, v.lon, 0.0);
+            sum.x += e.x;
+            sum.y += e.y;
+            sum.z += e.z;
+        }
+        let centre = sum.to_geodetic();
+        let frame = LocalFrame::new(Position { alt: None, ..centre });
+        let vertices: Vec<(f64, f64)> = vertices.iter()
+            .map(|v| frame.position_to_enu(&Position { alt: None, ..*v }))
+            .map(|enu| (enu.east, enu.north))
+            .collect();
+        let radius = vertices.iter().map(|&(x, y)| x.hypot(y)).fold(0.0, f64::max);
+        self.add(name.into(), Shape::Polygon { frame, vertices, radius })
+    }
+    fn add(&mut self, name: String, shape: Shape) -> FenceId {
+        self.fences.push(Fence { name, shape, dwell: None });
+        for states in self.devices.values_mut() {
+            states.push(FenceState::default());
+        }
+        FenceId(self.fences.len() - 1)
+    }
+    /// The name a fence was added with.
+    pub fn name(&self, id: FenceId) -> &str {
+        &self.fences[id.0].name
+    }
+    /// All of the fences, in the order they were added.
+    pub fn fences(&self) -> impl Iterator<Item = FenceId> {
+        (0..self.fences.len()).map(FenceId)
+    }
+    /// Send a `Dwell` event once a device has been inside a fence for
+    /// `dwell` (going by the times of the fixes), or stop sending them if
+    /// `None`. There are no dwell events by default.
+    pub fn set_dwell(&mut self, id: FenceId, dwell: Option<Duration>) {
+        self.fences[id.0].dwell = dwell;
+    }
+    /// Add `margin` meters of hysteresis on top of the fixes' error
+    /// estimates. This is 0 by default.
+    pub fn set_margin(&mut self, margin: f64) {
+        self.margin = margin;
+    }
+    /// How far inside a fence a position is, in meters. This is negative if
+    /// the position is outside.
+    pub fn depth(&self, id: FenceId, pos: &Position) -> f64 {
+        self.fences[id.0].shape.depth(pos)
+    }
+    /// Is a position inside a fence (ignoring any error)?
+    pub fn contains(&self, id: FenceId, pos: &Position) -> bool {
+        self.depth(id, pos) >= 0.0
+    }
+    /// Whether a device is currently inside a fence, or `None` if that isn't
+    /// known yet.
+    pub fn is_inside(&self, device: Option<&str>, id: FenceId) -> Option<bool> {
+        self.devices.get(&device.map(|d| d.to_owned()))?[id.0].inside
+    }
+    /// Check a response against the fences. Anything other than a TPV with a
+    /// position is ignored.
+    pub fn handle(&mut self, resp: &Response) -> Vec<GeofenceEvent> {
+        match *resp {
+            Response::Tpv(ref tpv) => self.update(tpv),
+            _ => vec![]
+        }
+    }
+    /// Check a TPV against the fences.
+    pub fn update(&mut self, tpv: &TpvResponse) -> Vec<GeofenceEvent> {
+        let position = match tpv.position() {
+            Some(p) => p,
+            None => return vec![]
+        };
+        let errors = tpv.errors();
+        let slack = errors.lat.unwrap_or(0.0).max(errors.lon.unwrap_or(0.0)) + self.margin;
+        let device = tpv.device().map(|d| d.to_owned());
+        let time = tpv.time();
+        let nfences = self.fences.len();
+        let states = self.devices.entry(device.clone())
+            .or_insert_with(|| vec![FenceState::default(); nfences]);
+        let mut ret = vec![];
+        for (i, (fence, state)) in self.fences.iter().zip(states.iter_mut()).enumerate() {
+            let depth = fence.shape.depth(&position);
+            let mut event = |kind| ret.push(GeofenceEvent {
+                fence: FenceId(i),
+                kind,
+                device: device.clone(),
+                position,
+                time
+            });
+            if depth >= slack && state.inside != Some(true) {
+                state.inside = Some(true);
+                state.entered = time;
+                state.dwelt = false;
+                event(GeofenceEventKind::Enter);
+            }
+            else if depth <= -slack && state.inside != Some(false) {
+                // The first fix just tells us where we started.
+                if state.inside.is_some() {
+                    event(GeofenceEventKind::Exit);
+                }
+                state.inside = Some(false);
+                state.entered = None;
+            }
+            if let (Some(true), false, Some(dwell), Some(entered), Some(now)) = (state.inside, state.dwelt, fence.dwell, state.entered, time) {
+                let dwell = chrono::Duration::from_std(dwell).unwrap_or(chrono::Duration::MAX);
+                if now - entered >= dwell {
+                    state.dwelt = true;
+                    event(GeofenceEventKind::Dwell);
+                }
+            }
+        }
+        ret
+    }
+}
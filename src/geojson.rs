@@ -0,0 +1,253 @@
+//! Writing tracks as GeoJSON.
+//!
+//! GeoJSON (RFC 7946) is what most web maps want. Each fix becomes a `Point`
+//! feature, whose properties are the TPV's fields as gpsd sends them (apart
+//! from `lat` and `lon`, which are in the geometry), along with the satellite
+//! count and DOPs from the most recent SKY (as `uSat`, `hdop`, `vdop` and
+//! `pdop`). Each run of fixes becomes a `LineString` feature, with the times
+//! of its first and last fixes as `start` and `end`. As with GPX, losing the
+//! fix ends the line, and the next fix starts a new one.
+//!
+//! `feature_collection` turns a whole recording into a `FeatureCollection` at
+//! once. `GeoJsonWriter` writes one out as the responses arrive, which suits
+//! long recordings better: only the current line is kept in memory.
+//!
+//! Altitudes are heights above the WGS-84 ellipsoid, as GeoJSON requires
+//! (`altHAE`, or worked out from `altMSL` and `geoidSep`; see
+//! `TpvResponse::hae`). A line only has altitudes if all of its points do.
+//! Lines that cross the antimeridian are split there into a
+//! `MultiLineString`, as the RFC recommends, so that maps don't draw them the
+//! wrong way round the world.
+use std::io::{self, Write};
+use std::mem;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{self, Map, Value};
+use gpx::SkySummary;
+use types::{Response, SkyResponse, TpvResponse};
+
+/// A DOP, as JSON. (Going via `f64` directly would give 3.299999952316284
+/// instead of 3.3.)
+fn dop(v: f32) -> Value {
+    v.to_string().parse::<f64>().map(|v| json!(v)).unwrap_or(Value::Null)
+}
+fn time_string(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+/// Split a line where it crosses the antimeridian.
+fn split_antimeridian(coords: Vec<Vec<f64>>) -> Vec<Vec<Vec<f64>>> {
+    let mut parts = vec![];
+    let mut part: Vec<Vec<f64>> = vec![];
+    for c in coords {
+        if let Some(p) = part.last().cloned() {
+            let dlon = c[0] - p[0];
+            if dlon.abs() > 180.0 {
+                // Going east across it if the longitude drops, else west.
+                let edge = if dlon < 0.0 { 180.0 } else { -180.0 };
+                let t = (edge - p[0]) / (c[0] + 2.0 * edge - p[0]);
+                let mut cross: Vec<f64> = p.iter().zip(c.iter()).map(|(a, b)| a + t * (b - a)).collect();
+                cross[0] = edge;
+                part.push(cross.clone());
+                parts.push(mem::take(&mut part));
+                cross[0] = -edge;
+                part.push(cross);
+            }
+        }
+        part.push(c);
+    }
+    parts.push(part);
+    parts
+}
+/// Turns responses into features, for `feature_collection` and
+/// `GeoJsonWriter`.
+#[derive(Debug, Clone)]
+struct Builder {
+    points: bool,
+    lines: bool,
+    sky: SkySummary,
+    /// Coordinates of the current line.
+    line: Vec<Vec<f64>>,
+    /// Times of the first and last fixes in the current line.
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>
+}
+impl Builder {
+    fn new() -> Self {
+        Builder {
+            points: true,
+            lines: true,
+            sky: SkySummary::default(),
+            line: vec![],
+            start: None,
+            end: None
+        }
+    }
+    fn handle(&mut self, resp: &Response) -> Vec<Value> {
+        match *resp {
+            Response::Tpv(ref tpv) => self.tpv(tpv),
+            Response::Sky(ref sky) => {
+                self.sky = SkySummary::new(sky);
+                vec![]
+            },
+            _ => vec![]
+        }
+    }
+    fn tpv(&mut self, tpv: &TpvResponse) -> Vec<Value> {
+        let pos = match tpv.position() {
+            Some(p) if tpv.mode().has_fix() => p,
+            _ => return self.end_line().into_iter().collect()
+        };
+        let coords = match tpv.hae() {
+            Some(alt) => vec![pos.lon, pos.lat, alt],
+            None => vec![pos.lon, pos.lat]
+        };
+        let mut ret = vec![];
+        if self.points {
+            let mut props = match serde_json::to_value(tpv) {
+                Ok(Value::Object(map)) => map,
+                _ => Map::new()
+            };
+            props.remove("class");
+            props.remove("lat");
+            props.remove("lon");
+            if let Some(sats) = self.sky.sats {
+                props.insert("uSat".into(), json!(sats));
+            }
+            for &(name, v) in [("hdop", self.sky.hdop), ("vdop", self.sky.vdop), ("pdop", self.sky.pdop)].iter() {
+                if let Some(v) = v {
+                    props.insert(name.into(), dop(v));
+                }
+            }
+            ret.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": coords },
+                "properties": props
+            }));
+        }
+        if self.lines {
+            self.line.push(coords);
+            if let Some(time) = tpv.time() {
+                self.start = self.start.or(Some(time));
+                self.end = Some(time);
+            }
+        }
+        ret
+    }
+    /// Finish the current line, returning it if it has at least two points.
+    fn end_line(&mut self) -> Option<Value> {
+        let line = mem::take(&mut self.line);
+        let (start, end) = (self.start.take(), self.end.take());
+        if line.len() < 2 {
+            return None;
+        }
+        let line = if line.iter().all(|c| c.len() == 3) {
+            line
+        }
+        else {
+            line.into_iter().map(|c| c[..2].to_vec()).collect()
+        };
+        let mut parts = split_antimeridian(line);
+        let geometry = if parts.len() == 1 {
+            json!({ "type": "LineString", "coordinates": parts.remove(0) })
+        }
+        else {
+            json!({ "type": "MultiLineString", "coordinates": parts })
+        };
+        let mut props = Map::new();
+        if let Some(start) = start {
+            props.insert("start".into(), json!(time_string(start)));
+        }
+        if let Some(end) = end {
+            props.insert("end".into(), json!(time_string(end)));
+        }
+        Some(json!({ "type": "Feature", "geometry": geometry, "properties": props }))
+    }
+}
+/// Make a GeoJSON `FeatureCollection` out of some responses: a `Point` for
+/// each TPV with a fix, and a `LineString` for each run of them. SKYs add
+/// their satellite counts and DOPs to the points after them. Anything else
+/// is ignored.
+pub fn feature_collection<'a, I: IntoIterator<Item = &'a Response>>(responses: I) -> Value {
+    let mut builder = Builder::new();
+    let mut features = vec![];
+    for resp in responses {
+        features.extend(builder.handle(resp));
+    }
+    features.extend(builder.end_line());
+    json!({ "type": "FeatureCollection", "features": features })
+}
+/// Writes TPVs out as a GeoJSON `FeatureCollection`, as they arrive.
+///
+/// `Point` features are written (and flushed) as soon as they're known, one
+/// per line, and each `LineString` once its line ends. If the program dies,
+/// the file will be missing the closing brackets `finish` writes.
+#[derive(Debug)]
+pub struct GeoJsonWriter<W: Write> {
+    out: W,
+    builder: Builder,
+    written: usize
+}
+impl<W: Write> GeoJsonWriter<W> {
+    /// Start writing a `FeatureCollection` to `out`.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        write!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+        out.flush()?;
+        Ok(GeoJsonWriter { out, builder: Builder::new(), written: 0 })
+    }
+    /// Whether to write a `Point` feature for each fix (true by default).
+    pub fn set_points(&mut self, points: bool) {
+        self.builder.points = points;
+    }
+    /// Whether to write a `LineString` feature for each run of fixes (true
+    /// by default).
+    pub fn set_lines(&mut self, lines: bool) {
+        self.builder.lines = lines;
+    }
+    fn write(&mut self, features: Vec<Value>) -> io::Result<()> {
+        if features.is_empty() {
+            return Ok(());
+        }
+        for feature in features {
+            if self.written > 0 {
+                write!(self.out, ",")?;
+            }
+            writeln!(self.out)?;
+            serde_json::to_writer(&mut self.out, &feature)?;
+            self.written += 1;
+        }
+        self.out.flush()
+    }
+    /// Write a response. TPVs with a fix become points, TPVs without one end
+    /// the current line, and SKYs are remembered for their satellite count
+    /// and DOPs. Anything else is ignored.
+    pub fn handle(&mut self, resp: &Response) -> io::Result<()> {
+        let features = self.builder.handle(resp);
+        self.write(features)
+    }
+    /// Use this SKY for the satellite count and DOPs of the following points.
+    pub fn set_sky(&mut self, sky: &SkyResponse) {
+        self.builder.sky = SkySummary::new(sky);
+    }
+    /// Write a TPV as a point, or end the current line if it doesn't have a
+    /// fix.
+    pub fn write_tpv(&mut self, tpv: &TpvResponse) -> io::Result<()> {
+        let features = self.builder.tpv(tpv);
+        self.write(features)
+    }
+    /// End the current line, if there is one, writing it out. The next point
+    /// will start a new one.
+    pub fn end_segment(&mut self) -> io::Result<()> {
+        let features = self.builder.end_line().into_iter().collect();
+        self.write(features)
+    }
+    /// The writer the GeoJSON is going to.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+    /// Finish off the file, and return the writer it went to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_segment()?;
+        writeln!(self.out, "\n]}}")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,627 @@
+//! Reading and writing tracks as GPX 1.1.
+//!
+//! # Writing
+//!
+//! `GpxWriter` turns a stream of TPVs into a GPX track (`trk`), one track
+//! point (`trkpt`) per fix. The elevation, time, fix type and geoid height
+//! come from the TPV; the satellite count and DOPs from the most recent SKY
+//! (pass SKYs to `handle` or `set_sky` as they arrive). Speed and course are
+//! written as Garmin `TrackPointExtension` extensions, which most tools that
+//! read GPX understand.
+//!
+//! When the fix is lost (a TPV with no fix arrives), the current track
+//! segment (`trkseg`) ends, and the next fix starts a new one, so that tools
+//! don't draw a line across the gap.
+//!
+//! Everything is written (and flushed) as soon as it's known, so if the
+//! program dies, the file has every point up to then. It'll be missing the
+//! closing tags that `finish` writes, but most tools cope with that, and
+//! they're easy enough to add by hand.
+//!
+//! # Reading
+//!
+//! `GpxReader` goes the other way, turning the track points in a GPX file
+//! back into `Response`s, so that a recorded track can be replayed through
+//! the same code that handles a live `GpsdConnection`. Each track point
+//! becomes a TPV with the point's time, preceded by a SKY whenever the
+//! satellite count or DOPs change. A TPV without a fix is put between track
+//! segments, and in place of points whose fix type is `none`.
+//!
+//! Points that don't record their speed or course (most GPX files don't) get
+//! them worked out from the points either side, in the same segment; likewise
+//! the climb rate, from their elevations (or 0, if only the point itself has
+//! one). GPX doesn't record error estimates,
+//! so the TPVs don't have any, apart from a time error (`ept`) of 0. Points
+//! without a time are skipped, since there'd be no way of knowing when to
+//! replay them.
+//!
+//! The reader understands enough XML for GPX files, and no more: it doesn't
+//! check that the file is valid, and ignores anything it doesn't recognise.
+//! Elements are matched by their names without any namespace prefix, so the
+//! `speed` and `course` elements of GPX 1.0, and of the various versions of
+//! Garmin's extensions, are all understood.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Map;
+use types::{FixMode, FixStatus, Position, Response, SkyResponse, TpvDetails, TpvFields, TpvResponse};
+use {Error, GpsdResult};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="unbounded-gpsd" xmlns="http://www.topografix.com/GPX/1/1" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2" xsi:schemaLocation="http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v2 http://www.garmin.com/xmlschemas/TrackPointExtensionv2.xsd">
+"#;
+
+/// Escape text for inclusion in XML.
+pub(crate) fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c)
+        }
+    }
+    ret
+}
+/// What we need to remember from the last SKY.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct SkySummary {
+    /// Number of satellites used in the fix.
+    pub(crate) sats: Option<u32>,
+    pub(crate) hdop: Option<f32>,
+    pub(crate) vdop: Option<f32>,
+    pub(crate) pdop: Option<f32>
+}
+impl SkySummary {
+    pub(crate) fn new(sky: &SkyResponse) -> Self {
+        let used = match sky.satellites {
+            Some(ref sats) if !sats.is_empty() => Some(sats.iter().filter(|s| s.used).count() as u32),
+            _ => None
+        };
+        SkySummary {
+            sats: sky.u_sat.or(used),
+            hdop: sky.hdop,
+            vdop: sky.vdop,
+            pdop: sky.pdop
+        }
+    }
+}
+/// Writes TPVs out as a GPX track.
+#[derive(Debug)]
+pub struct GpxWriter<W: Write> {
+    out: W,
+    in_segment: bool,
+    sky: SkySummary
+}
+impl<W: Write> GpxWriter<W> {
+    /// Start writing a GPX file to `out`, with an unnamed track.
+    pub fn new(out: W) -> io::Result<Self> {
+        Self::start(out, None)
+    }
+    /// Start writing a GPX file to `out`, with a track called `name`.
+    pub fn with_name(out: W, name: &str) -> io::Result<Self> {
+        Self::start(out, Some(name))
+    }
+    fn start(mut out: W, name: Option<&str>) -> io::Result<Self> {
+        out.write_all(HEADER.as_bytes())?;
+        writeln!(out, " <trk>")?;
+        if let Some(name) = name {
+            writeln!(out, "  <name>{}</name>", escape(name))?;
+        }
+        out.flush()?;
+        Ok(GpxWriter { out, in_segment: false, sky: SkySummary::default() })
+    }
+    /// Write a response. TPVs with a fix become track points, TPVs without
+    /// one end the current segment, and SKYs are remembered for their
+    /// satellite count and DOPs. Anything else is ignored.
+    pub fn handle(&mut self, resp: &Response) -> io::Result<()> {
+        match *resp {
+            Response::Tpv(ref tpv) => self.write_tpv(tpv),
+            Response::Sky(ref sky) => {
+                self.set_sky(sky);
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+    /// Use this SKY for the satellite count and DOPs of the following points.
+    pub fn set_sky(&mut self, sky: &SkyResponse) {
+        self.sky = SkySummary::new(sky);
+    }
+    /// Write a TPV as a track point, or end the current segment if it
+    /// doesn't have a fix.
+    pub fn write_tpv(&mut self, tpv: &TpvResponse) -> io::Result<()> {
+        let pos = match tpv.position() {
+            Some(p) if tpv.mode().has_fix() => p,
+            _ => return self.end_segment()
+        };
+        if !self.in_segment {
+            writeln!(self.out, "  <trkseg>")?;
+            self.in_segment = true;
+        }
+        let o = &mut self.out;
+        let details = tpv.details();
+        writeln!(o, "   <trkpt lat=\"{}\" lon=\"{}\">", pos.lat, pos.lon)?;
+        if let Some(ele) = details.alt_msl.or(pos.alt) {
+            writeln!(o, "    <ele>{}</ele>", ele)?;
+        }
+        if let Some(time) = tpv.time() {
+            writeln!(o, "    <time>{}</time>", time.to_rfc3339_opts(SecondsFormat::AutoSi, true))?;
+        }
+        if let Some(sep) = details.geoid_sep {
+            writeln!(o, "    <geoidheight>{}</geoidheight>", sep)?;
+        }
+        let fix = match (details.status, tpv.mode()) {
+            (Some(FixStatus::Dgps), _) => "dgps",
+            (_, FixMode::Fix3D) => "3d",
+            _ => "2d"
+        };
+        writeln!(o, "    <fix>{}</fix>", fix)?;
+        if let Some(sats) = self.sky.sats {
+            writeln!(o, "    <sat>{}</sat>", sats)?;
+        }
+        for &(name, dop) in [("hdop", self.sky.hdop), ("vdop", self.sky.vdop), ("pdop", self.sky.pdop)].iter() {
+            if let Some(dop) = dop {
+                writeln!(o, "    <{}>{}</{}>", name, dop, name)?;
+            }
+        }
+        if let Some(age) = details.dgps_age {
+            writeln!(o, "    <ageofdgpsdata>{}</ageofdgpsdata>", age)?;
+        }
+        if let Some(station) = details.dgps_station {
+            if station <= 1023 {
+                writeln!(o, "    <dgpsid>{}</dgpsid>", station)?;
+            }
+        }
+        if tpv.speed().is_some() || tpv.track().is_some() {
+            writeln!(o, "    <extensions>")?;
+            writeln!(o, "     <gpxtpx:TrackPointExtension>")?;
+            if let Some(speed) = tpv.speed() {
+                writeln!(o, "      <gpxtpx:speed>{}</gpxtpx:speed>", speed)?;
+            }
+            if let Some(track) = tpv.track() {
+                writeln!(o, "      <gpxtpx:course>{}</gpxtpx:course>", track)?;
+            }
+            writeln!(o, "     </gpxtpx:TrackPointExtension>")?;
+            writeln!(o, "    </extensions>")?;
+        }
+        writeln!(o, "   </trkpt>")?;
+        o.flush()
+    }
+    /// End the current track segment, if there is one. The next point will
+    /// start a new one.
+    pub fn end_segment(&mut self) -> io::Result<()> {
+        if self.in_segment {
+            writeln!(self.out, "  </trkseg>")?;
+            self.in_segment = false;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+    /// The writer the GPX is going to.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+    /// Finish off the file, and return the writer it went to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_segment()?;
+        writeln!(self.out, " </trk>")?;
+        writeln!(self.out, "</gpx>")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A piece of XML, from `Lexer::next`.
+#[derive(Debug)]
+enum Token {
+    /// A start tag: the element's name (without any namespace prefix), its
+    /// attributes, and whether it's empty (`<x/>`).
+    Start(String, Vec<(String, String)>, bool),
+    /// An end tag, with the element's name.
+    End(String),
+    /// Some text (not just whitespace), with the entities replaced.
+    Text(String)
+}
+/// Splits XML into tags and text.
+#[derive(Debug)]
+struct Lexer<R> {
+    inner: R,
+    /// Whether the last `<` read hasn't been dealt with yet.
+    in_tag: bool
+}
+fn malformed<S: Into<String>>(s: S) -> Error {
+    Error::MalformedGpx(s.into())
+}
+/// Drop the namespace prefix from a name.
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+/// Replace the entities in some XML text.
+fn unescape(s: &str) -> GpsdResult<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        ret.push_str(&rest[..amp]);
+        let semi = rest[amp..].find(';').ok_or_else(|| malformed(format!("unterminated entity in '{}'", s)))?;
+        let entity = &rest[amp + 1..amp + semi];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None
+        };
+        ret.push(c.ok_or_else(|| malformed(format!("unknown entity '&{};'", entity)))?);
+        rest = &rest[amp + semi + 1..];
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+/// Is the end of this tag inside a quoted attribute value?
+fn in_quotes(tag: &[u8]) -> bool {
+    let mut quote = None;
+    for &b in tag {
+        match quote {
+            Some(q) if b == q => quote = None,
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            _ => {}
+        }
+    }
+    quote.is_some()
+}
+/// Parse the inside of a start tag (without the `<` and `>`).
+fn parse_start(tag: &str) -> GpsdResult<Token> {
+    let (tag, empty) = match tag.strip_suffix('/') {
+        Some(t) => (t, true),
+        None => (tag, false)
+    };
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = local_name(&tag[..name_end]).to_owned();
+    let mut attrs = vec![];
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let bad = || malformed(format!("bad attributes in <{}>", tag));
+        let eq = rest.find('=').ok_or_else(bad)?;
+        let key = local_name(rest[..eq].trim()).to_owned();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'').ok_or_else(bad)?;
+        let end = value[1..].find(quote).ok_or_else(bad)? + 1;
+        attrs.push((key, unescape(&value[1..end])?));
+        rest = value[end + 1..].trim_start();
+    }
+    Ok(Token::Start(name, attrs, empty))
+}
+impl<R: BufRead> Lexer<R> {
+    /// The next tag or piece of text, or `None` at the end of the file.
+    fn next(&mut self) -> GpsdResult<Option<Token>> {
+        let mut buf = vec![];
+        loop {
+            buf.clear();
+            if !self.in_tag {
+                self.inner.read_until(b'<', &mut buf)?;
+                self.in_tag = buf.last() == Some(&b'<');
+                if self.in_tag {
+                    buf.pop();
+                }
+                let text = String::from_utf8(buf).map_err(|_| malformed("text isn't valid UTF-8"))?;
+                if !text.trim().is_empty() {
+                    return Ok(Some(Token::Text(unescape(&text)?)));
+                }
+                if !self.in_tag {
+                    return Ok(None);
+                }
+                buf = text.into_bytes();
+                continue;
+            }
+            self.in_tag = false;
+            // Comments, CDATA and quoted attribute values can have a `>` in
+            // them; keep going until the real end of the tag.
+            loop {
+                self.inner.read_until(b'>', &mut buf)?;
+                if buf.last() != Some(&b'>') {
+                    return Err(malformed("file ends in the middle of a tag"));
+                }
+                let done = if buf.starts_with(b"!--") {
+                    buf.len() >= 5 && buf.ends_with(b"-->")
+                }
+                else if buf.starts_with(b"![CDATA[") {
+                    buf.ends_with(b"]]>")
+                }
+                else {
+                    !in_quotes(&buf)
+                };
+                if done {
+                    break;
+                }
+            }
+            buf.pop();
+            let tag = String::from_utf8(buf).map_err(|_| malformed("tag isn't valid UTF-8"))?;
+            if tag.starts_with("![CDATA[") {
+                return Ok(Some(Token::Text(tag["![CDATA[".len()..tag.len() - 2].to_owned())));
+            }
+            else if tag.starts_with('?') || tag.starts_with('!') {
+                // processing instructions, comments and DOCTYPEs
+                buf = tag.into_bytes();
+                continue;
+            }
+            else if let Some(name) = tag.strip_prefix('/') {
+                return Ok(Some(Token::End(local_name(name.trim()).to_owned())));
+            }
+            else {
+                return parse_start(tag.trim()).map(Some);
+            }
+        }
+    }
+}
+/// A track point, as read from the file.
+#[derive(Debug, Clone, Default)]
+struct Point {
+    /// Which track segment the point is in.
+    segment: usize,
+    lat: f64,
+    lon: f64,
+    ele: Option<f64>,
+    time: Option<DateTime<Utc>>,
+    fix: Option<String>,
+    sky: SkySummary,
+    geoid_sep: Option<f64>,
+    dgps_age: Option<f64>,
+    dgps_station: Option<u32>,
+    speed: Option<f64>,
+    course: Option<f64>
+}
+impl Point {
+    fn has_fix(&self) -> bool {
+        self.fix.as_ref().map_or(true, |f| f != "none")
+    }
+    fn position(&self) -> Position {
+        Position { lat: self.lat, lon: self.lon, alt: None }
+    }
+}
+/// Parse a value from a GPX file.
+fn parse<T: FromStr>(what: &str, s: &str) -> GpsdResult<T> {
+    s.trim().parse().map_err(|_| malformed(format!("invalid {} '{}'", what, s)))
+}
+/// A TPV without a fix, at `time`.
+fn no_fix(time: DateTime<Utc>) -> Response {
+    Response::Tpv(TpvFields {
+        time: Some(time),
+        mode: Some(FixMode::NoFix),
+        ..TpvFields::default()
+    }.classify())
+}
+/// Reads the track points in a GPX file as `Response`s.
+///
+/// This is an iterator over the responses. If the file turns out to be
+/// malformed, the iterator returns the error, then stops.
+#[derive(Debug)]
+pub struct GpxReader<R> {
+    lexer: Lexer<R>,
+    /// The number of the current track segment.
+    segment: usize,
+    /// The last point turned into a TPV, if it's one that later points can be
+    /// interpolated from.
+    prev: Option<Point>,
+    /// The point to turn into a TPV next, once the one after it is known.
+    cur: Option<Point>,
+    /// The satellite count and DOPs in the last SKY returned.
+    sky: SkySummary,
+    queue: VecDeque<Response>,
+    done: bool
+}
+impl GpxReader<BufReader<File>> {
+    /// Read the GPX file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> GpsdResult<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+impl<R: BufRead> GpxReader<R> {
+    /// Read a GPX file from `inner`.
+    pub fn new(inner: R) -> Self {
+        GpxReader {
+            lexer: Lexer { inner, in_tag: false },
+            segment: 0,
+            prev: None,
+            cur: None,
+            sky: SkySummary::default(),
+            queue: VecDeque::new(),
+            done: false
+        }
+    }
+    /// Read the next track point that has a time, or `None` at the end of
+    /// the file.
+    fn read_point(&mut self) -> GpsdResult<Option<Point>> {
+        let mut point: Option<Point> = None;
+        let mut text = String::new();
+        while let Some(token) = self.lexer.next()? {
+            match token {
+                Token::Start(name, attrs, empty) => {
+                    match &name as &str {
+                        "trk" | "trkseg" => self.segment += 1,
+                        "trkpt" if !empty => {
+                            let attr = |key: &str| attrs.iter().find(|a| a.0 == key).map(|a| &a.1 as &str)
+                                .ok_or_else(|| malformed(format!("<trkpt> without a {} attribute", key)));
+                            point = Some(Point {
+                                segment: self.segment,
+                                lat: parse("latitude", attr("lat")?)?,
+                                lon: parse("longitude", attr("lon")?)?,
+                                ..Point::default()
+                            });
+                        },
+                        _ => {}
+                    }
+                    text.clear();
+                },
+                Token::Text(t) => text.push_str(&t),
+                Token::End(name) => {
+                    if name == "trkpt" {
+                        match point.take() {
+                            Some(p) if p.time.is_some() => return Ok(Some(p)),
+                            _ => continue
+                        }
+                    }
+                    if let Some(ref mut p) = point {
+                        let t = text.trim();
+                        match &name as &str {
+                            "ele" => p.ele = Some(parse("elevation", t)?),
+                            "time" => {
+                                let time = DateTime::parse_from_rfc3339(t)
+                                    .map_err(|_| malformed(format!("invalid time '{}'", t)))?;
+                                p.time = Some(time.with_timezone(&Utc));
+                            },
+                            "fix" => p.fix = Some(t.to_owned()),
+                            "sat" => p.sky.sats = Some(parse("satellite count", t)?),
+                            "hdop" => p.sky.hdop = Some(parse("HDOP", t)?),
+                            "vdop" => p.sky.vdop = Some(parse("VDOP", t)?),
+                            "pdop" => p.sky.pdop = Some(parse("PDOP", t)?),
+                            "geoidheight" => p.geoid_sep = Some(parse("geoid height", t)?),
+                            "ageofdgpsdata" => p.dgps_age = Some(parse("DGPS age", t)?),
+                            "dgpsid" => p.dgps_station = Some(parse("DGPS station", t)?),
+                            "speed" => p.speed = Some(parse("speed", t)?),
+                            "course" => p.course = Some(parse("course", t)?),
+                            _ => {}
+                        }
+                    }
+                    text.clear();
+                }
+            }
+        }
+        Ok(None)
+    }
+    /// Turn `cur` into responses, given the point after it (if any).
+    fn emit(&mut self, cur: Point, next: Option<&Point>) {
+        if !cur.has_fix() {
+            if let Some(time) = cur.time {
+                self.queue.push_back(no_fix(time));
+            }
+            self.prev = None;
+            return;
+        }
+        if let Some(ref prev) = self.prev {
+            if prev.segment != cur.segment {
+                if let Some(time) = prev.time {
+                    self.queue.push_back(no_fix(time));
+                }
+            }
+        }
+        if cur.sky != self.sky && cur.sky != SkySummary::default() {
+            self.sky = cur.sky;
+            self.queue.push_back(Response::Sky(SkyResponse {
+                device: None,
+                time: cur.time,
+                xdop: None,
+                ydop: None,
+                vdop: cur.sky.vdop,
+                tdop: None,
+                hdop: cur.sky.hdop,
+                gdop: None,
+                pdop: cur.sky.pdop,
+                n_sat: None,
+                u_sat: cur.sky.sats,
+                satellites: None,
+                extra: Map::new()
+            }));
+        }
+
+        // Interpolate from the points either side, or just the one side if
+        // this is at the end of a segment.
+        let before = self.prev.as_ref().filter(|p| p.segment == cur.segment).unwrap_or(&cur);
+        let after = next.filter(|p| p.segment == cur.segment && p.has_fix()).unwrap_or(&cur);
+        let secs = |a: &Point, b: &Point| match (a.time, b.time) {
+            (Some(a), Some(b)) => (b - a).num_microseconds().unwrap_or(0) as f64 / 1e6,
+            _ => 0.0
+        };
+        let (mut speed, mut track, mut climb) = (None, None, None);
+        let dt = secs(before, after);
+        if dt > 0.0 {
+            let (a, here, b) = (before.position(), cur.position(), after.position());
+            speed = Some((a.distance_to(&here) + here.distance_to(&b)) / dt);
+            if a != b {
+                track = Some(a.bearing_to(&b));
+            }
+        }
+        // A neighbour might be a 2D fix, without an elevation. If neither has
+        // one, there's no sign of any climb.
+        if cur.ele.is_some() {
+            let before = if before.ele.is_some() { before } else { &cur };
+            let after = if after.ele.is_some() { after } else { &cur };
+            let dt = secs(before, after);
+            climb = match (before.ele, after.ele) {
+                (Some(a), Some(b)) if dt > 0.0 => Some((b - a) / dt),
+                _ => Some(0.0)
+            };
+        }
+
+        let mode = match cur.fix.as_ref().map(|f| f as &str) {
+            Some("2d") => FixMode::Fix2D,
+            _ if cur.ele.is_some() => FixMode::Fix3D,
+            _ => FixMode::Fix2D
+        };
+        let status = match cur.fix.as_ref().map(|f| f as &str) {
+            Some("dgps") => Some(FixStatus::Dgps),
+            Some("pps") => Some(FixStatus::PyCode),
+            _ => None
+        };
+        let tpv = TpvFields {
+            time: cur.time,
+            mode: Some(mode),
+            time_err: Some(0.0),
+            lat: Some(cur.lat),
+            lon: Some(cur.lon),
+            alt: if mode == FixMode::Fix3D { cur.ele } else { None },
+            track: cur.course.or(track),
+            speed: cur.speed.or(speed),
+            climb: if mode == FixMode::Fix3D { climb } else { None },
+            details: TpvDetails {
+                status,
+                alt_msl: cur.ele,
+                alt_hae: cur.ele.and_then(|e| cur.geoid_sep.map(|s| e + s)),
+                geoid_sep: cur.geoid_sep,
+                dgps_age: cur.dgps_age,
+                dgps_station: cur.dgps_station,
+                ..TpvDetails::default()
+            },
+            ..TpvFields::default()
+        };
+        self.queue.push_back(Response::Tpv(tpv.classify()));
+        self.prev = Some(cur);
+    }
+    /// Read another point, and turn the one before it into responses.
+    fn step(&mut self) -> GpsdResult<()> {
+        let next = self.read_point()?;
+        match self.cur.take() {
+            Some(cur) => self.emit(cur, next.as_ref()),
+            None if next.is_none() => self.done = true,
+            None => {}
+        }
+        self.cur = next;
+        Ok(())
+    }
+}
+impl<R: BufRead> Iterator for GpxReader<R> {
+    type Item = GpsdResult<Response>;
+    fn next(&mut self) -> Option<GpsdResult<Response>> {
+        loop {
+            if let Some(resp) = self.queue.pop_front() {
+                return Some(Ok(resp));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.step() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
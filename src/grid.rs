@@ -0,0 +1,436 @@
+//! Grid references: UTM, MGRS and Maidenhead locators.
+//!
+//! UTM and MGRS are computed on the WGS-84 ellipsoid using Krüger's series,
+//! which is accurate to well under a millimetre within a zone. The Norway and
+//! Svalbard zone exceptions are followed. Neither system covers the polar
+//! regions (north of 84°N and south of 80°S), which use UPS instead; this
+//! module doesn't implement UPS, so positions there are rejected with
+//! `GridError::OutOfRange`.
+//!
+//! Maidenhead locators (as used by radio amateurs) cover the whole globe.
+//!
+//! Everything here can be parsed back from its string form.
+use std::error::Error as StdError;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use geo::{normalize_lon, WGS84_A, WGS84_F};
+use types::{Position, TpvResponse};
+
+/// UTM scale factor on the central meridian.
+const K0: f64 = 0.9996;
+/// False easting.
+const FALSE_EASTING: f64 = 500_000.0;
+/// False northing, in the southern hemisphere.
+const FALSE_NORTHING: f64 = 10_000_000.0;
+/// UTM latitude band letters, from 80°S northwards. Each is 8° high, except
+/// for X, which is 12°.
+const BANDS: &[u8] = b"CDEFGHJKLMNPQRSTUVWX";
+/// MGRS 100km square column letters, for each set of zones.
+const MGRS_COLUMNS: [&[u8]; 3] = [b"ABCDEFGH", b"JKLMNPQR", b"STUVWXYZ"];
+/// MGRS 100km square row letters.
+const MGRS_ROWS: &[u8] = b"ABCDEFGHJKLMNPQRSTUV";
+
+/// Something went wrong converting to or from a grid reference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GridError {
+    /// The position is outside the area the grid covers (such as the polar
+    /// regions for UTM and MGRS), or isn't a valid position at all.
+    OutOfRange,
+    /// The fix being converted has no position.
+    NoFix,
+    /// The grid reference couldn't be parsed.
+    Parse(String)
+}
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GridError::OutOfRange => write!(f, "position is outside the grid"),
+            GridError::NoFix => write!(f, "no position fix"),
+            GridError::Parse(ref s) => write!(f, "invalid grid reference '{}'", s)
+        }
+    }
+}
+impl StdError for GridError {}
+
+/// Coefficients of Krüger's series, to third order in n.
+struct Kruger {
+    /// Rectifying radius, times the scale factor.
+    a: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3]
+}
+fn kruger() -> Kruger {
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let (n2, n3) = (n * n, n * n * n);
+    Kruger {
+        a: K0 * WGS84_A / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0),
+        alpha: [n / 2.0 - 2.0 / 3.0 * n2 + 5.0 / 16.0 * n3, 13.0 / 48.0 * n2 - 3.0 / 5.0 * n3, 61.0 / 240.0 * n3],
+        beta: [n / 2.0 - 2.0 / 3.0 * n2 + 37.0 / 96.0 * n3, n2 / 48.0 + n3 / 15.0, 17.0 / 480.0 * n3],
+        delta: [2.0 * n - 2.0 / 3.0 * n2 - 2.0 * n3, 7.0 / 3.0 * n2 - 8.0 / 5.0 * n3, 56.0 / 15.0 * n3]
+    }
+}
+/// A UTM grid reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Utm {
+    /// Zone number, 1 to 60.
+    pub zone: u8,
+    /// Latitude band letter, `C` to `X`. Bands `N` and above are in the
+    /// northern hemisphere.
+    pub band: char,
+    /// Meters east, including the false easting of 500km.
+    pub easting: f64,
+    /// Meters north of the equator, or, in the southern hemisphere, of 10000km
+    /// south of it.
+    pub northing: f64
+}
+/// The latitude band letter for a latitude, or `None` outside 80°S to 84°N.
+fn band_for(lat: f64) -> Option<char> {
+    if !(-80.0..=84.0).contains(&lat) {
+        return None;
+    }
+    let idx = (((lat + 80.0) / 8.0).floor() as usize).min(BANDS.len() - 1);
+    Some(BANDS[idx] as char)
+}
+/// The southern edge of a latitude band, in degrees.
+fn band_bottom(band: char) -> Option<f64> {
+    BANDS.iter().position(|&b| b as char == band).map(|i| -80.0 + 8.0 * i as f64)
+}
+/// The UTM zone for a position, following the Norway and Svalbard exceptions.
+fn zone_for(lat: f64, lon: f64) -> u8 {
+    let lon = normalize_lon(lon);
+    let mut zone = (((lon + 180.0) / 6.0).floor() as u8 % 60) + 1;
+    if (56.0..64.0).contains(&lat) && (3.0..12.0).contains(&lon) {
+        zone = 32;
+    }
+    if (72.0..=84.0).contains(&lat) && (0.0..42.0).contains(&lon) {
+        zone = if lon < 9.0 { 31 } else if lon < 21.0 { 33 } else if lon < 33.0 { 35 } else { 37 };
+    }
+    zone
+}
+fn central_meridian(zone: u8) -> f64 {
+    (zone as f64 - 1.0) * 6.0 - 180.0 + 3.0
+}
+impl Utm {
+    /// Convert a position to UTM.
+    pub fn from_lat_lon(lat: f64, lon: f64) -> Result<Utm, GridError> {
+        if !lon.is_finite() {
+            return Err(GridError::OutOfRange);
+        }
+        let band = band_for(lat).ok_or(GridError::OutOfRange)?;
+        let zone = zone_for(lat, lon);
+        let (easting, northing) = project(zone, lat, lon);
+        Ok(Utm {
+            zone, band, easting,
+            northing: if lat < 0.0 { northing + FALSE_NORTHING } else { northing }
+        })
+    }
+    /// Is this in the northern hemisphere?
+    pub fn is_north(&self) -> bool {
+        self.band >= 'N'
+    }
+    /// Convert back to a position (with no altitude).
+    pub fn to_position(&self) -> Result<Position, GridError> {
+        if !(1..=60).contains(&self.zone) || band_bottom(self.band).is_none() {
+            return Err(GridError::OutOfRange);
+        }
+        let k = kruger();
+        let northing = if self.is_north() { self.northing } else { self.northing - FALSE_NORTHING };
+        let xi = northing / k.a;
+        let eta = (self.easting - FALSE_EASTING) / k.a;
+        let (mut xi_p, mut eta_p) = (xi, eta);
+        for j in 0..3 {
+            let m = 2.0 * (j + 1) as f64;
+            xi_p -= k.beta[j] * (m * xi).sin() * (m * eta).cosh();
+            eta_p -= k.beta[j] * (m * xi).cos() * (m * eta).sinh();
+        }
+        let chi = (xi_p.sin() / eta_p.cosh()).asin();
+        let mut lat = chi;
+        for j in 0..3 {
+            lat += k.delta[j] * (2.0 * (j + 1) as f64 * chi).sin();
+        }
+        let lon = central_meridian(self.zone).to_radians() + eta_p.sinh().atan2(xi_p.cos());
+        Ok(Position { lat: lat.to_degrees(), lon: normalize_lon(lon.to_degrees()), alt: None })
+    }
+}
+/// Project a position onto the transverse Mercator for `zone`, returning the
+/// easting and (unshifted) northing.
+fn project(zone: u8, lat: f64, lon: f64) -> (f64, f64) {
+    let k = kruger();
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let phi = lat.to_radians();
+    let mut d_lambda = (lon - central_meridian(zone)).to_radians();
+    if d_lambda > PI {
+        d_lambda -= 2.0 * PI;
+    }
+    else if d_lambda < -PI {
+        d_lambda += 2.0 * PI;
+    }
+    let c = 2.0 * n.sqrt() / (1.0 + n);
+    let t = (phi.sin().atanh() - c * (c * phi.sin()).atanh()).sinh();
+    let xi_p = t.atan2(d_lambda.cos());
+    let eta_p = (d_lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+    let (mut x, mut y) = (eta_p, xi_p);
+    for j in 0..3 {
+        let m = 2.0 * (j + 1) as f64;
+        x += k.alpha[j] * (m * xi_p).cos() * (m * eta_p).sinh();
+        y += k.alpha[j] * (m * xi_p).sin() * (m * eta_p).cosh();
+    }
+    (FALSE_EASTING + k.a * x, k.a * y)
+}
+impl fmt::Display for Utm {
+    /// Formats as e.g. `31U 448252 5411933`, to the nearest meter.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{} {:.0} {:.0}", self.zone, self.band, self.easting, self.northing)
+    }
+}
+impl FromStr for Utm {
+    type Err = GridError;
+    /// Parses e.g. `31U 448252 5411933`. The zone and band may be separated
+    /// by a space, and the easting and northing may have decimals.
+    fn from_str(s: &str) -> Result<Utm, GridError> {
+        let err = || GridError::Parse(s.into());
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        let (zone_band, rest) = match parts.len() {
+            3 => (parts[0].to_owned(), &parts[1..]),
+            // zone and band separated
+            4 => (format!("{}{}", parts[0], parts[1]), &parts[2..]),
+            _ => return Err(err())
+        };
+        let (zone, band) = split_zone_band(&zone_band).ok_or_else(err)?;
+        let easting = rest[0].parse().map_err(|_| err())?;
+        let northing = rest[1].parse().map_err(|_| err())?;
+        Ok(Utm { zone, band, easting, northing })
+    }
+}
+/// Split something like `31U` into a zone and band, checking both are valid.
+fn split_zone_band(s: &str) -> Option<(u8, char)> {
+    let idx = s.find(|c: char| !c.is_ascii_digit())?;
+    let zone: u8 = s[..idx].parse().ok()?;
+    let mut rest = s[idx..].chars();
+    let band = rest.next()?.to_ascii_uppercase();
+    if rest.next().is_some() || !(1..=60).contains(&zone) || band_bottom(band).is_none() {
+        return None;
+    }
+    // these were swallowed up by the Svalbard exception
+    if band == 'X' && (zone == 32 || zone == 34 || zone == 36) {
+        return None;
+    }
+    Some((zone, band))
+}
+/// An MGRS grid reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mgrs {
+    /// UTM zone number, 1 to 60.
+    pub zone: u8,
+    /// Latitude band letter.
+    pub band: char,
+    /// The two letters identifying the 100km square.
+    pub square: [char; 2],
+    /// Meters east within the 100km square.
+    pub easting: f64,
+    /// Meters north within the 100km square.
+    pub northing: f64
+}
+impl Mgrs {
+    /// Convert a position to MGRS.
+    pub fn from_lat_lon(lat: f64, lon: f64) -> Result<Mgrs, GridError> {
+        Ok(Utm::from_lat_lon(lat, lon)?.into())
+    }
+    /// Convert to UTM.
+    pub fn to_utm(&self) -> Result<Utm, GridError> {
+        let set = (self.zone as usize + 2) % 3;
+        let col = MGRS_COLUMNS[set].iter().position(|&c| c as char == self.square[0])
+            .ok_or(GridError::OutOfRange)?;
+        let row = MGRS_ROWS.iter().position(|&c| c as char == self.square[1])
+            .ok_or(GridError::OutOfRange)?;
+        let offset = if self.zone % 2 == 0 { 5 } else { 0 };
+        let easting = (col + 1) as f64 * 100_000.0 + self.easting;
+        let row = (row + MGRS_ROWS.len() - offset) % MGRS_ROWS.len();
+        let northing = row as f64 * 100_000.0 + self.northing;
+        // The row letters repeat every 2000km; pick the repetition that lands
+        // in the right latitude band. The bottom of the band at the zone edge
+        // can be a little south of the central meridian's, hence the margin.
+        let bottom = band_bottom(self.band).ok_or(GridError::OutOfRange)?;
+        let (_, mut band_northing) = project(self.zone, bottom, central_meridian(self.zone));
+        if bottom < 0.0 {
+            band_northing += FALSE_NORTHING;
+        }
+        let mut northing = northing;
+        while northing < band_northing - 100_000.0 {
+            northing += 2_000_000.0;
+        }
+        Ok(Utm { zone: self.zone, band: self.band, easting, northing })
+    }
+    /// Convert back to a position (with no altitude). This is the south-west
+    /// corner of the square the reference identifies.
+    pub fn to_position(&self) -> Result<Position, GridError> {
+        self.to_utm()?.to_position()
+    }
+    /// Format with `digits` digits each for the easting and northing, from 0
+    /// (a 100km square) to 5 (a 1m square). The position is truncated, not
+    /// rounded, as MGRS requires.
+    pub fn to_string_with_precision(&self, digits: usize) -> String {
+        let digits = digits.min(5);
+        let scale = 10f64.powi(5 - digits as i32);
+        let e = (self.easting / scale).floor() as u32;
+        let n = (self.northing / scale).floor() as u32;
+        let mut ret = format!("{}{} {}{}", self.zone, self.band, self.square[0], self.square[1]);
+        if digits > 0 {
+            ret.push_str(&format!(" {:0width$} {:0width$}", e, n, width = digits));
+        }
+        ret
+    }
+}
+impl From<Utm> for Mgrs {
+    fn from(utm: Utm) -> Mgrs {
+        let set = (utm.zone as usize + 2) % 3;
+        let col = ((utm.easting / 100_000.0).floor() as usize).clamp(1, 8) - 1;
+        let offset = if utm.zone % 2 == 0 { 5 } else { 0 };
+        let row = ((utm.northing / 100_000.0).floor() as usize + offset) % MGRS_ROWS.len();
+        Mgrs {
+            zone: utm.zone,
+            band: utm.band,
+            square: [MGRS_COLUMNS[set][col] as char, MGRS_ROWS[row] as char],
+            easting: utm.easting % 100_000.0,
+            northing: utm.northing % 100_000.0
+        }
+    }
+}
+impl fmt::Display for Mgrs {
+    /// Formats as e.g. `31U DQ 48251 11932`, to 1m.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_string_with_precision(5))
+    }
+}
+impl FromStr for Mgrs {
+    type Err = GridError;
+    /// Parses e.g. `31U DQ 48251 11932` or `31UDQ4825111932`, at any precision.
+    fn from_str(s: &str) -> Result<Mgrs, GridError> {
+        let err = || GridError::Parse(s.into());
+        let compact: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        // (everything below slices by byte)
+        if !compact.is_ascii() {
+            return Err(err());
+        }
+        let idx = compact.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+        if compact.len() < idx + 3 {
+            return Err(err());
+        }
+        let (zone, band) = split_zone_band(&compact[..idx + 1]).ok_or_else(err)?;
+        let mut letters = compact[idx + 1..idx + 3].chars().map(|c| c.to_ascii_uppercase());
+        let square = [letters.next().ok_or_else(err)?, letters.next().ok_or_else(err)?];
+        let digits = &compact[idx + 3..];
+        if digits.len() % 2 != 0 || digits.len() > 10 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(err());
+        }
+        let half = digits.len() / 2;
+        let scale = 10f64.powi(5 - half as i32);
+        let parse = |d: &str| if d.is_empty() { Ok(0.0) } else { d.parse::<f64>().map(|x| x * scale).map_err(|_| err()) };
+        let ret = Mgrs {
+            zone, band, square,
+            easting: parse(&digits[..half])?,
+            northing: parse(&digits[half..])?
+        };
+        // check the square letters are valid for the zone
+        ret.to_utm().map_err(|_| err())?;
+        Ok(ret)
+    }
+}
+/// The Maidenhead locator for a position, with `pairs` pairs of characters:
+/// 1 for a field (e.g. `JN`), 2 for a square (`JN58`), 3 for a subsquare
+/// (`JN58td`), and so on up to 5.
+pub fn to_maidenhead(lat: f64, lon: f64, pairs: usize) -> Result<String, GridError> {
+    if !(-90.0..=90.0).contains(&lat) || !lon.is_finite() {
+        return Err(GridError::OutOfRange);
+    }
+    let pairs = pairs.clamp(1, 5);
+    // the north pole is in the top row of squares, not above them
+    let mut lat = (lat + 90.0).min(180.0 - 1e-9);
+    let mut lon = normalize_lon(lon) + 180.0;
+    let mut ret = String::with_capacity(pairs * 2);
+    let (mut lon_size, mut lat_size) = (20.0, 10.0);
+    for i in 0..pairs {
+        let (base, divisions) = match i {
+            0 => (b'A', 18.0),
+            _ if i % 2 == 1 => (b'0', 10.0),
+            _ => (b'a', 24.0)
+        };
+        if i > 0 {
+            lon_size /= divisions;
+            lat_size /= divisions;
+        }
+        let x = ((lon / lon_size).floor() as u8).min(divisions as u8 - 1);
+        let y = ((lat / lat_size).floor() as u8).min(divisions as u8 - 1);
+        ret.push((base + x) as char);
+        ret.push((base + y) as char);
+        lon -= x as f64 * lon_size;
+        lat -= y as f64 * lat_size;
+    }
+    Ok(ret)
+}
+/// Parse a Maidenhead locator, returning the position at the centre of the
+/// area it identifies.
+pub fn from_maidenhead(loc: &str) -> Result<Position, GridError> {
+    let err = || GridError::Parse(loc.into());
+    let chars = loc.trim().as_bytes();
+    if chars.is_empty() || chars.len() % 2 != 0 || chars.len() > 10 {
+        return Err(err());
+    }
+    let (mut lon, mut lat) = (0.0, 0.0);
+    let (mut lon_size, mut lat_size) = (20.0, 10.0);
+    for (i, pair) in chars.chunks(2).enumerate() {
+        let (base, divisions) = match i {
+            0 => (b'A', 18u8),
+            _ if i % 2 == 1 => (b'0', 10),
+            _ => (b'A', 24)
+        };
+        if i > 0 {
+            lon_size /= divisions as f64;
+            lat_size /= divisions as f64;
+        }
+        let x = pair[0].to_ascii_uppercase().wrapping_sub(base);
+        let y = pair[1].to_ascii_uppercase().wrapping_sub(base);
+        if x >= divisions || y >= divisions {
+            return Err(err());
+        }
+        lon += x as f64 * lon_size;
+        lat += y as f64 * lat_size;
+    }
+    Ok(Position {
+        lat: lat + lat_size / 2.0 - 90.0,
+        lon: lon + lon_size / 2.0 - 180.0,
+        alt: None
+    })
+}
+impl Position {
+    /// This position as a UTM grid reference.
+    pub fn utm(&self) -> Result<Utm, GridError> {
+        Utm::from_lat_lon(self.lat, self.lon)
+    }
+    /// This position as an MGRS grid reference.
+    pub fn mgrs(&self) -> Result<Mgrs, GridError> {
+        Mgrs::from_lat_lon(self.lat, self.lon)
+    }
+    /// This position as a Maidenhead locator with `pairs` pairs of characters
+    /// (see `to_maidenhead`).
+    pub fn maidenhead(&self, pairs: usize) -> Result<String, GridError> {
+        to_maidenhead(self.lat, self.lon, pairs)
+    }
+}
+impl TpvResponse {
+    /// The position of this fix as a UTM grid reference.
+    pub fn utm(&self) -> Result<Utm, GridError> {
+        self.position().ok_or(GridError::NoFix)?.utm()
+    }
+    /// The position of this fix as an MGRS grid reference.
+    pub fn mgrs(&self) -> Result<Mgrs, GridError> {
+        self.position().ok_or(GridError::NoFix)?.mgrs()
+    }
+    /// The position of this fix as a Maidenhead locator with `pairs` pairs of
+    /// characters.
+    pub fn maidenhead(&self, pairs: usize) -> Result<String, GridError> {
+        self.position().ok_or(GridError::NoFix)?.maidenhead(pairs)
+    }
+}
@@ -0,0 +1,334 @@
+//! Smoothing fixes with a Kalman filter.
+//!
+//! `KalmanFilter` tracks a position and velocity under a constant-velocity
+//! model (with random accelerations), and corrects them with each TPV it's
+//! given. Each measurement is weighted by gpsd's error estimates: `epx`,
+//! `epy` and `epv` for the position, and `eps` and `epc` for the velocity. A
+//! fix with small errors moves the estimate a long way; one with large errors
+//! hardly moves it at all. This takes out most of the jitter in a stationary
+//! receiver's position, while still following it when it moves.
+//!
+//! Between fixes, `predict` dead-reckons from the last estimate.
+//!
+//! The filter works in a local east-north-up frame (see `coords::LocalFrame`)
+//! centred on the first fix, moved along whenever the estimate gets more than
+//! 10km from it; the velocity and covariance are rotated into the new frame.
+//! Altitudes are heights above the ellipsoid (see `TpvResponse::hae`), both
+//! going in and coming out.
+use chrono::{DateTime, Utc};
+use coords::{Enu, LocalFrame};
+use types::{Position, Response, TpvResponse};
+
+/// Move the frame once the estimate is this far from its origin, in meters.
+const RECENTRE_DISTANCE: f64 = 10_000.0;
+/// Variance of the velocity when there's no measurement of it, in (m/s)^2.
+const UNKNOWN_VELOCITY_VARIANCE: f64 = 100.0;
+
+/// An estimate of the position and velocity from a `KalmanFilter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The time the estimate is for.
+    pub time: DateTime<Utc>,
+    /// Estimated position. The altitude is a height above the ellipsoid, and
+    /// is missing if no fix has had one yet.
+    pub position: Position,
+    /// Estimated velocity, in meters per second.
+    pub velocity: Enu,
+    /// Covariance of the state, in the order east, north and up position
+    /// (m), then east, north and up velocity (m/s).
+    pub covariance: [[f64; 6]; 6]
+}
+impl Estimate {
+    /// Standard deviation of the position, in meters.
+    pub fn position_std_dev(&self) -> Enu {
+        let c = &self.covariance;
+        Enu { east: c[0][0].sqrt(), north: c[1][1].sqrt(), up: c[2][2].sqrt() }
+    }
+    /// Standard deviation of the velocity, in meters per second.
+    pub fn velocity_std_dev(&self) -> Enu {
+        let c = &self.covariance;
+        Enu { east: c[3][3].sqrt(), north: c[4][4].sqrt(), up: c[5][5].sqrt() }
+    }
+}
+/// The state of a `KalmanFilter` that has had at least one fix.
+#[derive(Debug, Clone, Copy)]
+struct State {
+    frame: LocalFrame,
+    time: DateTime<Utc>,
+    /// East, north and up position, then east, north and up velocity.
+    x: [f64; 6],
+    /// Covariance of `x`.
+    p: [[f64; 6]; 6],
+    /// Whether there's been an altitude yet.
+    has_alt: bool
+}
+// (matrix arithmetic reads better with indices)
+#[allow(clippy::needless_range_loop)]
+impl State {
+    /// Start axis `i` (0 for east, 1 for north, 2 for up) again at position
+    /// `x` with variance `var`, and an unknown velocity.
+    fn reset_axis(&mut self, i: usize, x: f64, var: f64) {
+        for j in [i, i + 3].iter() {
+            self.x[*j] = 0.0;
+            for k in 0..6 {
+                self.p[*j][k] = 0.0;
+                self.p[k][*j] = 0.0;
+            }
+        }
+        self.x[i] = x;
+        self.p[i][i] = var;
+        self.p[i + 3][i + 3] = UNKNOWN_VELOCITY_VARIANCE;
+    }
+    /// Move on by `dt` seconds, with acceleration noise of spectral density
+    /// `q` for each axis.
+    fn predict(&mut self, dt: f64, q: [f64; 3]) {
+        // P = F P F' + Q, where F adds dt times each velocity to its position
+        for i in 0..3 {
+            self.x[i] += self.x[i + 3] * dt;
+            for k in 0..6 {
+                self.p[i][k] += dt * self.p[i + 3][k];
+            }
+        }
+        for i in 0..3 {
+            for k in 0..6 {
+                self.p[k][i] += dt * self.p[k][i + 3];
+            }
+        }
+        for i in 0..3 {
+            self.p[i][i] += q[i] * dt * dt * dt / 3.0;
+            self.p[i][i + 3] += q[i] * dt * dt / 2.0;
+            self.p[i + 3][i] += q[i] * dt * dt / 2.0;
+            self.p[i + 3][i + 3] += q[i] * dt;
+        }
+        self.symmetrize();
+    }
+    /// Correct with a measurement `z` of state element `i`, with variance
+    /// `r`.
+    fn correct(&mut self, i: usize, z: f64, r: f64) {
+        let p = self.p;
+        let s = p[i][i] + r;
+        let y = z - self.x[i];
+        for j in 0..6 {
+            let k = p[j][i] / s;
+            self.x[j] += k * y;
+            for c in 0..6 {
+                self.p[j][c] = p[j][c] - k * p[i][c];
+            }
+        }
+        self.symmetrize();
+    }
+    /// Rotate the velocity and covariance by `r`, which takes vectors from
+    /// the old frame to the new.
+    fn rotate(&mut self, r: [[f64; 3]; 3]) {
+        // T = diag(R, R), applied to the velocity and to both sides of P
+        let t = |i: usize, j: usize| if i / 3 == j / 3 { r[i % 3][j % 3] } else { 0.0 };
+        let v = [self.x[3], self.x[4], self.x[5]];
+        for i in 0..3 {
+            self.x[i + 3] = (0..3).map(|j| r[i][j] * v[j]).sum();
+        }
+        let mut tp = [[0.0; 6]; 6];
+        for (i, row) in tp.iter_mut().enumerate() {
+            for (j, tp) in row.iter_mut().enumerate() {
+                *tp = (0..6).map(|k| t(i, k) * self.p[k][j]).sum();
+            }
+        }
+        for i in 0..6 {
+            for j in 0..6 {
+                self.p[i][j] = (0..6).map(|k| tp[i][k] * t(j, k)).sum();
+            }
+        }
+        self.symmetrize();
+    }
+    /// Take out the rounding errors that would make `p` asymmetric.
+    fn symmetrize(&mut self) {
+        for i in 0..6 {
+            for j in 0..i {
+                let mean = (self.p[i][j] + self.p[j][i]) / 2.0;
+                self.p[i][j] = mean;
+                self.p[j][i] = mean;
+            }
+        }
+    }
+}
+/// A constant-velocity Kalman filter over TPVs.
+#[derive(Debug, Clone)]
+pub struct KalmanFilter {
+    horizontal_noise: f64,
+    vertical_noise: f64,
+    default_error: f64,
+    default_speed_error: f64,
+    state: Option<State>
+}
+impl Default for KalmanFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// Variance from a 95% error estimate.
+fn variance(err95: f64) -> f64 {
+    let sd = err95 / 1.96;
+    sd * sd
+}
+impl KalmanFilter {
+    /// Make a new filter, with no fixes yet.
+    pub fn new() -> Self {
+        KalmanFilter {
+            horizontal_noise: 1.0,
+            vertical_noise: 0.5,
+            default_error: 20.0,
+            default_speed_error: 2.0,
+            state: None
+        }
+    }
+    /// Set how much the velocity is expected to change, as the spectral
+    /// density of the random acceleration, in m^2/s^3, horizontally and
+    /// vertically. Smaller numbers smooth more, but follow changes of speed
+    /// or direction more slowly. The defaults are 1 and 0.5, which suit
+    /// something walking or driving gently.
+    pub fn set_process_noise(&mut self, horizontal: f64, vertical: f64) {
+        self.horizontal_noise = horizontal;
+        self.vertical_noise = vertical;
+    }
+    /// Set the 95% error estimates assumed for fixes that don't have them:
+    /// `position` in meters (20 by default), and `speed` in meters per second
+    /// (2 by default).
+    pub fn set_default_errors(&mut self, position: f64, speed: f64) {
+        self.default_error = position;
+        self.default_speed_error = speed;
+    }
+    /// Forget everything, and start again from the next fix.
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+    /// Add a response to the filter, returning the new estimate if it was a
+    /// TPV with a fix. Anything else is ignored.
+    pub fn handle(&mut self, resp: &Response) -> Option<Estimate> {
+        match *resp {
+            Response::Tpv(ref tpv) => self.update(tpv),
+            _ => None
+        }
+    }
+    /// Add a TPV to the filter, returning the new estimate.
+    ///
+    /// TPVs without a fix or a time are ignored (returning `None`), as are
+    /// TPVs older than the current estimate.
+    pub fn update(&mut self, tpv: &TpvResponse) -> Option<Estimate> {
+        if !tpv.mode().has_fix() {
+            return None;
+        }
+        let pos = Position { alt: tpv.hae(), ..tpv.position()? };
+        let time = tpv.time()?;
+        let errors = tpv.errors();
+        let var_e = variance(errors.lon.unwrap_or(self.default_error));
+        let var_n = variance(errors.lat.unwrap_or(self.default_error));
+        let var_u = variance(errors.alt.unwrap_or(self.default_error));
+
+        let fresh = self.state.is_none();
+        let mut state = match self.state {
+            Some(s) if time < s.time => return None,
+            Some(mut s) => {
+                let dt = (time - s.time).num_microseconds().unwrap_or(0) as f64 / 1e6;
+                self.predict_state(&mut s, dt);
+                s.time = time;
+                self.recentre(&mut s);
+                s
+            },
+            None => {
+                let mut state = State {
+                    frame: LocalFrame::new(pos), time,
+                    x: [0.0; 6],
+                    p: [[0.0; 6]; 6],
+                    has_alt: pos.alt.is_some()
+                };
+                state.reset_axis(0, 0.0, var_e);
+                state.reset_axis(1, 0.0, var_n);
+                state.reset_axis(2, 0.0, var_u);
+                state
+            }
+        };
+        let z = state.frame.position_to_enu(&Position {
+            alt: Some(pos.alt.unwrap_or_else(|| state.frame.origin().alt.unwrap_or(0.0) + state.x[2])),
+            ..pos
+        });
+        // (the first fix is where we started from, not a correction)
+        if !fresh {
+            state.correct(0, z.east, var_e);
+            state.correct(1, z.north, var_n);
+            if pos.alt.is_some() && state.has_alt {
+                state.correct(2, z.up, var_u);
+            }
+        }
+        if pos.alt.is_some() && !state.has_alt {
+            state.reset_axis(2, z.up, var_u);
+            state.has_alt = true;
+        }
+
+        if let Some(vel) = tpv.enu_velocity() {
+            let var_s = variance(errors.speed.unwrap_or(self.default_speed_error));
+            state.correct(3, vel.east, var_s);
+            state.correct(4, vel.north, var_s);
+            if tpv.climb().is_some() || tpv.details().vel_d.is_some() {
+                let var_c = variance(errors.climb.unwrap_or(self.default_speed_error));
+                state.correct(5, vel.up, var_c);
+            }
+        }
+        self.state = Some(state);
+        self.estimate()
+    }
+    fn predict_state(&self, s: &mut State, dt: f64) {
+        s.predict(dt, [self.horizontal_noise, self.horizontal_noise, self.vertical_noise]);
+    }
+    /// Move the frame to the current estimate, if it's got too far away.
+    fn recentre(&self, s: &mut State) {
+        if s.x[0].hypot(s.x[1]) < RECENTRE_DISTANCE {
+            return;
+        }
+        let here = s.frame.enu_to_position(Enu { east: s.x[0], north: s.x[1], up: s.x[2] });
+        let frame = LocalFrame::new(here);
+        // the rotation from the old frame to the new, a column at a time
+        let mut r = [[0.0; 3]; 3];
+        let axes = [Enu { east: 1.0, north: 0.0, up: 0.0 }, Enu { east: 0.0, north: 1.0, up: 0.0 }, Enu { east: 0.0, north: 0.0, up: 1.0 }];
+        for (j, axis) in axes.iter().enumerate() {
+            let col = frame.rotate_to_enu(s.frame.rotate_to_ecef(*axis));
+            r[0][j] = col.east;
+            r[1][j] = col.north;
+            r[2][j] = col.up;
+        }
+        s.rotate(r);
+        s.frame = frame;
+        s.x[0] = 0.0;
+        s.x[1] = 0.0;
+        s.x[2] = 0.0;
+    }
+    fn make_estimate(s: &State) -> Estimate {
+        let mut position = s.frame.enu_to_position(Enu { east: s.x[0], north: s.x[1], up: s.x[2] });
+        if !s.has_alt {
+            position.alt = None;
+        }
+        Estimate {
+            time: s.time,
+            position,
+            velocity: Enu { east: s.x[3], north: s.x[4], up: s.x[5] },
+            covariance: s.p
+        }
+    }
+    /// The current estimate, as of the last fix, or `None` if there hasn't
+    /// been one.
+    pub fn estimate(&self) -> Option<Estimate> {
+        self.state.as_ref().map(Self::make_estimate)
+    }
+    /// Dead-reckon from the current estimate to `time`, without changing the
+    /// filter. The uncertainty grows the further ahead this is.
+    ///
+    /// Returns `None` if there hasn't been a fix yet. Times before the last fix
+    /// give the estimate as of that fix.
+    pub fn predict(&self, time: DateTime<Utc>) -> Option<Estimate> {
+        let mut s = self.state?;
+        if time > s.time {
+            let dt = (time - s.time).num_microseconds().unwrap_or(0) as f64 / 1e6;
+            self.predict_state(&mut s, dt);
+            s.time = time;
+        }
+        Some(Self::make_estimate(&s))
+    }
+}
@@ -0,0 +1,260 @@
+//! Writing tracks as KML, for Google Earth.
+//!
+//! Fixes are written as a `gx:Track`: a list of times (`when`) and positions
+//! (`gx:coord`), which Google Earth can play back with its time slider. Each
+//! point also has its speed, course, and the satellite count and HDOP from
+//! the most recent SKY, as `ExtendedData`, which Google Earth shows in the
+//! track's elevation profile. As with GPX, losing the fix ends the track, and
+//! the next fix starts a new one; all of the tracks go in one `gx:MultiTrack`.
+//!
+//! `write_kml` writes out a whole recording at once. `KmlWriter` writes one
+//! out as the responses arrive. A `gx:Track` lists all of its times before
+//! any of its positions, so each track has to be kept in memory until it's
+//! written; to stop a long recording using more and more memory, tracks are
+//! cut into pieces of at most `set_max_points` points (3600 by default), each
+//! starting where the last one left off.
+//!
+//! Altitudes are above mean sea level (`altMSL`, or `alt` if gpsd didn't send
+//! that), as KML expects; fixes without one use the altitude of the fix
+//! before. How Google Earth treats them is set with `set_altitude_mode`.
+//! Fixes without a time are left out, since there's nowhere to put them on
+//! the time slider.
+use std::io::{self, Write};
+use chrono::{DateTime, SecondsFormat, Utc};
+use gpx::{escape, SkySummary};
+use types::{Response, SkyResponse, TpvResponse};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+ <Document>
+"#;
+const SCHEMA: &str = r#"  <Schema id="tpv">
+   <gx:SimpleArrayField name="speed" type="float">
+    <displayName>Speed (m/s)</displayName>
+   </gx:SimpleArrayField>
+   <gx:SimpleArrayField name="course" type="float">
+    <displayName>Course (°)</displayName>
+   </gx:SimpleArrayField>
+   <gx:SimpleArrayField name="sats" type="int">
+    <displayName>Satellites</displayName>
+   </gx:SimpleArrayField>
+   <gx:SimpleArrayField name="hdop" type="float">
+    <displayName>HDOP</displayName>
+   </gx:SimpleArrayField>
+  </Schema>
+"#;
+
+/// How Google Earth should treat the altitudes of a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltitudeMode {
+    /// Ignore the altitudes, and draw the track along the ground.
+    ClampToGround,
+    /// Take the altitudes as heights above the ground.
+    RelativeToGround,
+    /// Take the altitudes as heights above mean sea level.
+    Absolute
+}
+impl AltitudeMode {
+    /// The name KML uses for this mode.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AltitudeMode::ClampToGround => "clampToGround",
+            AltitudeMode::RelativeToGround => "relativeToGround",
+            AltitudeMode::Absolute => "absolute"
+        }
+    }
+}
+/// A point waiting to be written.
+#[derive(Debug, Clone)]
+struct Point {
+    time: DateTime<Utc>,
+    lat: f64,
+    lon: f64,
+    alt: Option<f64>,
+    speed: Option<f64>,
+    course: Option<f64>,
+    sky: SkySummary
+}
+/// The `ExtendedData` fields, in the order `Point::values` gives them.
+const FIELDS: [&str; 4] = ["speed", "course", "sats", "hdop"];
+impl Point {
+    fn values(&self) -> [Option<String>; 4] {
+        [
+            self.speed.map(|v| v.to_string()),
+            self.course.map(|v| v.to_string()),
+            self.sky.sats.map(|v| v.to_string()),
+            self.sky.hdop.map(|v| v.to_string())
+        ]
+    }
+}
+/// Writes TPVs out as a KML track.
+#[derive(Debug)]
+pub struct KmlWriter<W: Write> {
+    out: W,
+    altitude_mode: AltitudeMode,
+    max_points: usize,
+    sky: SkySummary,
+    /// The current track, not yet written.
+    track: Vec<Point>,
+    /// Whether the first point of `track` was carried over from the last
+    /// piece written, so has been written already.
+    carried: bool,
+    /// The altitude of the last fix that had one.
+    last_alt: Option<f64>
+}
+impl<W: Write> KmlWriter<W> {
+    /// Start writing a KML file to `out`, with an unnamed track.
+    pub fn new(out: W) -> io::Result<Self> {
+        Self::start(out, None)
+    }
+    /// Start writing a KML file to `out`, with a track called `name`.
+    pub fn with_name(out: W, name: &str) -> io::Result<Self> {
+        Self::start(out, Some(name))
+    }
+    fn start(mut out: W, name: Option<&str>) -> io::Result<Self> {
+        out.write_all(HEADER.as_bytes())?;
+        if let Some(name) = name {
+            writeln!(out, "  <name>{}</name>", escape(name))?;
+        }
+        out.write_all(SCHEMA.as_bytes())?;
+        writeln!(out, "  <Placemark>")?;
+        if let Some(name) = name {
+            writeln!(out, "   <name>{}</name>", escape(name))?;
+        }
+        writeln!(out, "   <gx:MultiTrack>")?;
+        writeln!(out, "    <gx:interpolate>0</gx:interpolate>")?;
+        out.flush()?;
+        Ok(KmlWriter {
+            out,
+            altitude_mode: AltitudeMode::ClampToGround,
+            max_points: 3600,
+            sky: SkySummary::default(),
+            track: vec![],
+            carried: false,
+            last_alt: None
+        })
+    }
+    /// How Google Earth should treat the altitudes of the tracks written from
+    /// now on. This is `ClampToGround` by default, which suits things on the
+    /// ground; use `Absolute` for things in the air.
+    pub fn set_altitude_mode(&mut self, mode: AltitudeMode) {
+        self.altitude_mode = mode;
+    }
+    /// Write tracks out once they have `points` points (3600 by default),
+    /// carrying on in a new track.
+    pub fn set_max_points(&mut self, points: usize) {
+        self.max_points = points.max(2);
+    }
+    /// Write a response. TPVs with a fix are added to the track, TPVs without
+    /// one end it, and SKYs are remembered for their satellite count and HDOP.
+    /// Anything else is ignored.
+    pub fn handle(&mut self, resp: &Response) -> io::Result<()> {
+        match *resp {
+            Response::Tpv(ref tpv) => self.write_tpv(tpv),
+            Response::Sky(ref sky) => {
+                self.set_sky(sky);
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+    /// Use this SKY for the satellite count and HDOP of the following points.
+    pub fn set_sky(&mut self, sky: &SkyResponse) {
+        self.sky = SkySummary::new(sky);
+    }
+    /// Add a TPV to the track, or end the track if it doesn't have a fix.
+    pub fn write_tpv(&mut self, tpv: &TpvResponse) -> io::Result<()> {
+        let pos = match tpv.position() {
+            Some(p) if tpv.mode().has_fix() => p,
+            _ => return self.end_segment()
+        };
+        let time = match tpv.time() {
+            Some(t) => t,
+            None => return Ok(())
+        };
+        let alt = tpv.details().alt_msl.or(pos.alt).or(self.last_alt);
+        self.last_alt = alt;
+        self.track.push(Point {
+            time,
+            lat: pos.lat,
+            lon: pos.lon,
+            alt,
+            speed: tpv.speed(),
+            course: tpv.track(),
+            sky: self.sky
+        });
+        if self.track.len() >= self.max_points {
+            self.write_track()?;
+            // (carrying on from the last point, so there's no gap)
+            let n = self.track.len();
+            self.track.drain(..n - 1);
+            self.carried = true;
+        }
+        Ok(())
+    }
+    /// Write out the current track.
+    fn write_track(&mut self) -> io::Result<()> {
+        // (altitudes before the first one known are the first one known)
+        let first_alt = self.track.iter().filter_map(|p| p.alt).next().unwrap_or(0.0);
+        let (o, track) = (&mut self.out, &self.track);
+        writeln!(o, "    <gx:Track>")?;
+        writeln!(o, "     <altitudeMode>{}</altitudeMode>", self.altitude_mode.as_str())?;
+        for p in track.iter() {
+            writeln!(o, "     <when>{}</when>", p.time.to_rfc3339_opts(SecondsFormat::AutoSi, true))?;
+        }
+        for p in track.iter() {
+            writeln!(o, "     <gx:coord>{} {} {}</gx:coord>", p.lon, p.lat, p.alt.unwrap_or(first_alt))?;
+        }
+        writeln!(o, "     <ExtendedData>")?;
+        writeln!(o, "      <SchemaData schemaUrl=\"#tpv\">")?;
+        let values: Vec<[Option<String>; 4]> = track.iter().map(Point::values).collect();
+        for (i, name) in FIELDS.iter().enumerate() {
+            writeln!(o, "       <gx:SimpleArrayData name=\"{}\">", name)?;
+            for v in values.iter() {
+                writeln!(o, "        <gx:value>{}</gx:value>", v[i].as_ref().map_or("", |v| v as &str))?;
+            }
+            writeln!(o, "       </gx:SimpleArrayData>")?;
+        }
+        writeln!(o, "      </SchemaData>")?;
+        writeln!(o, "     </ExtendedData>")?;
+        writeln!(o, "    </gx:Track>")?;
+        o.flush()
+    }
+    /// End the current track, writing it out. The next point will start a new
+    /// one.
+    pub fn end_segment(&mut self) -> io::Result<()> {
+        // (a point carried over on its own would be a one-point repeat)
+        if self.track.len() > 1 || (self.track.len() == 1 && !self.carried) {
+            self.write_track()?;
+        }
+        self.track.clear();
+        self.carried = false;
+        Ok(())
+    }
+    /// The writer the KML is going to.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+    /// Finish off the file, and return the writer it went to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_segment()?;
+        writeln!(self.out, "   </gx:MultiTrack>")?;
+        writeln!(self.out, "  </Placemark>")?;
+        writeln!(self.out, " </Document>")?;
+        writeln!(self.out, "</kml>")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+/// Write some responses to `out` as a KML file, returning the writer. TPVs
+/// with a fix make up the track, and SKYs add their satellite counts and
+/// HDOPs to the points after them. Anything else is ignored.
+pub fn write_kml<'a, W, I>(out: W, altitude_mode: AltitudeMode, responses: I) -> io::Result<W>
+    where W: Write, I: IntoIterator<Item = &'a Response> {
+    let mut kml = KmlWriter::new(out)?;
+    kml.set_altitude_mode(altitude_mode);
+    for resp in responses {
+        kml.handle(resp)?;
+    }
+    kml.finish()
+}
@@ -9,69 +9,174 @@ extern crate serde;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
 extern crate chrono;
-#[macro_use] extern crate error_chain;
 #[macro_use] extern crate log;
 
 use std::net::{ToSocketAddrs, TcpStream};
 use std::io::{BufRead, BufReader, Write};
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-pub mod errors {
-    //! Error handling, using error-chain.
-
-    error_chain! {
-        types {
-            Error, ErrorKind, ResultExt, GpsdResult;
-        }
-        foreign_links {
-            Io(::std::io::Error);
-            Serde(::serde_json::Error);
-        }
-        errors {
-            DeserFailed(s: String, e: ::serde_json::Error) {
-                display("failed to deserialize text '{}': {}", s, e)
-            }
-            GpsdFailed(s: String) {
-                display("gpsd connection closed")
-            }
-        }
-    }
-}
-pub use errors::GpsdResult;
+pub mod errors;
+pub use errors::{CommandError, Error, GpsdResult};
 pub mod types;
+pub mod raw;
+pub mod nmea;
+pub mod protocol;
+pub mod relay;
+pub mod multi;
+pub mod geo;
+pub mod coords;
+pub mod grid;
+pub mod geofence;
+pub mod track;
+pub mod kalman;
+pub mod survey;
+pub mod gpx;
+pub mod geojson;
+pub mod kml;
+pub mod csv;
 #[cfg(test)]
 pub mod tests;
 use types::*;
+use raw::RawFrame;
+use nmea::NmeaSentence;
+use protocol::ProtocolVersion;
+
+/// Work out why `line` failed to deserialize: either it's an object of a class
+/// we don't know about, or it's malformed.
+fn deser_error(line: String, e: serde_json::Error) -> Error {
+    let class = serde_json::from_str::<serde_json::Value>(&line).ok()
+        .and_then(|v| v.get("class").and_then(|c| c.as_str()).map(String::from));
+    match class {
+        Some(class) if !KNOWN_CLASSES.contains(&(&class as &str)) => {
+            Error::UnknownClass { class, line }
+        },
+        _ => Error::MalformedJson { line, source: e }
+    }
+}
+/// Classes of object that `Response` knows how to deserialize.
+const KNOWN_CLASSES: &[&str] = &[
+    "TPV", "SKY", "POLL", "DEVICE", "DEVICES", "WATCH", "VERSION", "ERROR"
+];
+/// How long commands wait for gpsd to reply, unless told otherwise with
+/// `GpsdConnection::set_command_timeout`.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many responses a command will queue up while waiting for its reply,
+/// before giving up.
+const MAX_PENDING: usize = 1024;
 
 /// A connection to gpsd.
 pub struct GpsdConnection {
     raw_data: bool,
-    inner: BufReader<TcpStream>
+    nmea_data: bool,
+    inner: BufReader<TcpStream>,
+    /// Responses read while waiting for the reply to a command, which haven't
+    /// been returned from `get_response` yet.
+    pending: VecDeque<Response>,
+    /// Protocol version gpsd reported, if we've seen a VERSION object yet.
+    proto: Option<ProtocolVersion>,
+    /// How long commands wait for their replies.
+    command_timeout: Duration
 }
 impl GpsdConnection {
     /// Make a new connection to a given address.
     pub fn new<A: ToSocketAddrs>(addr: A) -> GpsdResult<Self> {
         let stream = TcpStream::connect(addr)?;
         let inner = BufReader::new(stream);
-        Ok(Self { inner, raw_data: false })
+        Ok(Self {
+            inner, raw_data: false, nmea_data: false, pending: VecDeque::new(), proto: None,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT
+        })
+    }
+    /// Make a new connection to a given address, and check that gpsd speaks a
+    /// supported protocol version (see `handshake`).
+    pub fn new_checked<A: ToSocketAddrs>(addr: A) -> GpsdResult<Self> {
+        let mut ret = Self::new(addr)?;
+        ret.handshake()?;
+        Ok(ret)
+    }
+    /// Read the VERSION object gpsd sends when a client connects, and check
+    /// that its protocol version is supported, returning
+    /// `Error::VersionMismatch` if not.
+    ///
+    /// This should be called straight after connecting. The VERSION object is
+    /// consumed, and won't be returned by `get_response`.
+    ///
+    /// You don't have to call this: the protocol version is picked up from any
+    /// VERSION object that passes through `get_response`. However, without it,
+    /// nothing checks that the version is supported.
+    pub fn handshake(&mut self) -> GpsdResult<ProtocolVersion> {
+        loop {
+            match self.read_response()? {
+                Response::Version { proto_major, proto_minor, .. } => {
+                    let proto = ProtocolVersion::new(proto_major, proto_minor);
+                    if !proto.is_supported() {
+                        return Err(Error::VersionMismatch { major: proto_major, minor: proto_minor });
+                    }
+                    return Ok(proto);
+                },
+                resp => self.pending.push_back(resp)
+            }
+        }
+    }
+    /// The protocol version gpsd reported, if it's done so yet.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.proto
+    }
+    /// Send a command to gpsd, and wait for a reply of class `reply`.
+    ///
+    /// The reply, and anything else received in the meantime, is queued up to be
+    /// returned by `get_response`. If gpsd replies with an ERROR instead, it's
+    /// returned as an `Error::Command`. If the reply doesn't come within the
+    /// command timeout, or too much else comes first, this gives up with
+    /// `Error::Timeout`; whatever was received is still queued.
+    fn command(&mut self, request: &str, reply: &str) -> GpsdResult<()> {
+        self.inner.get_mut().write_all(request.as_bytes())?;
+        let deadline = Instant::now() + self.command_timeout;
+        loop {
+            if self.pending.len() >= MAX_PENDING {
+                return Err(Error::Timeout);
+            }
+            match self.read_response_by(deadline)? {
+                Response::Error { message, .. } => {
+                    return Err(Error::Command {
+                        request: request.trim_end().into(),
+                        error: CommandError::from_message(message)
+                    });
+                },
+                resp => {
+                    let done = resp.class() == Some(reply);
+                    self.pending.push_back(resp);
+                    if done {
+                        return Ok(());
+                    }
+                }
+            }
+        }
     }
     /// Enable or disable watcher mode.
-    fn _watch(&mut self, watch: bool, json: bool, raw: u8) -> GpsdResult<()> {
-        let stream = self.inner.get_mut();
+    fn _watch(&mut self, watch: bool, json: bool, nmea: bool, raw: u8) -> GpsdResult<()> {
         let watch_data = json!({
             "class": "WATCH",
             "enable": watch,
             "json": json,
+            "nmea": nmea,
             "raw": raw,
         });
         self.raw_data = raw > 0;
-        let msg = format!("?WATCH={}\n", watch_data.to_string());
-        stream.write_all(msg.as_bytes())?;
-        Ok(())
+        self.nmea_data = nmea;
+        let msg = format!("?WATCH={}\n", watch_data);
+        self.command(&msg, "WATCH")
     }
     /// Enable or disable watcher mode.
+    ///
+    /// This, and the other commands, wait for gpsd to acknowledge the request,
+    /// and return an `Error::Command` if gpsd rejects it. The acknowledgement
+    /// itself is returned from `get_response` as usual. They block for up to
+    /// the command timeout (see `set_command_timeout`) waiting for it, and
+    /// return `Error::Timeout` if it doesn't come.
     pub fn watch(&mut self, watch: bool) -> GpsdResult<()> {
-        self._watch(watch, true, 0)
+        self._watch(watch, true, false, 0)
     }
     /// Enable RAW mode. In RAW mode, gpsd sends raw data from the GPS device, depending on the value of `raw`:
     ///
@@ -79,28 +184,65 @@ impl GpsdConnection {
     /// AIVDM data stream from whatever device is attached. Binary GPS packets are hex-dumped.
     /// RTCM2 and RTCM3 packets are not dumped in raw mode. When this attribute is set to 2 for a channel that
     /// processes binary data, gpsd reports the received data verbatim without hex-dumping.
+    ///
+    /// Raw data is returned from `get_response` as a `Response::Raw`, classified
+    /// into a `RawFrame`; hex dumps are decoded back into bytes.
+    ///
+    /// Blocks until gpsd acknowledges the request, for up to the command
+    /// timeout.
     pub fn watch_raw(&mut self, watch: bool, json: bool, raw: u8) -> GpsdResult<()> {
-        self._watch(watch, json, raw)
+        self._watch(watch, json, false, raw)
+    }
+    /// Enable pseudo-NMEA mode. In this mode, gpsd converts the data from the
+    /// GPS device (even binary data) into NMEA sentences. If `json` is true,
+    /// JSON reports are interleaved with the sentences.
+    ///
+    /// Sentences are returned from `get_response` as a `Response::Nmea`. AIS
+    /// sentences (`!AIVDM` and `!AIVDO`), which gpsd passes through as they
+    /// are, are returned as a `Response::Raw`.
+    ///
+    /// Blocks until gpsd acknowledges the request, for up to the command
+    /// timeout.
+    pub fn watch_nmea(&mut self, watch: bool, json: bool) -> GpsdResult<()> {
+        self._watch(watch, json, true, 0)
     }
     /// The POLL command requests data from the last-seen fixes on all active
     /// GPS devices. Devices must previously have been activated by ?WATCH to be
     /// pollable.
+    ///
+    /// Blocks until the POLL reply arrives, for up to the command timeout.
     pub fn poll(&mut self) -> GpsdResult<()> {
-        let stream = self.inner.get_mut();
-        stream.write_all("?POLL;\n".as_bytes())?;
-        Ok(())
+        self.command("?POLL;\n", "POLL")
     }
     /// Ask for the server's version (triggers a Response::Version).
+    ///
+    /// Blocks until the reply arrives, for up to the command timeout.
     pub fn version(&mut self) -> GpsdResult<()> {
-        let stream = self.inner.get_mut();
-        stream.write_all("?VERSION;\n".as_bytes())?;
-        Ok(())
+        self.command("?VERSION;\n", "VERSION")
     }
     /// Ask for the server's devices (triggers a Response::Devices)
+    ///
+    /// Blocks until the reply arrives, for up to the command timeout.
     pub fn devices(&mut self) -> GpsdResult<()> {
-        let stream = self.inner.get_mut();
-        stream.write_all("?DEVICES;\n".as_bytes())?;
-        Ok(())
+        self.command("?DEVICES;\n", "DEVICES")
+    }
+    /// Change the settings of a device (triggers a Response::Device with the
+    /// device's new settings).
+    ///
+    /// gpsd will refuse this if the device isn't writable, or if other clients
+    /// are using it; that's returned as an `Error::Command`. Blocks until the
+    /// reply arrives, for up to the command timeout.
+    pub fn configure_device(&mut self, config: &DeviceConfig) -> GpsdResult<()> {
+        let config = serde_json::to_string(config)
+            .expect("DeviceConfig is always serializable");
+        let msg = format!("?DEVICE={}\n", config);
+        self.command(&msg, "DEVICE")
+    }
+    /// Sets how long commands (such as `watch` and `poll`) wait for gpsd to
+    /// reply before returning `Error::Timeout` (`DEFAULT_COMMAND_TIMEOUT` by
+    /// default).
+    pub fn set_command_timeout(&mut self, dur: Duration) {
+        self.command_timeout = dur;
     }
     /// Sets the read timeout for `get_response`.
     ///
@@ -113,30 +255,78 @@ impl GpsdConnection {
     ///
     /// Ideally, you run this in a loop somewhere to process messages.
     pub fn get_response(&mut self) -> GpsdResult<Response> {
+        match self.pending.pop_front() {
+            Some(resp) => Ok(resp),
+            None => self.read_response()
+        }
+    }
+    /// Read a response from the socket, like `read_response`, but giving up
+    /// with `Error::Timeout` at `deadline` (or sooner, if the read timeout is
+    /// shorter).
+    fn read_response_by(&mut self, deadline: Instant) -> GpsdResult<Response> {
+        let timeout = self.inner.get_ref().read_timeout()?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(Error::Timeout);
+        }
+        let wait = match timeout {
+            Some(t) if t < remaining => t,
+            _ => remaining
+        };
+        self.inner.get_ref().set_read_timeout(Some(wait))?;
+        let ret = self.read_response();
+        self.inner.get_ref().set_read_timeout(timeout)?;
+        ret
+    }
+    /// Read a response from the socket, ignoring the pending queue.
+    fn read_response(&mut self) -> GpsdResult<Response> {
         loop {
             let mut buf = String::new();
             if self.inner.read_line(&mut buf)? == 0 {
-                bail!(errors::ErrorKind::GpsdFailed(String::from("Gpsd Connection Closed")));
+                return Err(Error::ConnectionClosed);
             }
 
-            if buf == "" {
+            if buf.trim().is_empty() {
                 debug!("empty line received from GPSD");
                 continue;
             }
             debug!("raw GPSD data: {}", buf);
-            let data = serde_json::from_str(&buf);
+            let data = self.deserialize(&buf);
             debug!("serde output: {:?}", data);
             match data {
                 Err(e) => {
-                    if self.raw_data {
-                        return Ok(Response::Raw(buf))
-                    } else {
+                    if self.nmea_data && buf.starts_with('$') {
+                        match NmeaSentence::parse(&buf) {
+                            Some(s) => return Ok(Response::Nmea(s)),
+                            None => return Err(Error::MalformedNmea(buf))
+                        }
+                    }
+                    // (AIS data is passed through as it is, even in NMEA mode)
+                    else if (self.nmea_data && buf.starts_with('!')) || self.raw_data {
+                        return Ok(Response::Raw(RawFrame::classify(&buf)))
+                    }
+                    else {
                         debug!("deserializing response failed: {:?}", e);
-                        bail!(errors::ErrorKind::DeserFailed(buf, e));
+                        return Err(deser_error(buf, e));
                     }
                 },
                 Ok(x) => return Ok(x)
             }
         }
     }
+    /// Deserialize a line of JSON, taking the protocol version into account.
+    fn deserialize(&mut self, line: &str) -> Result<Response, serde_json::Error> {
+        let resp = match self.proto {
+            Some(proto) if proto.has_numeric_timestamps() => {
+                let mut value = serde_json::from_str(line)?;
+                protocol::fix_numeric_timestamps(&mut value);
+                serde_json::from_value(value)?
+            },
+            _ => serde_json::from_str(line)?
+        };
+        if let Response::Version { proto_major, proto_minor, .. } = resp {
+            self.proto = Some(ProtocolVersion::new(proto_major, proto_minor));
+        }
+        Ok(resp)
+    }
 }
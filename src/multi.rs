@@ -0,0 +1,280 @@
+//! Merging the data from several gpsd instances into one stream.
+//!
+//! `MultiGpsd` reads from any number of `GpsdConnection`s at once (each in its
+//! own thread), and hands back their responses in the order they arrive,
+//! tagged with the source they came from. It also keeps track of the health of
+//! each source, so that you can tell when (for example) the main receiver has
+//! gone quiet and it's time to start believing the backup.
+//!
+//! Different hosts can have devices with the same path (everything is
+//! `/dev/ttyUSB0`, after all). To tell them apart, once more than one source
+//! has reported a device path, that path is rewritten to qualify it with its
+//! source: the source's `remote` URL if gpsd has reported one (in a WATCH,
+//! DEVICES or VERSION object), and its name otherwise. So two
+//! `/dev/ttyUSB0`s might come out as `gpsd://main:2947/dev/ttyUSB0` and
+//! `backup:/dev/ttyUSB0`, while a `/dev/ttyACM0` only one source has stays as
+//! it is. `set_qualify_paths` can qualify every path instead, or none.
+//!
+//! A path is only known to collide once the second source reports it, so the
+//! first source's responses before then are unqualified. gpsd sends DEVICES
+//! and WATCH before any data, so collisions (and `remote` URLs) are usually
+//! known in time; if a `remote` URL turns up after a source's paths have been
+//! qualified with its name, it's used from then on.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use types::*;
+use {Error, GpsdConnection, GpsdResult};
+
+/// Identifies one of the sources of a `MultiGpsd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceId(pub usize);
+
+/// A response, and the source it came from.
+#[derive(Debug, Clone)]
+pub struct SourcedResponse {
+    /// Where this response came from.
+    pub source: SourceId,
+    /// The response itself. Device paths may have been qualified with the
+    /// source; see `MultiGpsd::set_qualify_paths`.
+    pub response: Response
+}
+/// How a source of a `MultiGpsd` is doing.
+#[derive(Debug, Clone, Default)]
+pub struct SourceHealth {
+    /// Is the connection still up?
+    pub connected: bool,
+    /// Number of responses received.
+    pub responses: u64,
+    /// Number of errors (such as malformed lines) encountered.
+    pub errors: u64,
+    /// The last error encountered, if any.
+    pub last_error: Option<String>,
+    /// When the last response was received.
+    pub last_response: Option<Instant>,
+    /// When the last TPV with a 2D or 3D fix was received.
+    pub last_fix: Option<Instant>
+}
+impl SourceHealth {
+    /// Is the source connected, and has it reported a fix within `max_age`?
+    pub fn is_healthy(&self, max_age: Duration) -> bool {
+        self.connected && self.last_fix.map(|t| t.elapsed() <= max_age).unwrap_or(false)
+    }
+}
+/// Which device paths a `MultiGpsd` qualifies with their source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualifyPaths {
+    /// Leave every path as gpsd reported it.
+    Never,
+    /// Qualify paths that more than one source has reported (the default).
+    #[default]
+    Collisions,
+    /// Qualify every path.
+    Always
+}
+struct Source {
+    name: String,
+    /// The `remote` URL this source has reported, if any.
+    remote: Option<String>,
+    /// Every device path this source has reported, as gpsd reported it.
+    paths: HashSet<String>,
+    health: Arc<Mutex<SourceHealth>>
+}
+impl Source {
+    /// What this source's device paths are qualified with.
+    fn qualifier(&self) -> String {
+        match self.remote {
+            Some(ref remote) => remote.trim_end_matches('/').to_owned(),
+            None => format!("{}:", self.name)
+        }
+    }
+}
+/// Several gpsd connections, merged into one stream of responses.
+pub struct MultiGpsd {
+    sources: Vec<Source>,
+    /// Number of sources whose threads are still running.
+    live: usize,
+    tx: Sender<(SourceId, GpsdResult<Response>)>,
+    rx: Receiver<(SourceId, GpsdResult<Response>)>,
+    /// Which device paths to qualify with their source.
+    qualify: QualifyPaths
+}
+impl Default for MultiGpsd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MultiGpsd {
+    /// Make a new, empty, set of sources.
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        MultiGpsd { sources: vec![], live: 0, tx, rx, qualify: QualifyPaths::default() }
+    }
+    /// Which device paths to qualify with the source they came from: by
+    /// default, only those that more than one source has reported. Set this
+    /// before receiving anything, so that paths are qualified from the start.
+    pub fn set_qualify_paths(&mut self, qualify: QualifyPaths) {
+        self.qualify = qualify;
+    }
+    /// Add a connection as a source, with a name to tell it apart from the
+    /// others. The connection should already be watching (see
+    /// `GpsdConnection::watch`).
+    ///
+    /// If the connection has a read timeout, timeouts are ignored, so there's
+    /// no need to set one.
+    pub fn add<N: Into<String>>(&mut self, name: N, mut conn: GpsdConnection) -> SourceId {
+        let id = SourceId(self.sources.len());
+        let health = Arc::new(Mutex::new(SourceHealth { connected: true, ..Default::default() }));
+        self.sources.push(Source { name: name.into(), remote: None, paths: HashSet::new(), health: health.clone() });
+        self.live += 1;
+        let tx = self.tx.clone();
+        thread::spawn(move || loop {
+            match conn.get_response() {
+                Ok(resp) => {
+                    {
+                        let mut health = lock(&health);
+                        let now = Instant::now();
+                        health.responses += 1;
+                        health.last_response = Some(now);
+                        if let Response::Tpv(ref tpv) = resp {
+                            if tpv.mode().has_fix() {
+                                health.last_fix = Some(now);
+                            }
+                        }
+                    }
+                    if tx.send((id, Ok(resp))).is_err() {
+                        return;
+                    }
+                },
+                Err(Error::Timeout) => {},
+                Err(e) => {
+                    let fatal = matches!(e, Error::ConnectionClosed | Error::Io(..));
+                    {
+                        let mut health = lock(&health);
+                        health.errors += 1;
+                        health.last_error = Some(e.to_string());
+                        health.connected = !fatal;
+                    }
+                    if fatal {
+                        let _ = tx.send((id, Err(e)));
+                        return;
+                    }
+                    warn!("error from gpsd source {}: {}", id.0, e);
+                }
+            }
+        });
+        id
+    }
+    /// The name a source was added with.
+    pub fn name(&self, id: SourceId) -> &str {
+        &self.sources[id.0].name
+    }
+    /// How a source is doing.
+    pub fn health(&self, id: SourceId) -> SourceHealth {
+        lock(&self.sources[id.0].health).clone()
+    }
+    /// All of the sources, in the order they were added.
+    pub fn sources(&self) -> impl Iterator<Item = SourceId> {
+        (0..self.sources.len()).map(SourceId)
+    }
+    /// Wait for the next response from any source.
+    ///
+    /// Errors from individual sources are recorded in their `SourceHealth`,
+    /// rather than returned. This returns `Error::ConnectionClosed` once every
+    /// source has disconnected.
+    pub fn recv(&mut self) -> GpsdResult<SourcedResponse> {
+        loop {
+            if self.live == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            // can't fail, since we hold a sender ourselves
+            let msg = self.rx.recv().expect("MultiGpsd channel closed");
+            if let Some(ret) = self.handle(msg) {
+                return Ok(ret);
+            }
+        }
+    }
+    /// Like `recv`, but gives up with `Error::Timeout` after `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> GpsdResult<SourcedResponse> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.live == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(remaining) {
+                Ok(msg) => if let Some(ret) = self.handle(msg) {
+                    return Ok(ret);
+                },
+                Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("MultiGpsd channel closed")
+            }
+        }
+    }
+    fn handle(&mut self, (source, msg): (SourceId, GpsdResult<Response>)) -> Option<SourcedResponse> {
+        match msg {
+            Ok(mut response) => {
+                self.note_remote(source, &response);
+                self.qualify_paths(source, &mut response);
+                Some(SourcedResponse { source, response })
+            },
+            Err(e) => {
+                warn!("gpsd source {} disconnected: {}", self.sources[source.0].name, e);
+                self.live -= 1;
+                None
+            }
+        }
+    }
+    /// Note any `remote` URL in a response.
+    fn note_remote(&mut self, source: SourceId, resp: &Response) {
+        match *resp {
+            Response::Watch(WatchObject { remote: Some(ref r), .. }) |
+            Response::Devices { remote: Some(ref r), .. } |
+            Response::Version { remote: Some(ref r), .. } => {
+                self.sources[source.0].remote = Some(r.clone());
+            },
+            _ => {}
+        }
+    }
+    /// Note the device paths in a response, and qualify those that need it
+    /// with their source.
+    fn qualify_paths(&mut self, source: SourceId, resp: &mut Response) {
+        let mut paths: Vec<&mut Option<String>> = vec![];
+        match *resp {
+            Response::Tpv(ref mut tpv) => paths.push(tpv.device_mut()),
+            Response::Sky(ref mut sky) => paths.push(&mut sky.device),
+            Response::Device(ref mut dev) => paths.push(dev.path_mut()),
+            Response::Devices { ref mut devices, .. } => {
+                paths.extend(devices.iter_mut().map(|d| d.path_mut()));
+            },
+            Response::Poll { ref mut tpv, ref mut sky, .. } => {
+                paths.extend(tpv.iter_mut().map(|t| t.device_mut()));
+                paths.extend(sky.iter_mut().map(|s| &mut s.device));
+            },
+            Response::Watch(ref mut watch) => paths.push(&mut watch.device),
+            _ => {}
+        }
+        for path in paths.iter().filter_map(|p| p.as_ref()) {
+            if !self.sources[source.0].paths.contains(path) {
+                self.sources[source.0].paths.insert(path.clone());
+            }
+        }
+        let qualifier = self.sources[source.0].qualifier();
+        for path in paths.into_iter().flatten() {
+            let qualify = match self.qualify {
+                QualifyPaths::Never => false,
+                QualifyPaths::Collisions => self.sources.iter().enumerate()
+                    .any(|(i, src)| i != source.0 && src.paths.contains(path)),
+                QualifyPaths::Always => true
+            };
+            if qualify {
+                *path = format!("{}{}", qualifier, path);
+            }
+        }
+    }
+}
+fn lock(health: &Mutex<SourceHealth>) -> MutexGuard<'_, SourceHealth> {
+    health.lock().expect("source health poisoned")
+}
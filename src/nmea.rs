@@ -0,0 +1,93 @@
+//! Parsing of NMEA 0183 sentences, as sent by gpsd in pseudo-NMEA mode.
+//!
+//! This only deals with the framing of a sentence (talker, type, fields and
+//! checksum); it doesn't attempt to interpret the fields of any particular
+//! sentence type.
+use std::fmt;
+
+/// A single NMEA 0183 sentence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NmeaSentence {
+    /// Talker ID, e.g. `GP` or `GN`. For proprietary sentences, this is `P`.
+    pub talker: String,
+    /// Sentence type, e.g. `GGA`. For proprietary sentences, this is
+    /// everything in the address field after the `P`, e.g. `SRF103`.
+    pub kind: String,
+    /// The comma-separated data fields following the address field.
+    pub fields: Vec<String>,
+    /// The checksum transmitted with the sentence, if any. If this is present,
+    /// it has already been validated.
+    pub checksum: Option<u8>,
+    /// Whether this sentence used the `!` start delimiter (encapsulated data,
+    /// like AIVDM) rather than `$`.
+    pub encapsulated: bool
+}
+impl NmeaSentence {
+    /// Parse a sentence, such as `$GPGGA,...*4A`.
+    ///
+    /// Any trailing line terminator is ignored. Returns `None` if the line
+    /// isn't a sentence, or if its checksum doesn't match.
+    pub fn parse(line: &str) -> Option<NmeaSentence> {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        let encapsulated = match line.chars().next() {
+            Some('$') => false,
+            Some('!') => true,
+            _ => return None
+        };
+        let body = &line[1..];
+        let (body, checksum) = match body.rfind('*') {
+            Some(idx) => {
+                let sum = u8::from_str_radix(&body[idx + 1..], 16).ok()?;
+                if checksum(&body[..idx]) != sum {
+                    return None;
+                }
+                (&body[..idx], Some(sum))
+            },
+            None => (body, None)
+        };
+        let mut parts = body.split(',');
+        let address = parts.next()?;
+        if address.is_empty() || !address.is_ascii() {
+            return None;
+        }
+        let (talker, kind) = if address.starts_with('P') {
+            address.split_at(1)
+        }
+        else if address.len() >= 3 {
+            address.split_at(2)
+        }
+        else {
+            return None;
+        };
+        Some(NmeaSentence {
+            talker: talker.into(),
+            kind: kind.into(),
+            fields: parts.map(|x| x.into()).collect(),
+            checksum,
+            encapsulated
+        })
+    }
+    /// Get a field by index, or `None` if it's missing or empty.
+    pub fn field(&self, idx: usize) -> Option<&str> {
+        self.fields.get(idx)
+            .map(|x| x as &str)
+            .filter(|x| !x.is_empty())
+    }
+}
+impl fmt::Display for NmeaSentence {
+    /// Formats the sentence as it would be sent over the wire, without a line
+    /// terminator. A checksum is always included.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut body = format!("{}{}", self.talker, self.kind);
+        for field in self.fields.iter() {
+            body.push(',');
+            body.push_str(field);
+        }
+        let start = if self.encapsulated { '!' } else { '$' };
+        write!(f, "{}{}*{:02X}", start, body, checksum(&body))
+    }
+}
+/// Compute the NMEA checksum (XOR of all bytes) of a sentence body.
+fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |acc, b| acc ^ b)
+}
@@ -0,0 +1,114 @@
+//! Classification of the data gpsd sends in raw mode.
+//!
+//! When raw mode 1 is enabled (see `GpsdConnection::watch_raw`), gpsd passes
+//! NMEA and AIVDM sentences through untouched, but hex-dumps binary GPS
+//! packets. The `RawFrame` type tells these apart, and decodes the hex dumps
+//! back into the bytes the receiver actually sent.
+use std::fmt;
+
+/// A single line of raw data received from gpsd.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RawFrame {
+    /// An NMEA 0183 sentence, such as `$GPGGA,...*4A`.
+    Nmea(String),
+    /// An AIVDM or AIVDO sentence carrying AIS data, such as `!AIVDM,...`.
+    Aivdm(String),
+    /// A binary packet, decoded from gpsd's hex dump.
+    Binary(Vec<u8>),
+    /// A line that doesn't look like any of the above.
+    Other(String)
+}
+/// A binary GPS protocol, as identified by a packet's sync bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryProtocol {
+    /// u-blox UBX (sync `B5 62`).
+    Ubx,
+    /// SiRF binary (sync `A0 A2`).
+    Sirf,
+    /// SkyTraq binary (sync `A0 A1`).
+    Skytraq,
+    /// RTCM 3 (preamble `D3`).
+    Rtcm3,
+    /// Rockwell Zodiac binary (sync `FF 81`).
+    Zodiac
+}
+impl RawFrame {
+    /// Classify a line of raw data, as read from gpsd.
+    ///
+    /// Any trailing line terminator is stripped first.
+    pub fn classify(line: &str) -> RawFrame {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.starts_with('!') && is_aivdm(line) {
+            RawFrame::Aivdm(line.into())
+        }
+        else if line.starts_with('$') || line.starts_with('!') {
+            RawFrame::Nmea(line.into())
+        }
+        else if let Some(bytes) = decode_hex(line) {
+            RawFrame::Binary(bytes)
+        }
+        else {
+            RawFrame::Other(line.into())
+        }
+    }
+    /// If this is a binary packet, attempt to work out which protocol it
+    /// belongs to, so it can be handed off to the right decoder.
+    pub fn protocol(&self) -> Option<BinaryProtocol> {
+        match *self {
+            RawFrame::Binary(ref bytes) => BinaryProtocol::identify(bytes),
+            _ => None
+        }
+    }
+}
+impl fmt::Display for RawFrame {
+    /// Formats the frame as gpsd sent it, without a line terminator. Binary
+    /// packets are hex-dumped again.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RawFrame::Nmea(ref s) | RawFrame::Aivdm(ref s) | RawFrame::Other(ref s) => f.write_str(s),
+            RawFrame::Binary(ref bytes) => f.write_str(&encode_hex(bytes))
+        }
+    }
+}
+impl BinaryProtocol {
+    /// Identify the protocol of a binary packet from its leading sync bytes.
+    pub fn identify(packet: &[u8]) -> Option<BinaryProtocol> {
+        match packet {
+            [0xb5, 0x62, ..] => Some(BinaryProtocol::Ubx),
+            [0xa0, 0xa2, ..] => Some(BinaryProtocol::Sirf),
+            [0xa0, 0xa1, ..] => Some(BinaryProtocol::Skytraq),
+            [0xff, 0x81, ..] => Some(BinaryProtocol::Zodiac),
+            [0xd3, ..] => Some(BinaryProtocol::Rtcm3),
+            _ => None
+        }
+    }
+}
+/// Is this an AIS sentence (`!xxVDM` or `!xxVDO`)?
+fn is_aivdm(line: &str) -> bool {
+    matches!(line.get(3..6), Some("VDM") | Some("VDO"))
+}
+/// Decode a hex dump, as produced by gpsd, back into bytes.
+///
+/// Returns `None` if the string is empty, has an odd length, or contains
+/// anything other than hexadecimal digits.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    let digits = hex.as_bytes();
+    let mut ret = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let hi = (pair[0] as char).to_digit(16)?;
+        let lo = (pair[1] as char).to_digit(16)?;
+        ret.push((hi << 4 | lo) as u8);
+    }
+    Some(ret)
+}
+/// Hex-dump bytes the way gpsd does (lowercase, no separators).
+pub fn encode_hex(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        ret.push_str(&format!("{:02x}", b));
+    }
+    ret
+}
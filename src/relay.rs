@@ -0,0 +1,418 @@
+//! A relay that serves the data from one gpsd to many clients.
+//!
+//! `Relay` takes an upstream `GpsdConnection` and listens for clients, speaking
+//! enough of gpsd's own server protocol that they can't tell the difference:
+//! `?VERSION`, `?DEVICES`, `?WATCH`, `?POLL` and `?DEVICE` are answered from
+//! what the relay has seen upstream, and reports are passed on to each client
+//! according to its own watch settings.
+//!
+//! The relay is read-only: attempts to reconfigure devices with `?DEVICE=` are
+//! refused with an ERROR, rather than being passed upstream.
+//!
+//! Each client has a bounded queue of lines waiting to be written to it. If a
+//! client falls so far behind that its queue fills up, further reports are
+//! dropped for that client until it catches up, so that one slow client can't
+//! hold up the others (or the upstream connection).
+//!
+//! Raw and pseudo-NMEA frames don't say which device they came from. A client
+//! watching one device gets them only if upstream has just that one device;
+//! with more than one, there's no telling which frames are whose, so they're
+//! withheld.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use chrono::Utc;
+use serde_json::{self, Map, Value};
+use types::*;
+use {Error, GpsdConnection, GpsdResult};
+
+/// Default number of lines queued up for each client.
+pub const DEFAULT_CLIENT_BUFFER: usize = 256;
+
+/// A gpsd-compatible server, relaying the data from an upstream gpsd.
+pub struct Relay {
+    upstream: GpsdConnection,
+    listener: TcpListener,
+    client_buffer: usize,
+    nmea: bool,
+    state: Arc<Mutex<State>>,
+    /// The thread accepting clients, once it's been started.
+    acceptor: Option<JoinHandle<()>>
+}
+/// Stops a running `Relay`, from another thread.
+pub struct RelayStopper {
+    state: Arc<Mutex<State>>,
+    upstream: TcpStream
+}
+impl RelayStopper {
+    /// Stop the relay: `Relay::run` disconnects every client and returns
+    /// `Ok(())`.
+    pub fn stop(&self) {
+        lock(&self.state).stopping = true;
+        let _ = self.upstream.shutdown(Shutdown::Both);
+    }
+}
+/// What the relay knows about upstream, and its clients.
+#[derive(Default)]
+struct State {
+    /// The VERSION object upstream sent, which is passed on to clients.
+    version: Option<Response>,
+    devices: Vec<DeviceObject>,
+    /// The last TPV and SKY from each device, for `?POLL`.
+    tpv: Vec<TpvResponse>,
+    sky: Vec<SkyResponse>,
+    clients: Vec<Client>,
+    next_id: u64,
+    /// Set once the upstream connection has failed, or the relay's stopped.
+    closed: bool,
+    /// Set by `RelayStopper::stop`.
+    stopping: bool
+}
+struct Client {
+    id: u64,
+    watch: WatchObject,
+    tx: SyncSender<Arc<String>>,
+    stream: TcpStream,
+    /// Lines dropped because the client's queue was full.
+    dropped: u64
+}
+impl Relay {
+    /// Make a relay for `upstream`, listening for clients on `addr`.
+    ///
+    /// Nothing happens until `run` is called.
+    pub fn bind<A: ToSocketAddrs>(upstream: GpsdConnection, addr: A) -> GpsdResult<Self> {
+        Ok(Relay {
+            upstream,
+            listener: TcpListener::bind(addr)?,
+            client_buffer: DEFAULT_CLIENT_BUFFER,
+            nmea: false,
+            state: Default::default(),
+            acceptor: None
+        })
+    }
+    /// Get something that can stop the relay once it's running.
+    pub fn stopper(&self) -> GpsdResult<RelayStopper> {
+        Ok(RelayStopper { state: self.state.clone(), upstream: self.upstream.inner.get_ref().try_clone()? })
+    }
+    /// The address the relay is listening on.
+    pub fn local_addr(&self) -> GpsdResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+    /// Set how many lines can be queued up for each client before reports are
+    /// dropped for it (default `DEFAULT_CLIENT_BUFFER`, and at least 1).
+    pub fn set_client_buffer(&mut self, lines: usize) {
+        self.client_buffer = lines.max(1);
+    }
+    /// Also pass on pseudo-NMEA sentences, to clients that ask for them. This
+    /// puts the upstream connection into pseudo-NMEA mode.
+    pub fn set_nmea(&mut self, nmea: bool) {
+        self.nmea = nmea;
+    }
+    /// Run the relay, accepting clients in a background thread and passing on
+    /// data from upstream in this one.
+    ///
+    /// This only returns if something goes wrong with the upstream connection,
+    /// or the relay is stopped with a `RelayStopper`. Either way, all clients
+    /// are disconnected, and the thread accepting them has finished.
+    pub fn run(mut self) -> GpsdResult<()> {
+        let ret = self.relay();
+        let stopping = {
+            let mut state = lock(&self.state);
+            state.closed = true;
+            for client in state.clients.drain(..) {
+                let _ = client.stream.shutdown(Shutdown::Both);
+            }
+            state.stopping
+        };
+        if let Some(acceptor) = self.acceptor.take() {
+            // wake it up, so it sees it's time to go
+            let mut addr = self.listener.local_addr()?;
+            match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+                _ => {}
+            }
+            let _ = TcpStream::connect(addr);
+            let _ = acceptor.join();
+        }
+        if stopping { Ok(()) } else { ret }
+    }
+    fn relay(&mut self) -> GpsdResult<()> {
+        self.upstream.version()?;
+        self.upstream.devices()?;
+        if self.nmea {
+            self.upstream.watch_nmea(true, true)?;
+        }
+        else {
+            self.upstream.watch(true)?;
+        }
+        // Process the replies to those before letting anyone in, so there's a
+        // VERSION to greet them with.
+        loop {
+            let resp = self.upstream.get_response()?;
+            let done = resp.class() == Some("WATCH");
+            self.handle_upstream(resp);
+            if done {
+                break;
+            }
+        }
+        let listener = self.listener.try_clone()?;
+        let state = self.state.clone();
+        let client_buffer = self.client_buffer;
+        self.acceptor = Some(thread::spawn(move || accept(listener, state, client_buffer)));
+        loop {
+            match self.upstream.get_response() {
+                Ok(resp) => self.handle_upstream(resp),
+                Err(Error::Timeout) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+    /// Record a response from upstream, and pass it on to clients if it's
+    /// data (rather than a reply to one of our commands).
+    fn handle_upstream(&mut self, resp: Response) {
+        let mut state = lock(&self.state);
+        match resp {
+            Response::Version { .. } => state.version = Some(resp),
+            Response::Devices { devices, .. } => state.devices = devices,
+            Response::Watch(..) | Response::Poll { .. } | Response::Error { .. } => {},
+            Response::Device(ref dev) => {
+                let path = dev.path();
+                state.devices.retain(|d| d.path() != path);
+                state.devices.push(dev.clone());
+                broadcast(&mut state, &resp);
+            },
+            Response::Tpv(ref tpv) => {
+                state.tpv.retain(|t| t.device() != tpv.device());
+                state.tpv.push(tpv.clone());
+                broadcast(&mut state, &resp);
+            },
+            Response::Sky(ref sky) => {
+                state.sky.retain(|s| s.device != sky.device);
+                state.sky.push(sky.clone());
+                broadcast(&mut state, &resp);
+            },
+            Response::Raw(..) | Response::Nmea(..) => broadcast(&mut state, &resp)
+        }
+    }
+}
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().expect("relay state poisoned")
+}
+/// Would a client with these watch settings like to see `resp`? `sole_device`
+/// is the path of upstream's only device, if it has just the one.
+fn wants(watch: &WatchObject, resp: &Response, sole_device: Option<&str>) -> bool {
+    if !watch.enable {
+        return false;
+    }
+    let device = match *resp {
+        Response::Raw(..) | Response::Nmea(..) => {
+            let on = match *resp {
+                Response::Raw(..) => watch.raw.unwrap_or(0) > 0,
+                _ => watch.nmea
+            };
+            // (these are untagged, so can only be from the sole device)
+            return on && watch.device.as_ref().map_or(true, |want| sole_device == Some(want));
+        },
+        Response::Tpv(ref tpv) => tpv.device(),
+        Response::Sky(ref sky) => sky.device.as_ref().map(|x| x as &str),
+        Response::Device(ref dev) => dev.path(),
+        _ => None
+    };
+    let device_ok = match (watch.device.as_ref(), device) {
+        (Some(want), Some(got)) => want == got,
+        _ => true
+    };
+    watch.json && device_ok
+}
+/// Queue `resp` for every client that wants it, without blocking.
+fn broadcast(state: &mut State, resp: &Response) {
+    let sole_device = match state.devices[..] {
+        [ref dev] => dev.path().map(String::from),
+        _ => None
+    };
+    let sole_device = sole_device.as_ref().map(|x| x as &str);
+    if !state.clients.iter().any(|c| wants(&c.watch, resp, sole_device)) {
+        return;
+    }
+    let line = Arc::new(format!("{}\r\n", resp.to_gpsd_json()));
+    state.clients.retain_mut(|client| {
+        if !wants(&client.watch, resp, sole_device) {
+            return true;
+        }
+        match client.tx.try_send(line.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                client.dropped += 1;
+                if client.dropped.is_power_of_two() {
+                    warn!("relay client {} is too slow; {} lines dropped so far", client.id, client.dropped);
+                }
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false
+        }
+    });
+}
+/// Accept clients until the relay is shut down.
+fn accept(listener: TcpListener, state: Arc<Mutex<State>>, client_buffer: usize) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("relay failed to accept client: {}", e);
+                continue;
+            }
+        };
+        if lock(&state).closed {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        if let Err(e) = spawn_client(stream, state.clone(), client_buffer) {
+            warn!("relay failed to set up client: {}", e);
+        }
+    }
+}
+/// Greet a new client, and start threads to read its commands and write to it.
+fn spawn_client(stream: TcpStream, state: Arc<Mutex<State>>, client_buffer: usize) -> GpsdResult<()> {
+    let (tx, rx) = mpsc::sync_channel::<Arc<String>>(client_buffer);
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for line in rx {
+            if writer.write_all(line.as_bytes()).is_err() {
+                break;
+            }
+        }
+    });
+    let (id, greeting) = {
+        let mut state = lock(&state);
+        let id = state.next_id;
+        state.next_id += 1;
+        let greeting = state.version.as_ref().map(|v| Arc::new(format!("{}\r\n", v.to_gpsd_json())));
+        state.clients.push(Client {
+            id,
+            watch: WatchObject { enable: false, ..Default::default() },
+            tx: tx.clone(),
+            stream: stream.try_clone()?,
+            dropped: 0
+        });
+        (id, greeting)
+    };
+    // (not watching yet, so nothing can be queued before this)
+    if let Some(greeting) = greeting {
+        let _ = tx.send(greeting);
+    }
+    debug!("relay client {} connected", id);
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut buf = String::new();
+        while let Ok(n) = reader.read_line(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            for cmd in split_commands(&buf) {
+                for reply in handle_command(&state, id, cmd) {
+                    if tx.send(Arc::new(format!("{}\r\n", reply.to_gpsd_json()))).is_err() {
+                        return;
+                    }
+                }
+            }
+            buf.clear();
+        }
+        debug!("relay client {} disconnected", id);
+        lock(&state).clients.retain(|c| c.id != id);
+    });
+    Ok(())
+}
+/// Split a line from a client into commands, which are terminated by `;` (or
+/// the end of the line). Semicolons inside JSON strings don't count.
+fn split_commands(line: &str) -> Vec<&str> {
+    let mut ret = vec![];
+    let mut start = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            ';' if !in_str => {
+                ret.push(line[start..i].trim());
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    ret.push(line[start..].trim());
+    ret.retain(|x| !x.is_empty());
+    ret
+}
+fn error(message: String) -> Response {
+    Response::Error { message, extra: Map::new() }
+}
+/// Work out the replies to a command from client `id`.
+fn handle_command(state: &Mutex<State>, id: u64, cmd: &str) -> Vec<Response> {
+    let mut state = lock(state);
+    let (name, arg) = match cmd.find('=') {
+        Some(idx) => (&cmd[..idx], Some(&cmd[idx + 1..])),
+        None => (cmd, None)
+    };
+    match (name, arg) {
+        ("?VERSION", _) => state.version.iter().cloned().collect(),
+        ("?DEVICES", _) => vec![devices(&state)],
+        ("?POLL", _) => vec![Response::Poll {
+            time: Utc::now(),
+            active: state.devices.iter().filter(|d| !matches!(**d, DeviceObject::Inactive { .. })).count() as u32,
+            tpv: state.tpv.clone(),
+            sky: state.sky.clone(),
+            extra: Map::new()
+        }],
+        ("?DEVICE", None) => match state.devices.first() {
+            Some(dev) => vec![Response::Device(dev.clone())],
+            None => vec![error("No such device".into())]
+        },
+        ("?DEVICE", Some(_)) => {
+            vec![error("DEVICE configuration is not permitted through this relay".into())]
+        },
+        ("?WATCH", arg) => {
+            let devices = devices(&state);
+            let client = match state.clients.iter_mut().find(|c| c.id == id) {
+                Some(c) => c,
+                None => return vec![]
+            };
+            if let Some(arg) = arg {
+                match update_watch(&client.watch, arg) {
+                    Ok(w) => client.watch = w,
+                    Err(e) => return vec![error(format!("Invalid WATCH: {}", e))]
+                }
+            }
+            let watch = Response::Watch(client.watch.clone());
+            if client.watch.enable && arg.is_some() {
+                vec![devices, watch]
+            }
+            else {
+                vec![watch]
+            }
+        },
+        _ => vec![error(format!("Unrecognized request '{}'", name.trim_start_matches('?')))]
+    }
+}
+fn devices(state: &State) -> Response {
+    Response::Devices { devices: state.devices.clone(), remote: None, extra: Map::new() }
+}
+/// Apply the settings in the JSON argument to `?WATCH=` to a client's current
+/// ones. As with gpsd, settings that aren't mentioned stay as they are.
+fn update_watch(watch: &WatchObject, arg: &str) -> Result<WatchObject, serde_json::Error> {
+    let changes: Map<String, Value> = serde_json::from_str(arg)?;
+    let mut current = match serde_json::to_value(watch)? {
+        Value::Object(map) => map,
+        _ => unreachable!("WatchObject serializes to an object")
+    };
+    for (key, val) in changes {
+        if key != "class" {
+            current.insert(key, val);
+        }
+    }
+    serde_json::from_value(Value::Object(current))
+}
@@ -0,0 +1,214 @@
+//! Averaging fixes to survey in a stationary position.
+//!
+//! `PositionAverager` collects 3D fixes from a receiver that isn't moving (a
+//! fixed antenna, say) and works out a precise position from them. Each fix is
+//! weighted by gpsd's error estimates (`epx`/`epy` horizontally, `epv`
+//! vertically), so that good fixes count for more than bad ones. Once enough
+//! fixes have been collected to know how much they usually scatter, ones that
+//! are too far from the mean are rejected as outliers.
+//!
+//! Alongside the mean, `AveragedPosition` describes how the fixes scatter
+//! (standard deviations, CEP and R95), and how far the mean can be trusted.
+//! The errors in successive fixes are strongly correlated over minutes, so
+//! the statistical error of the mean (`mean_error`) is optimistic; the drift
+//! of the mean over the last few samples (`drift`) is a more honest guide to
+//! whether the average has settled down.
+use coords::{Enu, LocalFrame};
+use types::{FixMode, Position, Response, TpvResponse};
+
+/// Don't reject outliers until there are this many samples.
+const MIN_SAMPLES_FOR_REJECTION: usize = 10;
+
+/// The result of averaging, from `PositionAverager::result`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AveragedPosition {
+    /// Number of fixes averaged.
+    pub samples: usize,
+    /// Number of fixes rejected as outliers.
+    pub rejected: usize,
+    /// The weighted mean position.
+    pub position: Position,
+    /// Weighted standard deviation of the fixes, in meters.
+    pub std_dev: Enu,
+    /// Radius of the circle containing half of the fixes (horizontally), in
+    /// meters.
+    pub cep50: f64,
+    /// Radius of the circle containing 95% of the fixes (horizontally), in
+    /// meters.
+    pub cep95: f64,
+    /// Radius of the sphere containing 95% of the fixes (in 3D), in meters.
+    pub r95: f64,
+    /// 95% horizontal error of the mean position, in meters, if the fixes'
+    /// errors were independent. (They aren't, so this is optimistic.)
+    pub mean_error: f64,
+    /// How far the mean position has moved (horizontally) over the last
+    /// `window` samples, in meters, or `None` if there haven't been that many.
+    pub drift: Option<f64>
+}
+impl AveragedPosition {
+    /// Has the average settled down to within `tolerance` meters? This is
+    /// true when both `mean_error` and `drift` are within it.
+    pub fn is_converged(&self, tolerance: f64) -> bool {
+        self.mean_error <= tolerance && self.drift.is_some_and(|d| d <= tolerance)
+    }
+}
+/// Weighted running sums for one axis.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sums {
+    w: f64,
+    w2: f64,
+    wx: f64,
+    wxx: f64
+}
+impl Sums {
+    fn add(&mut self, x: f64, w: f64) {
+        self.w += w;
+        self.w2 += w * w;
+        self.wx += w * x;
+        self.wxx += w * x * x;
+    }
+    fn mean(&self) -> f64 {
+        self.wx / self.w
+    }
+    fn variance(&self) -> f64 {
+        (self.wxx / self.w - self.mean().powi(2)).max(0.0)
+    }
+    /// Kish's effective sample size.
+    fn effective_n(&self) -> f64 {
+        self.w * self.w / self.w2
+    }
+}
+/// Averages fixes from a stationary receiver.
+#[derive(Debug, Clone)]
+pub struct PositionAverager {
+    outlier_sigmas: f64,
+    window: usize,
+    default_error: f64,
+    frame: Option<LocalFrame>,
+    sums: [Sums; 3],
+    samples: usize,
+    rejected: usize,
+    /// The horizontal mean after each sample, for working out the drift.
+    history: Vec<(f64, f64)>
+}
+impl Default for PositionAverager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// Standard deviation from a 95% error estimate.
+fn std_dev(err95: f64) -> f64 {
+    err95 / 1.96
+}
+impl PositionAverager {
+    /// Make a new averager, with no fixes yet.
+    pub fn new() -> Self {
+        PositionAverager {
+            outlier_sigmas: 3.0,
+            window: 60,
+            default_error: 10.0,
+            frame: None,
+            sums: [Sums::default(); 3],
+            samples: 0,
+            rejected: 0,
+            history: vec![]
+        }
+    }
+    /// Reject fixes further than `sigmas` standard deviations from the mean
+    /// (3 by default). The standard deviation used is the larger of the
+    /// scatter of the fixes so far, and the fix's own error estimate.
+    pub fn set_outlier_threshold(&mut self, sigmas: f64) {
+        self.outlier_sigmas = sigmas;
+    }
+    /// Work out the drift over the last `samples` samples (60 by default).
+    pub fn set_window(&mut self, samples: usize) {
+        self.window = samples.max(1);
+    }
+    /// Assume a 95% error of `error` meters for fixes without an estimate (10
+    /// by default).
+    pub fn set_default_error(&mut self, error: f64) {
+        self.default_error = error;
+    }
+    /// Forget all the fixes so far.
+    pub fn reset(&mut self) {
+        self.frame = None;
+        self.sums = [Sums::default(); 3];
+        self.samples = 0;
+        self.rejected = 0;
+        self.history.clear();
+    }
+    /// Add a response. Anything other than a TPV is ignored.
+    pub fn handle(&mut self, resp: &Response) -> bool {
+        match *resp {
+            Response::Tpv(ref tpv) => self.update(tpv),
+            _ => false
+        }
+    }
+    /// Add a TPV, returning whether it was used. Only 3D fixes are used, and
+    /// not ones rejected as outliers.
+    pub fn update(&mut self, tpv: &TpvResponse) -> bool {
+        if tpv.mode() != FixMode::Fix3D {
+            return false;
+        }
+        let pos = match tpv.position() {
+            Some(p) if p.alt.is_some() => p,
+            _ => return false
+        };
+        let errors = tpv.errors();
+        let sd_h = std_dev(errors.lat.unwrap_or(self.default_error).max(errors.lon.unwrap_or(self.default_error)));
+        let sd_v = std_dev(errors.alt.unwrap_or(self.default_error * 1.5));
+        let frame = *self.frame.get_or_insert_with(|| LocalFrame::new(pos));
+        let enu = frame.position_to_enu(&pos);
+
+        if self.samples >= MIN_SAMPLES_FOR_REJECTION {
+            let [e, n, u] = self.sums;
+            let scatter_h = (e.variance() + n.variance()).sqrt();
+            let off_h = (enu.east - e.mean()).hypot(enu.north - n.mean());
+            let off_v = (enu.up - u.mean()).abs();
+            if off_h > self.outlier_sigmas * scatter_h.max(sd_h) ||
+                off_v > self.outlier_sigmas * u.variance().sqrt().max(sd_v) {
+                self.rejected += 1;
+                return false;
+            }
+        }
+        let (w_h, w_v) = (1.0 / (sd_h * sd_h).max(1e-6), 1.0 / (sd_v * sd_v).max(1e-6));
+        self.sums[0].add(enu.east, w_h);
+        self.sums[1].add(enu.north, w_h);
+        self.sums[2].add(enu.up, w_v);
+        self.samples += 1;
+        self.history.push((self.sums[0].mean(), self.sums[1].mean()));
+        if self.history.len() > self.window + 1 {
+            self.history.remove(0);
+        }
+        true
+    }
+    /// The average so far, or `None` if there haven't been any fixes.
+    pub fn result(&self) -> Option<AveragedPosition> {
+        let frame = self.frame?;
+        if self.samples == 0 {
+            return None;
+        }
+        let [e, n, u] = self.sums;
+        let mean = Enu { east: e.mean(), north: n.mean(), up: u.mean() };
+        let std_dev = Enu { east: e.variance().sqrt(), north: n.variance().sqrt(), up: u.variance().sqrt() };
+        let drift = if self.history.len() > self.window {
+            let (e0, n0) = self.history[0];
+            Some((mean.east - e0).hypot(mean.north - n0))
+        }
+        else {
+            None
+        };
+        Some(AveragedPosition {
+            samples: self.samples,
+            rejected: self.rejected,
+            position: frame.enu_to_position(mean),
+            std_dev,
+            // the usual approximations for a bivariate (and trivariate) normal
+            cep50: 0.5887 * (std_dev.east + std_dev.north),
+            cep95: 1.2239 * (std_dev.east + std_dev.north),
+            r95: 0.9318 * (std_dev.east + std_dev.north + std_dev.up),
+            mean_error: 2.4477 * ((e.variance() + n.variance()) / 2.0 / e.effective_n()).sqrt(),
+            drift
+        })
+    }
+}
@@ -1,13 +1,12 @@
 use super::*;
 use std::process::Command;
-use super::errors::*;
 use serde_json::error::Category;
 use std::thread;
 use std::time::Duration;
 #[test]
 fn gpsfake_basic() {
     let mut cmd = Command::new("gpsfake")
-        .args(&["-1", "stockholm_walk.nmea"])
+        .args(["-1", "stockholm_walk.nmea"])
         .spawn()
         .unwrap();
     thread::sleep(Duration::from_millis(1000));
@@ -20,12 +19,12 @@ fn gpsfake_basic() {
         }
         let resp = conn.get_response();
         if let Err(e) = resp {
-            if let &ErrorKind::DeserFailed(_, ref e) = e.kind() {
-                if let Category::Eof = e.classify() {
+            if let Error::MalformedJson { ref source, .. } = e {
+                if let Category::Eof = source.classify() {
                     continue;
                 }
             }
-            if let &ErrorKind::Io(..) = e.kind() {
+            if let Error::Io(..) | Error::Timeout = e {
                 return;
             }
             panic!("error: {:?}", e);
@@ -33,9 +32,10 @@ fn gpsfake_basic() {
     }
 }
 #[test]
+#[allow(clippy::zombie_processes)]
 fn gpsfake_poll() {
     Command::new("gpsfake")
-        .args(&["-1", "stockholm_walk.nmea"])
+        .args(["-1", "stockholm_walk.nmea"])
         .spawn()
         .unwrap();
     thread::sleep(Duration::from_millis(1000));
@@ -45,14 +45,1571 @@ fn gpsfake_poll() {
     conn.poll().unwrap();
     let resp = conn.get_response();
     if let Err(e) = resp {
-        if let &ErrorKind::DeserFailed(_, ref e) = e.kind() {
-            if let Category::Eof = e.classify() {
+        if let Error::MalformedJson { ref source, .. } = e {
+            if let Category::Eof = source.classify() {
                 return;
             }
         }
-        if let &ErrorKind::Io(..) = e.kind() {
+        if let Error::Io(..) | Error::Timeout = e {
             return;
         }
         panic!("error: {:?}", e);
     }
 }
+#[test]
+fn raw_frame_classify() {
+    use raw::{RawFrame, BinaryProtocol};
+    let nmea = RawFrame::classify("$GPGSA,A,3,05,24,17,30,02,,,,,,,,5.6,3.3,4.5*34\r\n");
+    assert_eq!(nmea, RawFrame::Nmea("$GPGSA,A,3,05,24,17,30,02,,,,,,,,5.6,3.3,4.5*34".into()));
+    let ais = RawFrame::classify("!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C\n");
+    assert!(matches!(ais, RawFrame::Aivdm(_)));
+    let ubx = RawFrame::classify("b5620107\n");
+    assert_eq!(ubx, RawFrame::Binary(vec![0xb5, 0x62, 0x01, 0x07]));
+    assert_eq!(ubx.protocol(), Some(BinaryProtocol::Ubx));
+    assert_eq!(RawFrame::classify("a0a2"), RawFrame::Binary(vec![0xa0, 0xa2]));
+    assert_eq!(RawFrame::classify("xyz"), RawFrame::Other("xyz".into()));
+    assert_eq!(RawFrame::classify("abc"), RawFrame::Other("abc".into()));
+}
+#[test]
+fn nmea_sentence_parse() {
+    use nmea::NmeaSentence;
+    let line = "$GPGGA,104427.591,5920.7009,N,01803.2938,E,1,05,3.3,78.2,M,23.2,M,0.0,0000*4A\r\n";
+    let s = NmeaSentence::parse(line).unwrap();
+    assert_eq!(s.talker, "GP");
+    assert_eq!(s.kind, "GGA");
+    assert_eq!(s.fields.len(), 14);
+    assert_eq!(s.field(1), Some("5920.7009"));
+    assert_eq!(s.checksum, Some(0x4a));
+    assert_eq!(s.to_string(), line.trim_end());
+    let prop = NmeaSentence::parse("$PSRF103,00,01,00,01").unwrap();
+    assert_eq!(prop.talker, "P");
+    assert_eq!(prop.kind, "SRF103");
+    assert_eq!(prop.checksum, None);
+    // corrupted checksum
+    assert!(NmeaSentence::parse("$GPGSA,A,3,05,24,17,30,02,,,,,,,,5.6,3.3,4.5*35").is_none());
+    assert!(NmeaSentence::parse("{\"class\":\"TPV\"}").is_none());
+}
+#[test]
+fn tpv_modern_fields() {
+    let data = r#"{"class":"TPV","device":"/dev/ttyACM0","status":2,"mode":3,"time":"2020-03-31T19:35:45.000Z","leapseconds":18,"ept":0.005,"lat":59.345031667,"lon":18.054821667,"altHAE":101.4,"altMSL":78.2,"alt":78.2,"epx":2.3,"epy":3.1,"epv":4.5,"track":320.93,"magtrack":326.1,"magvar":5.2,"speed":0.06,"climb":0.1,"eps":0.6,"epc":9.0,"ecefx":3103710.46,"ecefy":1012204.04,"ecefz":5463429.12,"ecefvx":0.01,"ecefvy":-0.02,"ecefvz":0.0,"ecefpAcc":4.1,"ecefvAcc":0.6,"velN":0.047,"velE":-0.038,"velD":-0.1,"geoidSep":23.2,"eph":3.9,"sep":6.0}"#;
+    let resp: Response = serde_json::from_str(data).unwrap();
+    match resp {
+        Response::Tpv(TpvResponse::Fix3D { details, .. }) => {
+            assert_eq!(details.status, Some(FixStatus::Dgps));
+            assert_eq!(details.leap_seconds, Some(18));
+            assert_eq!(details.alt_hae, Some(101.4));
+            assert_eq!(details.geoid_sep, Some(23.2));
+            assert_eq!(details.ecef_x, Some(3103710.46));
+            assert_eq!(details.vel_d, Some(-0.1));
+            assert_eq!(details.sphere_err, Some(6.0));
+        },
+        x => panic!("unexpected response: {:?}", x)
+    }
+    assert_eq!(FixStatus::from(4), FixStatus::RtkFloat);
+    assert_eq!(u8::from(FixStatus::Other(42)), 42);
+}
+#[test]
+fn sky_modern_fields() {
+    let data = r#"{"class":"SKY","device":"/dev/ttyACM0","time":"2020-03-31T19:35:45.000Z","xdop":0.54,"ydop":0.77,"vdop":0.85,"tdop":0.79,"hdop":0.94,"gdop":1.49,"pdop":1.27,"nSat":3,"uSat":2,"satellites":[{"PRN":5,"el":31.0,"az":86.0,"ss":45.0,"used":true,"gnssid":0,"svid":5,"sigid":0,"health":1,"prRes":-1.2,"qual":7},{"PRN":68,"ss":20.0,"used":false,"gnssid":6,"svid":4,"freqid":13},{"PRN":301,"el":12.0,"az":270.0,"ss":33.0,"used":true,"pr":21612345.12,"prRate":-120.5}]}"#;
+    let resp: Response = serde_json::from_str(data).unwrap();
+    let sky = match resp {
+        Response::Sky(sky) => sky,
+        x => panic!("unexpected response: {:?}", x)
+    };
+    assert_eq!(sky.n_sat, Some(3));
+    assert_eq!(sky.u_sat, Some(2));
+    let sats = sky.satellites.unwrap();
+    assert_eq!(sats[0].pr_res, Some(-1.2));
+    assert_eq!(sats[0].quality, Some(7));
+    assert_eq!(sats[1].azimuth, None);
+    assert_eq!(sats[1].freq_id, Some(13));
+    assert_eq!(sats[2].pseudorange, Some(21612345.12));
+    let systems: Vec<_> = sats.iter().map(|s| s.system()).collect();
+    assert_eq!(systems, vec![Some(GnssSystem::Gps), Some(GnssSystem::Glonass), Some(GnssSystem::Galileo)]);
+    let empty: Response = serde_json::from_str(r#"{"class":"SKY","hdop":1.2}"#).unwrap();
+    match empty {
+        Response::Sky(sky) => assert!(sky.satellites.is_none()),
+        x => panic!("unexpected response: {:?}", x)
+    }
+}
+#[test]
+fn device_typed_fields() {
+    let data = r#"{"class":"DEVICES","devices":[{"class":"DEVICE","path":"/dev/ttyUSB0","driver":"SiRF","activated":"2020-03-31T19:35:45.000Z","flags":9,"native":1,"bps":4800,"parity":"N","stopbits":1,"cycle":1.00}]}"#;
+    let resp: Response = serde_json::from_str(data).unwrap();
+    let devices = match resp {
+        Response::Devices { devices, .. } => devices,
+        x => panic!("unexpected response: {:?}", x)
+    };
+    match devices[0] {
+        DeviceObject::ActiveSeenPackets { flags, parity, stopbits, native, .. } => {
+            assert!(flags.contains(DeviceFlags::GPS | DeviceFlags::AIS));
+            assert!(!flags.contains(DeviceFlags::RTCM2));
+            assert_eq!(parity, Some(Parity::None));
+            assert_eq!(stopbits, Some(StopBits::One));
+            assert_eq!(native, Some(NativeMode::Alternate));
+        },
+        ref x => panic!("unexpected device: {:?}", x)
+    }
+    let json = serde_json::to_value(&devices[0]).unwrap();
+    assert_eq!(json["flags"], 9);
+    assert_eq!(json["parity"], "N");
+    assert_eq!(json["stopbits"], 1);
+    assert_eq!(json["native"], 1);
+    let stopbits: StopBits = serde_json::from_str("\"2\"").unwrap();
+    assert_eq!(stopbits, StopBits::Two);
+    let mode: FixMode = serde_json::from_str("3").unwrap();
+    assert_eq!(mode, FixMode::Fix3D);
+    assert_eq!(serde_json::to_string(&mode).unwrap(), "3");
+    assert!(serde_json::from_str::<FixMode>("4").is_err());
+}
+#[test]
+fn tpv_accessors() {
+    let data = r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":2,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.345015,"lon":18.054897,"epx":21.5,"epy":33.1,"track":320.93,"speed":0.075,"eps":66.2}"#;
+    let tpv: TpvResponse = serde_json::from_str(data).unwrap();
+    assert!(matches!(tpv, TpvResponse::Fix2D { .. }));
+    assert_eq!(tpv.device(), Some("/dev/ttyUSB0"));
+    assert_eq!(tpv.mode(), FixMode::Fix2D);
+    assert_eq!(tpv.lat(), Some(59.345015));
+    assert_eq!(tpv.alt(), None);
+    assert_eq!(tpv.time().unwrap().timestamp(), 1103021067);
+    assert_eq!(tpv.position(), Some(Position { lat: 59.345015, lon: 18.054897, alt: None }));
+    assert_eq!(tpv.velocity(), Some(Velocity { speed: 0.075, track: Some(320.93), climb: None }));
+    let errs = tpv.errors();
+    assert_eq!(errs.lat, Some(33.1));
+    assert_eq!(errs.lon, Some(21.5));
+    assert_eq!(errs.alt, None);
+    let nofix: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":1,"time":"2004-12-14T10:44:27.591Z"}"#).unwrap();
+    assert_eq!(nofix.mode(), FixMode::NoFix);
+    assert_eq!(nofix.position(), None);
+    assert_eq!(nofix.errors(), TpvErrors::default());
+    assert_eq!(TpvResponse::default().mode(), FixMode::Unknown);
+}
+#[test]
+fn unknown_fields_round_trip() {
+    let lines = [
+        r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":3,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.345015,"lon":18.054897,"alt":78.2,"epx":21.5,"epy":33.1,"epv":51.75,"track":320.93,"speed":0.075,"climb":0.7,"eps":66.2,"epc":103.5,"tag":"GGA","foo":{"bar":[1,2]}}"#,
+        r#"{"class":"SKY","device":"/dev/ttyUSB0","hdop":3.3,"satellites":[{"PRN":5,"el":70.0,"az":125.0,"ss":39.0,"used":true,"wibble":1}],"tag":"GSV"}"#,
+        r#"{"class":"DEVICES","devices":[{"class":"DEVICE","path":"/dev/ttyUSB0","activated":"2004-12-14T10:44:27.591Z","flags":1,"driver":"NMEA0183","bps":4800,"parity":"N","stopbits":1,"native":0,"cycle":1.0,"sernum":"1234"}]}"#,
+        r#"{"class":"VERSION","release":"3.22","rev":"3.22","proto_major":3,"proto_minor":14,"future":true}"#,
+        r#"{"class":"WATCH","enable":true,"json":true,"nmea":false,"raw":0,"scaled":false,"timing":false,"split24":false,"pps":false}"#,
+        r#"{"class":"POLL","time":"2010-06-04T10:31:00.289Z","active":1,"tpv":[{"class":"TPV","device":"/dev/ttyUSB0","mode":1,"time":"2010-06-04T10:31:00.000Z"}],"sky":[{"class":"SKY","device":"/dev/ttyUSB0","hdop":0.94}]}"#,
+    ];
+    for line in lines.iter() {
+        let resp: Response = serde_json::from_str(line).unwrap();
+        let out = serde_json::to_string(&resp).unwrap();
+        let orig: serde_json::Value = serde_json::from_str(line).unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(orig, out);
+    }
+    // nested objects' `class` is implied, so isn't an unknown field
+    match serde_json::from_str(lines[2]).unwrap() {
+        Response::Devices { ref devices, .. } => assert_eq!(devices[0].extra().keys().collect::<Vec<_>>(), ["sernum"]),
+        x => panic!("unexpected response: {:?}", x)
+    }
+    match serde_json::from_str(lines[5]).unwrap() {
+        Response::Poll { ref tpv, ref sky, .. } => {
+            assert!(tpv[0].extra().is_empty());
+            assert!(sky[0].extra.is_empty());
+        },
+        x => panic!("unexpected response: {:?}", x)
+    }
+    // gpsd sends `class` first, but it doesn't have to come first
+    let late: Response = serde_json::from_str(r#"{"mode":1,"time":"2004-12-14T10:44:27.591Z","class":"TPV","tag":"GGA"}"#).unwrap();
+    assert!(matches!(late, Response::Tpv(TpvResponse::NoFix { ref extra, .. }) if extra.len() == 1));
+    let version: Response = serde_json::from_value(json!({ "release": "3.22", "rev": "3.22", "class": "VERSION", "proto_major": 3, "proto_minor": 14 })).unwrap();
+    assert!(matches!(version, Response::Version { proto_minor: 14, ref extra, .. } if extra.is_empty()));
+    assert!(serde_json::from_str::<Response>(r#"{"mode":1}"#).is_err());
+    let tpv: TpvResponse = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(tpv.extra()["tag"], "GGA");
+}
+#[test]
+fn tpv_classification() {
+    let fix2d: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":2,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"speed":0.1,"epv":12.0}"#).unwrap();
+    match fix2d {
+        TpvResponse::Fix2D { ref extra, .. } => assert_eq!(extra["epv"], 12.0),
+        ref x => panic!("unexpected TPV: {:?}", x)
+    }
+    // a 3D fix with no altitude can only be a 2D fix
+    let fix2d: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":3,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"speed":0.1,"climb":0.2}"#).unwrap();
+    assert!(matches!(fix2d, TpvResponse::Fix2D { .. }));
+    let nofix: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":1,"time":"2004-12-14T10:44:27.591Z","ept":0.005}"#).unwrap();
+    match nofix {
+        TpvResponse::NoFix { ref extra, .. } => assert_eq!(extra["ept"], 0.005),
+        ref x => panic!("unexpected TPV: {:?}", x)
+    }
+    // fields moved into `extra` are written back where they came from
+    let line = r#"{"class":"TPV","mode":1,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"tag":"GGA"}"#;
+    let nofix: Response = serde_json::from_str(line).unwrap();
+    assert_eq!(serde_json::to_string(&nofix).unwrap(), line);
+    let nothing: TpvResponse = serde_json::from_str(r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":0}"#).unwrap();
+    assert!(matches!(nothing, TpvResponse::Nothing { .. }));
+    // no time error, so this doesn't fit anywhere
+    let dustbin: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":3,"time":"2004-12-14T10:44:27.591Z","lat":59.3,"lon":18.0,"alt":null}"#).unwrap();
+    match dustbin {
+        TpvResponse::Dustbin { lat, .. } => assert_eq!(lat, Some(59.3)),
+        ref x => panic!("unexpected TPV: {:?}", x)
+    }
+    // no mode, which mustn't appear from nowhere on the way back out
+    let line = r#"{"class":"TPV","time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0}"#;
+    let modeless: Response = serde_json::from_str(line).unwrap();
+    match modeless {
+        Response::Tpv(TpvResponse::LatLonOnly { mode, .. }) => assert_eq!(mode, None),
+        ref x => panic!("unexpected TPV: {:?}", x)
+    }
+    let out: serde_json::Value = serde_json::from_str(&serde_json::to_string(&modeless).unwrap()).unwrap();
+    assert_eq!(out, serde_json::from_str::<serde_json::Value>(line).unwrap());
+    let err = serde_json::from_str::<Response>(r#"{"class":"TPV","mode":3,"lat":"north"}"#).unwrap_err();
+    assert!(err.to_string().contains("`lat`"), "{}", err);
+}
+/// Run a fake gpsd on a local port, handing the first connection to `handler`.
+fn fake_gpsd<F>(handler: F) -> ::std::net::SocketAddr
+    where F: FnOnce(::std::net::TcpStream) + Send + 'static {
+    let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        handler(stream);
+    });
+    addr
+}
+#[test]
+fn structured_errors() {
+    use std::error::Error as StdError;
+    use std::io::Write;
+    let addr = fake_gpsd(|mut stream| {
+        stream.write_all(b"{\"class\":\"PPS\",\"device\":\"/dev/ttyUSB0\"}\n").unwrap();
+        stream.write_all(b"{\"class\":\"TPV\",\"mode\":\"x\"}\n").unwrap();
+        stream.write_all(b"{\"class\":\"ERROR\",\"message\":\"Unrecognized request 'FOO'\"}\n").unwrap();
+        thread::sleep(Duration::from_millis(300));
+    });
+    let mut conn = GpsdConnection::new(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    match conn.get_response() {
+        Err(Error::UnknownClass { ref class, .. }) => assert_eq!(class, "PPS"),
+        x => panic!("unexpected result: {:?}", x)
+    }
+    match conn.get_response() {
+        Err(e @ Error::MalformedJson { .. }) => assert!(e.source().is_some()),
+        x => panic!("unexpected result: {:?}", x)
+    }
+    assert!(matches!(conn.get_response(), Ok(Response::Error { .. })));
+    assert!(matches!(conn.get_response(), Err(Error::Timeout)));
+    thread::sleep(Duration::from_millis(300));
+    assert!(matches!(conn.get_response(), Err(Error::ConnectionClosed)));
+}
+#[test]
+fn nmea_mode() {
+    use std::io::{BufRead, BufReader, Write};
+    let addr = fake_gpsd(|stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("?WATCH"));
+        stream.write_all(b"{\"class\":\"WATCH\",\"enable\":true,\"json\":false,\"nmea\":true}\n").unwrap();
+        stream.write_all(b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n").unwrap();
+        stream.write_all(b"!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C\r\n").unwrap();
+        stream.write_all(b"$GPGGA,123519,4807.038,N*00\r\n").unwrap();
+    });
+    let mut conn = GpsdConnection::new(addr).unwrap();
+    conn.watch_nmea(true, false).unwrap();
+    assert!(matches!(conn.get_response().unwrap(), Response::Watch(WatchObject { nmea: true, .. })));
+    assert!(matches!(conn.get_response().unwrap(), Response::Nmea(..)));
+    match conn.get_response().unwrap() {
+        Response::Raw(frame) => assert_eq!(frame, RawFrame::Aivdm("!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C".into())),
+        x => panic!("unexpected response: {:?}", x)
+    }
+    assert!(matches!(conn.get_response(), Err(Error::MalformedNmea(..))));
+}
+#[test]
+fn command_timeouts() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::time::Instant;
+    let addr = fake_gpsd(|stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        let mut line = String::new();
+        // never replies to the first command...
+        reader.read_line(&mut line).unwrap();
+        // ...and floods the second with fixes
+        reader.read_line(&mut line).unwrap();
+        for _ in 0..2000 {
+            if stream.write_all(b"{\"class\":\"TPV\",\"mode\":1,\"time\":\"2004-12-14T10:44:27.591Z\"}\n").is_err() {
+                return;
+            }
+        }
+        thread::sleep(Duration::from_millis(500));
+    });
+    let mut conn = GpsdConnection::new(addr).unwrap();
+    conn.set_command_timeout(Duration::from_millis(200));
+    let start = Instant::now();
+    assert!(matches!(conn.version(), Err(Error::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    conn.set_command_timeout(Duration::from_secs(30));
+    let start = Instant::now();
+    assert!(matches!(conn.poll(), Err(Error::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(conn.pending.len(), 1024);
+    assert!(matches!(conn.get_response(), Ok(Response::Tpv(..))));
+}
+#[test]
+fn command_errors() {
+    use std::io::{BufRead, BufReader, Write};
+    let addr = fake_gpsd(|stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            let reply = if line.starts_with("?VERSION") {
+                "{\"class\":\"TPV\",\"mode\":1,\"time\":\"2004-12-14T10:44:27.591Z\"}\n{\"class\":\"VERSION\",\"release\":\"3.22\",\"rev\":\"3.22\",\"proto_major\":3,\"proto_minor\":14}\n"
+            }
+            else if line.starts_with("?POLL") {
+                "{\"class\":\"ERROR\",\"message\":\"Unrecognized request 'POLL'\"}\n"
+            }
+            else if line.starts_with("?WATCH") {
+                "{\"class\":\"ERROR\",\"message\":\"Invalid WATCH: unknown attribute name\"}\n"
+            }
+            else {
+                "{\"class\":\"ERROR\",\"message\":\"Can't perform DEVICE configuration, /dev/ttyUSB0 device not writeable.\"}\n"
+            };
+            stream.write_all(reply.as_bytes()).unwrap();
+            line.clear();
+        }
+    });
+    let mut conn = GpsdConnection::new(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_millis(1000))).unwrap();
+    conn.version().unwrap();
+    // anything received while waiting for the reply is still returned
+    assert!(matches!(conn.get_response(), Ok(Response::Tpv(..))));
+    assert!(matches!(conn.get_response(), Ok(Response::Version { .. })));
+    match conn.poll() {
+        Err(Error::Command { ref request, error: CommandError::UnrecognizedRequest(..) }) => {
+            assert_eq!(request, "?POLL;");
+        },
+        x => panic!("unexpected result: {:?}", x)
+    }
+    assert!(matches!(conn.watch(true), Err(Error::Command { error: CommandError::InvalidJson(..), .. })));
+    let config = DeviceConfig { path: Some("/dev/ttyUSB0".into()), bps: Some(9600), ..Default::default() };
+    assert!(matches!(conn.configure_device(&config), Err(Error::Command { error: CommandError::DeviceConfig(..), .. })));
+}
+#[test]
+fn protocol_negotiation() {
+    use std::io::Write;
+    use protocol::ProtocolVersion;
+    let addr = fake_gpsd(|mut stream| {
+        stream.write_all(b"{\"class\":\"VERSION\",\"release\":\"3.25\",\"rev\":\"3.25\",\"proto_major\":3,\"proto_minor\":15}\n").unwrap();
+        stream.write_all(b"{\"class\":\"TPV\",\"mode\":3,\"time\":\"2004-12-14T10:44:27.591Z\",\"ept\":0.005,\"lat\":59.3,\"lon\":18.0,\"altHAE\":101.4,\"altMSL\":78.2,\"speed\":0.1,\"climb\":0.2}\n").unwrap();
+    });
+    let mut conn = GpsdConnection::new_checked(addr).unwrap();
+    assert_eq!(conn.protocol_version(), Some(ProtocolVersion::new(3, 15)));
+    // no alt, so alt() falls back to altMSL, but nothing is added to the TPV
+    let resp = conn.get_response().unwrap();
+    match resp {
+        Response::Tpv(ref tpv) => {
+            assert_eq!(tpv.mode(), FixMode::Fix3D);
+            assert_eq!(tpv.alt(), Some(78.2));
+        },
+        ref x => panic!("unexpected response: {:?}", x)
+    }
+    assert_eq!(resp.to_gpsd_json(), r#"{"class":"TPV","mode":3,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"altHAE":101.4,"altMSL":78.2,"speed":0.1,"climb":0.2}"#);
+
+    let addr = fake_gpsd(|mut stream| {
+        stream.write_all(b"{\"class\":\"VERSION\",\"release\":\"2.90\",\"rev\":\"2.90\",\"proto_major\":3,\"proto_minor\":1}\n").unwrap();
+        stream.write_all(b"{\"class\":\"TPV\",\"tag\":\"MID2\",\"device\":\"/dev/ttyUSB0\",\"time\":1270517264.240,\"ept\":0.005,\"lat\":40.035093060,\"lon\":-75.519748733,\"alt\":31.1,\"track\":99.4319,\"speed\":0.123,\"mode\":3}\n").unwrap();
+    });
+    let mut conn = GpsdConnection::new_checked(addr).unwrap();
+    match conn.get_response().unwrap() {
+        Response::Tpv(tpv) => {
+            assert_eq!(tpv.time().unwrap().timestamp_millis(), 1270517264240);
+            assert!(matches!(tpv, TpvResponse::Fix2D { .. }));
+        },
+        x => panic!("unexpected response: {:?}", x)
+    }
+
+    let addr = fake_gpsd(|mut stream| {
+        stream.write_all(b"{\"class\":\"VERSION\",\"release\":\"9.0\",\"rev\":\"9.0\",\"proto_major\":4,\"proto_minor\":0}\n").unwrap();
+    });
+    match GpsdConnection::new_checked(addr) {
+        Err(Error::VersionMismatch { major: 4, minor: 0 }) => {},
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("version 4.0 accepted")
+    }
+}
+#[test]
+fn gpsd_json_exact() {
+    let lines = [
+        r#"{"class":"TPV","device":"/dev/ttyACM0","status":2,"mode":3,"time":"2019-10-04T08:51:34.000Z","leapseconds":18,"ept":0.005,"lat":59.345015,"lon":18.054897,"altHAE":101.4,"altMSL":78.2,"alt":78.2,"epx":21.5,"epy":33.1,"epv":51.75,"track":320.93,"magtrack":326.1,"magvar":5.2,"speed":0.075,"climb":0.7,"eps":66.2,"epc":103.5,"ecefx":3100142.49,"ecefy":1011368.56,"ecefz":5463720.12,"ecefvx":0.01,"ecefvy":-0.02,"ecefvz":0.03,"ecefpAcc":12.5,"ecefvAcc":0.07,"velN":0.06,"velE":-0.04,"velD":-0.7,"geoidSep":23.2,"eph":39.5,"sep":64.3}"#,
+        r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":2,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"epx":21.5,"epy":33.1,"track":12.5,"speed":0.1,"eps":66.2}"#,
+        // a 3D fix without climb is a 2D fix; alt and epv go into extra and back
+        r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":3,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"alt":78.2,"epv":51.75,"speed":0.1}"#,
+        r#"{"class":"TPV","mode":2,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.3,"lon":18.0,"track":12.5}"#,
+        r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":1,"time":"2004-12-14T10:44:27.591Z","ept":0.005}"#,
+        r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":0}"#,
+        r#"{"class":"TPV","mode":3,"lat":59.3,"lon":18.0,"wibble":[1,{"b":2,"a":1}],"aardvark":true}"#,
+        r#"{"class":"SKY","device":"/dev/ttyUSB0","time":"2004-12-14T10:44:27.591Z","xdop":0.54,"ydop":0.77,"vdop":0.82,"tdop":0.59,"hdop":0.94,"gdop":1.51,"pdop":1.25,"nSat":2,"uSat":1,"satellites":[{"PRN":5,"el":31.0,"az":86.0,"ss":32.5,"used":true,"gnssid":0,"svid":5,"health":1},{"PRN":66,"el":12.5,"az":301.0,"ss":0.0,"used":false,"gnssid":6,"svid":2,"freqid":6}]}"#,
+        // an empty list of satellites isn't the same as none at all
+        r#"{"class":"SKY","device":"/dev/ttyUSB0","time":"2004-12-14T10:44:27.591Z","hdop":0.94,"nSat":0,"uSat":0,"satellites":[]}"#,
+        r#"{"class":"SKY","device":"/dev/ttyUSB0","time":"2004-12-14T10:44:27.591Z","hdop":0.94,"nSat":0,"uSat":0}"#,
+        r#"{"class":"POLL","time":"2010-06-04T10:31:00.289Z","active":1,"tpv":[{"class":"TPV","device":"/dev/ttyUSB0","mode":3,"time":"2010-06-04T10:31:00.000Z","ept":0.005,"lat":46.498203637,"lon":7.56807435,"alt":1327.689,"epx":15.319,"epy":17.054,"epv":124.484,"track":10.3797,"speed":0.091,"climb":-0.085,"eps":34.11,"epc":248.97}],"sky":[{"class":"SKY","device":"/dev/ttyUSB0","time":"2010-06-04T10:31:00.000Z","hdop":0.94}]}"#,
+        r#"{"class":"DEVICE","path":"/dev/ttyACM0","driver":"u-blox","subtype":"SW ROM CORE 3.01","activated":"2019-10-04T08:51:34.123Z","flags":1,"native":1,"bps":9600,"parity":"N","stopbits":1,"cycle":1.0,"mincycle":0.25}"#,
+        r#"{"class":"DEVICES","devices":[{"class":"DEVICE","path":"/dev/ttyUSB0","driver":"NMEA0183","activated":"2004-12-14T10:44:27.591Z","flags":1,"native":0,"bps":4800,"parity":"N","stopbits":1,"cycle":1.0},{"class":"DEVICE","path":"/dev/ttyS0"}],"remote":"gpsd://boat:2947"}"#,
+        r#"{"class":"WATCH","enable":true,"json":true,"nmea":false,"raw":0,"scaled":false,"timing":false,"split24":false,"pps":false,"device":"/dev/ttyUSB0"}"#,
+        r#"{"class":"VERSION","release":"3.25","rev":"3.25","proto_major":3,"proto_minor":15}"#,
+        r#"{"class":"ERROR","message":"Unrecognized request 'FOO'"}"#,
+    ];
+    for line in lines.iter() {
+        let resp: Response = serde_json::from_str(line).unwrap();
+        assert_eq!(&resp.to_gpsd_json(), line);
+    }
+    let raw = [
+        "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47",
+        "!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C",
+        "b5620107000000",
+        "something else entirely",
+    ];
+    for line in raw.iter() {
+        let resp = Response::Raw(RawFrame::classify(line));
+        assert_eq!(&resp.to_gpsd_json(), line);
+    }
+    let nmea = Response::Nmea(NmeaSentence::parse(raw[0]).unwrap());
+    assert_eq!(nmea.to_gpsd_json(), raw[0]);
+    // numbers come out in their shortest form, but otherwise unchanged
+    let padded = r#"{"class":"TPV","mode":3,"lat":46.498203637,"lon":7.568074350,"alt":1327.600}"#;
+    let resp: Response = serde_json::from_str(padded).unwrap();
+    assert_eq!(resp.to_gpsd_json(), r#"{"class":"TPV","mode":3,"lat":46.498203637,"lon":7.56807435,"alt":1327.6}"#);
+}
+#[test]
+fn relay() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::mpsc;
+    use relay::Relay;
+    let (go, wait) = mpsc::channel::<()>();
+    let upstream = fake_gpsd(move |stream| {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        let version = "{\"class\":\"VERSION\",\"release\":\"3.25\",\"rev\":\"3.25\",\"proto_major\":3,\"proto_minor\":15}\n";
+        let devices = "{\"class\":\"DEVICES\",\"devices\":[{\"class\":\"DEVICE\",\"path\":\"/dev/ttyUSB0\",\"driver\":\"NMEA0183\",\"activated\":\"2004-12-14T10:44:27.591Z\",\"flags\":1}]}\n";
+        writer.write_all(version.as_bytes()).unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("?VERSION"));
+        writer.write_all(version.as_bytes()).unwrap();
+        reader.read_line(&mut line).unwrap();
+        writer.write_all(devices.as_bytes()).unwrap();
+        reader.read_line(&mut line).unwrap();
+        writer.write_all(devices.as_bytes()).unwrap();
+        writer.write_all(b"{\"class\":\"WATCH\",\"enable\":true,\"json\":true}\n").unwrap();
+        wait.recv().unwrap();
+        writer.write_all(b"{\"class\":\"TPV\",\"device\":\"/dev/ttyUSB0\",\"mode\":3,\"time\":\"2004-12-14T10:44:27.591Z\",\"ept\":0.005,\"lat\":59.3,\"lon\":18.0,\"alt\":78.2,\"speed\":0.1,\"climb\":0.2}\n").unwrap();
+        wait.recv().unwrap();
+        writer.write_all(b"$GPGGA,104427.591,5920.7009,N,01803.2938,E,1,05,3.3,78.2,M,23.2,M,0.0,0000*4A\n").unwrap();
+        let _ = wait.recv();
+    });
+    let mut relay = Relay::bind(GpsdConnection::new_checked(upstream).unwrap(), "127.0.0.1:0").unwrap();
+    // (taken as 1, so that reports can still be queued)
+    relay.set_client_buffer(0);
+    relay.set_nmea(true);
+    let addr = relay.local_addr().unwrap();
+    let stopper = relay.stopper().unwrap();
+    let running = thread::spawn(move || relay.run());
+
+    // clients wait in the listen backlog until the relay has synced with upstream
+    let mut watcher = GpsdConnection::new_checked(addr).unwrap();
+    assert_eq!(watcher.protocol_version(), Some(protocol::ProtocolVersion::new(3, 15)));
+    watcher.watch_nmea(true, true).unwrap();
+    assert!(matches!(watcher.get_response().unwrap(), Response::Devices { ref devices, .. } if devices.len() == 1));
+    assert!(matches!(watcher.get_response().unwrap(), Response::Watch(WatchObject { enable: true, json: true, .. })));
+
+    let mut other = GpsdConnection::new(addr).unwrap();
+    other.inner.get_mut().write_all(b"?WATCH={\"enable\":true,\"json\":true,\"nmea\":true,\"device\":\"/dev/ttyS0\"};?FOO;\n").unwrap();
+    other.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    assert!(matches!(other.get_response().unwrap(), Response::Version { .. }));
+    assert!(matches!(other.get_response().unwrap(), Response::Devices { .. }));
+    match other.get_response().unwrap() {
+        Response::Watch(w) => assert_eq!(w.device.as_ref().map(|x| x as &str), Some("/dev/ttyS0")),
+        x => panic!("unexpected response: {:?}", x)
+    }
+    assert!(matches!(other.get_response().unwrap(), Response::Error { ref message, .. } if message.starts_with("Unrecognized request")));
+    assert!(matches!(other.configure_device(&Default::default()), Err(Error::Command { error: CommandError::DeviceConfig(..), .. })));
+
+    go.send(()).unwrap();
+    match watcher.get_response().unwrap() {
+        Response::Tpv(tpv) => assert_eq!(tpv.lat(), Some(59.3)),
+        x => panic!("unexpected response: {:?}", x)
+    }
+    // the other client is only watching a different device
+    assert!(matches!(other.get_response(), Err(Error::Timeout)));
+    other.poll().unwrap();
+    match other.get_response().unwrap() {
+        Response::Poll { active, ref tpv, .. } => {
+            assert_eq!(active, 1);
+            assert_eq!(tpv[0].device(), Some("/dev/ttyUSB0"));
+        },
+        x => panic!("unexpected response: {:?}", x)
+    }
+
+    // NMEA doesn't say which device it's from, but there's only the one
+    let mut chosen = GpsdConnection::new_checked(addr).unwrap();
+    chosen.inner.get_mut().write_all(b"?WATCH={\"enable\":true,\"nmea\":true,\"device\":\"/dev/ttyUSB0\"};\n").unwrap();
+    chosen.nmea_data = true;
+    assert!(matches!(chosen.get_response().unwrap(), Response::Devices { .. }));
+    assert!(matches!(chosen.get_response().unwrap(), Response::Watch(..)));
+    go.send(()).unwrap();
+    assert!(matches!(watcher.get_response().unwrap(), Response::Nmea(ref s) if s.kind == "GGA"));
+    assert!(matches!(chosen.get_response().unwrap(), Response::Nmea(ref s) if s.kind == "GGA"));
+    assert!(matches!(other.get_response(), Err(Error::Timeout)));
+
+    // stopping disconnects everyone, and the relay's threads finish
+    stopper.stop();
+    assert!(running.join().unwrap().is_ok());
+    assert!(matches!(watcher.get_response(), Err(Error::ConnectionClosed)));
+    assert!(GpsdConnection::new_checked(addr).is_err());
+}
+#[test]
+fn multi_gpsd() {
+    use std::io::Write;
+    use std::sync::mpsc;
+    use multi::{MultiGpsd, QualifyPaths};
+    let tpv = "{\"class\":\"TPV\",\"device\":\"/dev/ttyUSB0\",\"mode\":3,\"time\":\"2004-12-14T10:44:27.591Z\",\"ept\":0.005,\"lat\":59.3,\"lon\":18.0,\"alt\":78.2,\"speed\":0.1,\"climb\":0.2}\n";
+    let device = |resp: &Response| match *resp {
+        Response::Tpv(ref t) => t.device().map(String::from),
+        Response::Devices { ref devices, .. } => devices[0].path().map(String::from),
+        ref x => panic!("unexpected response: {:?}", x)
+    };
+    let (go, wait) = mpsc::channel();
+    let main = fake_gpsd(move |mut stream| {
+        stream.write_all(b"{\"class\":\"WATCH\",\"enable\":true,\"json\":true,\"remote\":\"gpsd://main:2947\"}\n").unwrap();
+        stream.write_all(b"{\"class\":\"DEVICES\",\"devices\":[{\"class\":\"DEVICE\",\"path\":\"/dev/ttyUSB0\"}]}\n").unwrap();
+        stream.write_all(tpv.as_bytes()).unwrap();
+        wait.recv().unwrap();
+        stream.write_all(tpv.as_bytes()).unwrap();
+    });
+    let main = GpsdConnection::new(main).unwrap();
+    let mut multi = MultiGpsd::new();
+    let main = multi.add("main", main);
+    let mut seen = vec![];
+    for _ in 0..3 {
+        let resp = multi.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(resp.source, main);
+        seen.push(resp.response);
+    }
+    // nothing to tell apart yet
+    assert_eq!(device(&seen[1]).as_deref(), Some("/dev/ttyUSB0"));
+    assert_eq!(device(&seen[2]).as_deref(), Some("/dev/ttyUSB0"));
+
+    let backup = fake_gpsd(move |mut stream| {
+        stream.write_all(b"{\"class\":\"DEVICES\",\"devices\":[{\"class\":\"DEVICE\",\"path\":\"/dev/ttyUSB0\"}]}\n").unwrap();
+        stream.write_all(b"{\"class\":\"TPV\",\"device\":\"/dev/ttyUSB0\",\"mode\":1,\"time\":\"2004-12-14T10:44:27.591Z\"}\n").unwrap();
+    });
+    let backup = multi.add("backup", GpsdConnection::new(backup).unwrap());
+    assert_eq!(multi.name(backup), "backup");
+    for _ in 0..2 {
+        let resp = multi.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(resp.source, backup);
+        assert_eq!(device(&resp.response).as_deref(), Some("backup:/dev/ttyUSB0"));
+    }
+    // now the path collides, main's is qualified too, with its remote URL
+    go.send(()).unwrap();
+    let resp = multi.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(resp.source, main);
+    assert_eq!(device(&resp.response).as_deref(), Some("gpsd://main:2947/dev/ttyUSB0"));
+    assert!(matches!(multi.recv(), Err(Error::ConnectionClosed)));
+    let health = multi.health(main);
+    assert!(!health.connected);
+    assert_eq!(health.responses, 4);
+    assert!(health.last_fix.is_some());
+    assert!(!health.is_healthy(Duration::from_secs(60)));
+    assert!(multi.health(backup).last_fix.is_none());
+
+    // data before the remote URL is known doesn't stop it being used later
+    let early = fake_gpsd(move |mut stream| {
+        stream.write_all(tpv.as_bytes()).unwrap();
+        stream.write_all(b"{\"class\":\"WATCH\",\"enable\":true,\"json\":true,\"remote\":\"gpsd://early:2947/\"}\n").unwrap();
+        stream.write_all(tpv.as_bytes()).unwrap();
+    });
+    let mut multi = MultiGpsd::new();
+    multi.set_qualify_paths(QualifyPaths::Always);
+    multi.add("early", GpsdConnection::new(early).unwrap());
+    let mut recv = || multi.recv_timeout(Duration::from_secs(5)).unwrap().response;
+    assert_eq!(device(&recv()).as_deref(), Some("early:/dev/ttyUSB0"));
+    assert!(matches!(recv(), Response::Watch(WatchObject { device: None, .. })));
+    assert_eq!(device(&recv()).as_deref(), Some("gpsd://early:2947/dev/ttyUSB0"));
+}
+#[test]
+fn geodesics() {
+    use types::Position;
+    fn dms(d: f64, m: f64, s: f64) -> f64 { d.signum() * (d.abs() + m / 60.0 + s / 3600.0) }
+    // Vincenty's own test case: Flinders Peak to Buninyong
+    let flinders = Position { lat: dms(-37.0, 57.0, 3.72030), lon: dms(144.0, 25.0, 29.52440), alt: None };
+    let buninyong = Position { lat: dms(-37.0, 39.0, 10.15610), lon: dms(143.0, 55.0, 35.38390), alt: None };
+    let g = flinders.geodesic_to(&buninyong);
+    assert!((g.distance - 54972.271).abs() < 1e-3, "{:?}", g);
+    assert!((g.initial_bearing - dms(306.0, 52.0, 5.37)).abs() < 1e-5, "{:?}", g);
+    assert!((g.final_bearing - dms(307.0, 10.0, 25.07)).abs() < 1e-5, "{:?}", g);
+    assert!((flinders.haversine_distance_to(&buninyong) / g.distance - 1.0).abs() < 0.005);
+
+    let dest = flinders.destination(g.initial_bearing, g.distance);
+    assert!((dest.lat - buninyong.lat).abs() < 1e-9 && (dest.lon - buninyong.lon).abs() < 1e-9, "{:?}", dest);
+    let back = geo::direct(flinders.lat, flinders.lon, g.initial_bearing, g.distance);
+    assert!((back.final_bearing - g.final_bearing).abs() < 1e-6);
+
+    // along the equator, and across the antimeridian
+    let a = Position { lat: 0.0, lon: 179.5, alt: None };
+    let b = Position { lat: 0.0, lon: -179.5, alt: None };
+    assert!((a.distance_to(&b) - 111319.491).abs() < 1e-3, "{}", a.distance_to(&b));
+    assert!((a.bearing_to(&b) - 90.0).abs() < 1e-9);
+    assert!((a.destination(90.0, 111319.491).lon - -179.5).abs() < 1e-6);
+    assert_eq!(a.distance_to(&a), 0.0);
+
+    // nearly antipodal, where Vincenty doesn't converge: Karney's example
+    assert!(geo::vincenty_inverse(-30.0, 0.0, 29.9, 179.8).is_none());
+    let g = geo::inverse(-30.0, 0.0, 29.9, 179.8);
+    assert!((g.distance - 19989832.8276).abs() < 1e-3, "{:?}", g);
+    assert!((g.initial_bearing - 161.890524736).abs() < 1e-7, "{:?}", g);
+    assert!((g.final_bearing - 18.090737246).abs() < 1e-7, "{:?}", g);
+    // the same geodesic every which way round
+    for &(lat1, lon1, lat2, lon2) in [(0.0, 0.0, 0.5, 179.7), (29.9, 179.8, -30.0, 0.0), (30.0, 10.0, -29.9, -170.2), (0.0, 0.0, 0.0, 179.9)].iter() {
+        assert!(geo::vincenty_inverse(lat1, lon1, lat2, lon2).is_none());
+        let g = geo::inverse(lat1, lon1, lat2, lon2);
+        let dest = geo::direct(lat1, lon1, g.initial_bearing, g.distance);
+        assert!((dest.lat - lat2).abs() < 1e-9 && geo::normalize_lon(dest.lon - lon2).abs() < 1e-9, "{:?} {:?}", g, dest);
+        assert!(geo::normalize_lon(dest.final_bearing - g.final_bearing).abs() < 1e-6, "{:?} {:?}", g, dest);
+        let back = geo::inverse(lat2, lon2, lat1, lon1);
+        assert!((back.distance - g.distance).abs() < 1e-6, "{:?} {:?}", g, back);
+    }
+
+    // works straight off a fix
+    let tpv: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":2,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":-37.950,"lon":144.425,"speed":0.1}"#).unwrap();
+    let fix = tpv.position().unwrap();
+    assert!((fix.distance_to(&buninyong) - 55000.0).abs() < 1000.0);
+}
+#[test]
+fn local_coordinates() {
+    use coords::{Ecef, Enu, LocalFrame, Ned};
+    use geo::{WGS84_A, WGS84_B};
+    use types::{Position, Velocity};
+    let origin = Ecef::from_geodetic(0.0, 0.0, 0.0);
+    assert!((origin.x - WGS84_A).abs() < 1e-6 && origin.y.abs() < 1e-6 && origin.z.abs() < 1e-6);
+    let pole = Ecef::from_geodetic(90.0, 0.0, 0.0);
+    assert!((pole.z - WGS84_B).abs() < 1e-6);
+    for &(lat, lon, hae) in [(59.345015, 18.054897, 101.4), (-37.95, 144.42, -12.0), (89.9999, -170.0, 5000.0), (-90.0, 0.0, 10.0), (0.0, 180.0, 0.0)].iter() {
+        let back = Ecef::from_geodetic(lat, lon, hae).to_geodetic();
+        assert!((back.lat - lat).abs() < 1e-9 && (back.lon - lon).abs() < 1e-9, "{:?}", back);
+        assert!((back.alt.unwrap() - hae).abs() < 1e-4, "{:?}", back);
+    }
+    // on (or right next to) the polar axis, where Zhu's method divides by zero
+    for &(x, z, lat, hae) in [(0.0, WGS84_B, 90.0, 0.0), (0.0, -WGS84_B - 250.0, -90.0, 250.0), (0.5, WGS84_B + 10.0, 90.0, 10.0)].iter() {
+        let back = Ecef { x, y: 0.0, z }.to_geodetic();
+        assert!((back.lat - lat).abs() < 1e-4 && (back.alt.unwrap() - hae).abs() < 1e-4, "{:?}", back);
+    }
+
+    let here = Position { lat: 59.345015, lon: 18.054897, alt: Some(45.0) };
+    let frame = LocalFrame::new(here);
+    let north = here.destination(0.0, 100.0);
+    let enu = frame.position_to_enu(&north);
+    assert!((enu.north - 100.0).abs() < 0.01 && enu.east.abs() < 1e-6, "{:?}", enu);
+    // the ground curves away below the tangent plane
+    assert!(enu.up < 0.0 && enu.up > -0.01, "{:?}", enu);
+    let ned = frame.position_to_ned(&here.destination(90.0, 50.0));
+    assert!((ned.east - 50.0).abs() < 0.01 && ned.north.abs() < 0.01, "{:?}", ned);
+    let back = frame.ned_to_position(Ned { north: 10.0, east: -20.0, down: -3.0 });
+    let again = frame.position_to_enu(&back);
+    assert!((again.north - 10.0).abs() < 1e-6 && (again.east + 20.0).abs() < 1e-6 && (again.up - 3.0).abs() < 1e-6);
+
+    let vel = Velocity { speed: 2.0, track: Some(90.0), climb: Some(0.5) };
+    let enu = vel.to_enu().unwrap();
+    assert!((enu.east - 2.0).abs() < 1e-12 && enu.north.abs() < 1e-12 && enu.up == 0.5);
+    let round = Velocity::from_enu(Enu { east: -1.0, north: -1.0, up: 0.0 });
+    assert!((round.track.unwrap() - 225.0).abs() < 1e-9);
+    assert!(Velocity { speed: 1.0, track: None, climb: None }.to_enu().is_none());
+    let v = frame.rotate_to_ecef(enu);
+    let v = frame.rotate_to_enu(v);
+    assert!((v.east - 2.0).abs() < 1e-12 && (v.up - 0.5).abs() < 1e-12);
+
+    // gpsd's ECEF fields are used if present, and agree with the lat/lon/altHAE
+    let tpv: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":3,"time":"2004-12-14T10:44:27.591Z","ept":0.005,"lat":59.345015,"lon":18.054897,"altHAE":101.4,"altMSL":78.2,"alt":78.2,"speed":0.1,"climb":0.0,"track":0.0,"geoidSep":23.2}"#).unwrap();
+    assert_eq!(tpv.hae(), Some(101.4));
+    let computed = tpv.ecef().unwrap();
+    let with_ecef: TpvResponse = serde_json::from_str(&format!(r#"{{"class":"TPV","mode":3,"lat":59.345015,"lon":18.054897,"ecefx":{},"ecefy":{},"ecefz":{},"ecefvx":0.1,"ecefvy":0.0,"ecefvz":0.0}}"#, computed.x, computed.y, computed.z)).unwrap();
+    let given = with_ecef.ecef().unwrap();
+    assert!((given.x - computed.x).abs() < 1e-6 && (given.z - computed.z).abs() < 1e-6);
+    assert_eq!(with_ecef.ecef_velocity(), Some(Ecef { x: 0.1, y: 0.0, z: 0.0 }));
+    let enu = tpv.to_enu(&LocalFrame::new(Position { alt: Some(101.4), ..here })).unwrap();
+    assert!(enu.up.abs() < 1e-6);
+    let vel = tpv.enu_velocity().unwrap();
+    assert!((vel.north - 0.1).abs() < 1e-12);
+}
+#[test]
+fn grid_references() {
+    use grid::{self, GridError, Mgrs, Utm};
+    use types::Position;
+    let eiffel = Position { lat: 48.8582, lon: 2.2945, alt: None };
+    let utm = eiffel.utm().unwrap();
+    assert_eq!((utm.zone, utm.band), (31, 'U'));
+    assert!((utm.easting - 448251.8).abs() < 0.5 && (utm.northing - 5411932.7).abs() < 0.5, "{:?}", utm);
+    assert_eq!(utm.to_string(), "31U 448252 5411933");
+    let mgrs = eiffel.mgrs().unwrap();
+    assert_eq!(mgrs.to_string(), "31U DQ 48251 11932");
+    assert_eq!(mgrs.to_string_with_precision(2), "31U DQ 48 11");
+    assert_eq!(mgrs.to_string_with_precision(0), "31U DQ");
+
+    // round trips, in both hemispheres and on both sides of the antimeridian
+    for &(lat, lon) in [(48.8582, 2.2945), (-33.8568, 151.2153), (0.0, -179.9999), (-79.9, 179.9), (83.9, -45.0), (59.345015, 18.054897)].iter() {
+        let utm = Utm::from_lat_lon(lat, lon).unwrap();
+        assert_eq!(utm.is_north(), lat >= 0.0);
+        let back = utm.to_position().unwrap();
+        assert!((back.lat - lat).abs() < 1e-8 && (back.lon - lon).abs() < 1e-8, "{:?} {:?}", utm, back);
+        let parsed: Utm = utm.to_string().parse().unwrap();
+        assert_eq!((parsed.zone, parsed.band), (utm.zone, utm.band));
+        let mgrs = Mgrs::from_lat_lon(lat, lon).unwrap();
+        let parsed: Mgrs = mgrs.to_string().parse().unwrap();
+        let back = parsed.to_position().unwrap();
+        assert!(back.distance_to(&Position { lat, lon, alt: None }) < 2.0, "{} {:?}", mgrs, back);
+    }
+    let sydney: Utm = "56 H 334873 6252266".parse().unwrap();
+    assert!(!sydney.is_north());
+    let pos = sydney.to_position().unwrap();
+    assert!((pos.lat + 33.8568).abs() < 1e-3 && (pos.lon - 151.2153).abs() < 1e-3, "{:?}", pos);
+    let compact: Mgrs = "31udq4825111932".parse().unwrap();
+    assert_eq!(compact, "31U DQ 48251 11932".parse().unwrap());
+    // a 100km square parses to its south-west corner
+    let square: Mgrs = "31U DQ".parse().unwrap();
+    assert_eq!((square.easting, square.northing), (0.0, 0.0));
+
+    // zone boundaries, and the Norway and Svalbard exceptions
+    assert_eq!(Utm::from_lat_lon(50.0, 5.999999).unwrap().zone, 31);
+    assert_eq!(Utm::from_lat_lon(50.0, 6.0).unwrap().zone, 32);
+    assert_eq!(Utm::from_lat_lon(60.0, 5.0).unwrap().zone, 32);
+    assert_eq!(Utm::from_lat_lon(55.9, 5.0).unwrap().zone, 31);
+    assert_eq!(Utm::from_lat_lon(60.0, 2.9).unwrap().zone, 31);
+    assert_eq!(Utm::from_lat_lon(78.0, 8.9).unwrap().zone, 31);
+    assert_eq!(Utm::from_lat_lon(78.0, 10.0).unwrap().zone, 33);
+    assert_eq!(Utm::from_lat_lon(78.0, 25.0).unwrap().zone, 35);
+    assert_eq!(Utm::from_lat_lon(78.0, 40.0).unwrap().zone, 37);
+    assert_eq!(Utm::from_lat_lon(78.0, 42.0).unwrap().zone, 38);
+    assert_eq!(Utm::from_lat_lon(10.0, 180.0).unwrap().zone, 1);
+    let svalbard = Mgrs::from_lat_lon(78.2, 15.6).unwrap();
+    let back = svalbard.to_position().unwrap();
+    assert!((back.lat - 78.2).abs() < 1e-4 && (back.lon - 15.6).abs() < 1e-4);
+    assert_eq!(Utm::from_lat_lon(84.0, 0.0).unwrap().band, 'X');
+    assert_eq!(Utm::from_lat_lon(-80.0, 0.0).unwrap().band, 'C');
+
+    // UTM and MGRS don't cover the poles
+    assert_eq!(Utm::from_lat_lon(84.0001, 0.0), Err(GridError::OutOfRange));
+    assert_eq!(Utm::from_lat_lon(-80.0001, 0.0), Err(GridError::OutOfRange));
+    assert_eq!(Mgrs::from_lat_lon(90.0, 0.0), Err(GridError::OutOfRange));
+    assert_eq!(Utm::from_lat_lon(f64::NAN, 0.0), Err(GridError::OutOfRange));
+    for bad in ["32X 500000 8600000", "61U 1 2", "31A 1 2", "31U 448252"].iter() {
+        assert!(bad.parse::<Utm>().is_err(), "{}", bad);
+    }
+    for bad in ["31U DI 1 2", "31U DQ 123 45", "31U IQ", "31U", "ZZ", "31éAB123", "31Ué 1 2", "31UDé12"].iter() {
+        assert!(bad.parse::<Mgrs>().is_err(), "{}", bad);
+    }
+
+    // Maidenhead
+    let munich = Position { lat: 48.14666, lon: 11.60833, alt: None };
+    assert_eq!(munich.maidenhead(3).unwrap(), "JN58td");
+    assert_eq!(munich.maidenhead(1).unwrap(), "JN");
+    assert_eq!(munich.maidenhead(4).unwrap().len(), 8);
+    let centre = grid::from_maidenhead("jn58TD").unwrap();
+    assert!((centre.lat - 48.1458).abs() < 1e-3 && (centre.lon - 11.625).abs() < 1e-3, "{:?}", centre);
+    assert_eq!(grid::from_maidenhead(&munich.maidenhead(5).unwrap()).unwrap().maidenhead(5), munich.maidenhead(5));
+    assert_eq!(grid::to_maidenhead(90.0, 180.0, 2).unwrap(), "AR09");
+    assert_eq!(grid::to_maidenhead(-90.0, -180.0, 2).unwrap(), "AA00");
+    assert_eq!(grid::to_maidenhead(91.0, 0.0, 2), Err(GridError::OutOfRange));
+    for bad in ["", "J", "SA", "JN5", "JN5x", "JN58ty"].iter() {
+        assert!(grid::from_maidenhead(bad).is_err(), "{}", bad);
+    }
+
+    // straight off a fix
+    let tpv: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":2,"lat":48.14666,"lon":11.60833}"#).unwrap();
+    assert_eq!(tpv.maidenhead(3).unwrap(), "JN58td");
+    assert_eq!(tpv.utm().unwrap().zone, 32);
+    assert!(tpv.mgrs().unwrap().to_string().starts_with("32U PU"));
+    let nofix: TpvResponse = serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap();
+    assert_eq!(nofix.utm(), Err(GridError::NoFix));
+}
+#[test]
+fn geofencing() {
+    use geofence::{FenceId, Geofence, GeofenceEventKind};
+    use types::Position;
+    let fix = |device: &str, secs: u32, lat: f64, lon: f64, err: f64| -> Response {
+        serde_json::from_str(&format!(r#"{{"class":"TPV","device":"{}","mode":2,"time":"2020-01-01T00:{:02}:{:02}Z","ept":0.005,"lat":{},"lon":{},"epx":{},"epy":{}}}"#, device, secs / 60, secs % 60, lat, lon, err, err)).unwrap()
+    };
+    let depot = Position { lat: 59.345, lon: 18.055, alt: None };
+    let mut fences = Geofence::new();
+    let circle = fences.add_circle("depot", depot, 100.0);
+    fences.set_dwell(circle, Some(Duration::from_secs(60)));
+    assert_eq!(fences.name(circle), "depot");
+    let kinds = |events: Vec<geofence::GeofenceEvent>| events.into_iter().map(|e| (e.fence, e.kind)).collect::<Vec<_>>();
+
+    // starting outside says nothing; driving in and out does
+    let outside = depot.destination(90.0, 300.0);
+    assert!(fences.handle(&fix("/dev/a", 0, outside.lat, outside.lon, 5.0)).is_empty());
+    assert_eq!(fences.is_inside(Some("/dev/a"), circle), Some(false));
+    let edge = depot.destination(90.0, 97.0);
+    // 3m inside, but the error's 5m: no change
+    assert!(fences.handle(&fix("/dev/a", 1, edge.lat, edge.lon, 5.0)).is_empty());
+    // same place, better fix
+    let events = fences.handle(&fix("/dev/a", 2, edge.lat, edge.lon, 2.0));
+    assert_eq!(events[0].device.as_deref(), Some("/dev/a"));
+    assert_eq!(kinds(events), vec![(circle, GeofenceEventKind::Enter)]);
+    // wandering back over the line within the error doesn't flap
+    let over = depot.destination(90.0, 103.0);
+    assert!(fences.handle(&fix("/dev/a", 3, over.lat, over.lon, 5.0)).is_empty());
+    assert_eq!(fences.is_inside(Some("/dev/a"), circle), Some(true));
+    // dwelling only once per visit
+    assert!(fences.handle(&fix("/dev/a", 61, depot.lat, depot.lon, 5.0)).is_empty());
+    assert_eq!(kinds(fences.handle(&fix("/dev/a", 62, depot.lat, depot.lon, 5.0))), vec![(circle, GeofenceEventKind::Dwell)]);
+    assert!(fences.handle(&fix("/dev/a", 120, depot.lat, depot.lon, 5.0)).is_empty());
+    assert_eq!(kinds(fences.handle(&fix("/dev/a", 121, outside.lat, outside.lon, 5.0))), vec![(circle, GeofenceEventKind::Exit)]);
+    // other devices are tracked separately; starting inside is an entry
+    assert_eq!(fences.is_inside(Some("/dev/b"), circle), None);
+    assert_eq!(kinds(fences.handle(&fix("/dev/b", 0, depot.lat, depot.lon, 5.0))), vec![(circle, GeofenceEventKind::Enter)]);
+    assert_eq!(fences.is_inside(Some("/dev/a"), circle), Some(false));
+    // with a margin, being just inside isn't enough
+    fences.set_margin(10.0);
+    assert!(fences.handle(&fix("/dev/a", 130, edge.lat, edge.lon, 0.0)).is_empty());
+    fences.set_margin(0.0);
+
+    // a polygon straddling the antimeridian, in Fiji
+    let square = [(-16.0, 179.9), (-16.0, -179.9), (-16.2, -179.9), (-16.2, 179.9)]
+        .iter().map(|&(lat, lon)| Position { lat, lon, alt: None }).collect::<Vec<_>>();
+    let fiji = fences.add_polygon("fiji", &square);
+    assert_eq!(fences.fences().collect::<Vec<_>>(), vec![circle, FenceId(1)]);
+    assert!(fences.contains(fiji, &Position { lat: -16.1, lon: 179.95, alt: None }));
+    assert!(fences.contains(fiji, &Position { lat: -16.1, lon: -179.95, alt: None }));
+    assert!(fences.contains(fiji, &Position { lat: -16.1, lon: 180.0, alt: None }));
+    assert!(!fences.contains(fiji, &Position { lat: -16.1, lon: 179.8, alt: None }));
+    assert!(!fences.contains(fiji, &Position { lat: -15.9, lon: 180.0, alt: None }));
+    // the far side of the world doesn't sneak in through the tangent plane
+    assert!(!fences.contains(fiji, &Position { lat: 16.1, lon: 0.0, alt: None }));
+    // depth is the distance to the nearest edge: 0.05° of longitude here
+    let depth = fences.depth(fiji, &Position { lat: -16.1, lon: -179.95, alt: None });
+    assert!((depth - 5350.0).abs() < 50.0, "{}", depth);
+    let events = fences.handle(&fix("/dev/a", 140, -16.1, 179.95, 5.0));
+    assert_eq!(kinds(events), vec![(fiji, GeofenceEventKind::Enter)]);
+    assert_eq!(kinds(fences.handle(&fix("/dev/a", 141, -16.1, 179.8, 5.0))), vec![(fiji, GeofenceEventKind::Exit)]);
+
+    // round the pole
+    let cap = [0.0, 90.0, 180.0, 270.0].iter().map(|&lon| Position { lat: 89.0, lon, alt: None }).collect::<Vec<_>>();
+    let pole = fences.add_polygon("pole", &cap);
+    assert!(fences.contains(pole, &Position { lat: 89.9, lon: 123.0, alt: None }));
+    assert!(!fences.contains(pole, &Position { lat: 88.9, lon: 45.0, alt: None }));
+    // fences added later start out unknown for existing devices
+    assert_eq!(fences.is_inside(Some("/dev/a"), pole), None);
+    // positionless responses are ignored
+    assert!(fences.handle(&Response::Tpv(serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap())).is_empty());
+}
+/// The fixes in `stockholm_walk.nmea`, as the TPVs gpsd would make of them
+/// (taking the UERE to be 5m for the error estimates).
+fn stockholm_walk() -> Vec<TpvResponse> {
+    use nmea::NmeaSentence;
+    let log = ::std::fs::read_to_string("stockholm_walk.nmea").unwrap();
+    let degrees = |s: &str, hemi: &str| {
+        let v: f64 = s.parse().unwrap();
+        let deg = (v / 100.0).floor() + (v % 100.0) / 60.0;
+        if hemi == "S" || hemi == "W" { -deg } else { deg }
+    };
+    let (mut alt, mut mode, mut hdop, mut vdop) = (None, 1, 0.0, 0.0);
+    let mut ret = vec![];
+    for line in log.lines() {
+        let s = match NmeaSentence::parse(line) {
+            Some(s) => s,
+            None => continue
+        };
+        match &s.kind as &str {
+            "GGA" => alt = s.field(8).map(|a| a.parse::<f64>().unwrap()),
+            "GSA" => {
+                mode = s.field(1).unwrap().parse().unwrap();
+                hdop = s.field(15).unwrap().parse().unwrap();
+                vdop = s.field(16).unwrap().parse().unwrap();
+            },
+            "RMC" => {
+                let (t, d) = (s.field(0).unwrap(), s.field(8).unwrap());
+                let time = format!("20{}-{}-{}T{}:{}:{}Z", &d[4..6], &d[2..4], &d[0..2], &t[0..2], &t[2..4], &t[4..]);
+                let mut tpv = json!({
+                    "class": "TPV", "mode": mode, "time": time, "ept": 0.005,
+                    "lat": degrees(s.field(2).unwrap(), s.field(3).unwrap()),
+                    "lon": degrees(s.field(4).unwrap(), s.field(5).unwrap()),
+                    "epx": hdop * 5.0, "epy": hdop * 5.0,
+                    "speed": s.field(6).unwrap().parse::<f64>().unwrap() * 0.514444,
+                    "track": s.field(7).unwrap().parse::<f64>().unwrap(),
+                    "eps": hdop * 0.1
+                });
+                if mode == 3 {
+                    tpv["alt"] = json!(alt.unwrap());
+                    tpv["epv"] = json!(vdop * 5.0);
+                    tpv["climb"] = json!(0.0);
+                }
+                ret.push(serde_json::from_value(tpv).unwrap());
+            },
+            _ => {}
+        }
+    }
+    ret
+}
+#[test]
+fn track_statistics() {
+    use track::{BoundingBox, TrackStats};
+    use types::Position;
+    let walk = stockholm_walk();
+    assert_eq!(walk.len(), 675);
+    let mut stats = TrackStats::new();
+    for tpv in walk.iter() {
+        stats.handle(&Response::Tpv(tpv.clone()));
+    }
+    let s = stats.summary();
+    // the first RMC comes before any GSA, so has no fix
+    assert_eq!(s.fixes, 674);
+    assert_eq!(s.start.unwrap().to_rfc3339(), "2004-12-14T10:44:27.591+00:00");
+    let span = (s.end.unwrap() - s.start.unwrap()).to_std().unwrap();
+    assert_eq!(span.as_secs(), 672);
+    assert_eq!(s.moving_time + s.stopped_time, span);
+    // it starts off standing still for a bit
+    assert!(s.stopped_time.as_secs() > 20, "{:?}", s);
+    assert!(s.moving_time > s.stopped_time);
+
+    // the raw distance includes all the jitter
+    let raw: f64 = walk[1..].windows(2).map(|w| w[0].position().unwrap().distance_to(&w[1].position().unwrap())).sum();
+    assert!(s.distance < raw && s.distance > raw * 0.6, "{} vs {}", s.distance, raw);
+    assert!(s.distance > 700.0 && s.distance < 1100.0, "{:?}", s);
+    assert!((s.max_speed - 5.289288 * 0.514444).abs() < 1e-6);
+    assert!((s.avg_speed - s.distance / s.moving_time.as_secs_f64()).abs() < 1e-9);
+    assert!(s.avg_speed > 1.0 && s.avg_speed < 2.0, "{:?}", s);
+    // ...and so does the raw climb
+    let alts: Vec<f64> = walk.iter().filter_map(|t| t.alt()).collect();
+    let raw_gain: f64 = alts.windows(2).map(|w| (w[1] - w[0]).max(0.0)).sum();
+    assert!(s.elevation_gain < raw_gain / 2.0, "{} vs {}", s.elevation_gain, raw_gain);
+    assert_eq!(s.min_alt, alts.iter().cloned().reduce(f64::min));
+    assert_eq!(s.max_alt, alts.iter().cloned().reduce(f64::max));
+    let b = s.bounds.unwrap();
+    assert!(walk.iter().all(|t| b.contains(&t.position().unwrap())));
+    assert!(b.north - b.south < 0.01 && b.east - b.west < 0.01 && b.west < b.east, "{:?}", b);
+
+    // without the error estimates, all the jitter counts
+    let mut naive = TrackStats::new();
+    for tpv in walk.iter() {
+        let mut tpv = serde_json::to_value(tpv).unwrap();
+        for key in ["epx", "epy", "epv", "eps"].iter() {
+            tpv.as_object_mut().unwrap().remove(*key);
+        }
+        naive.update(&serde_json::from_value(tpv).unwrap());
+    }
+    assert!((naive.summary().distance - raw).abs() < 1e-6);
+
+    // no fix, no statistics; and across the antimeridian, the box is small
+    stats.reset();
+    stats.update(&serde_json::from_str(r#"{"class":"TPV","mode":1,"time":"2020-01-01T00:00:00Z"}"#).unwrap());
+    stats.update(&serde_json::from_str(r#"{"class":"TPV","mode":2,"time":"2020-01-01T00:00:01Z","ept":0.005,"lat":-16.1,"lon":179.9,"speed":5.0}"#).unwrap());
+    stats.update(&serde_json::from_str(r#"{"class":"TPV","mode":2,"time":"2020-01-01T00:00:11Z","ept":0.005,"lat":-16.0,"lon":-179.9,"speed":5.0}"#).unwrap());
+    let s = stats.summary();
+    assert_eq!(s.fixes, 2);
+    assert_eq!(s.moving_time, Duration::from_secs(10));
+    assert_eq!(s.bounds, Some(BoundingBox { south: -16.1, west: 179.9, north: -16.0, east: -179.9 }));
+    assert!(s.bounds.unwrap().contains(&Position { lat: -16.05, lon: 180.0 - 360.0, alt: None }));
+    assert!(!s.bounds.unwrap().contains(&Position { lat: -16.05, lon: 0.0, alt: None }));
+    assert!(s.distance > 20000.0 && s.distance < 25000.0);
+    // a position with no mode counts, but not one with a mode saying no fix
+    stats.update(&serde_json::from_str(r#"{"class":"TPV","time":"2020-01-01T00:00:21Z","ept":0.005,"lat":-16.0,"lon":-179.8}"#).unwrap());
+    stats.update(&serde_json::from_str(r#"{"class":"TPV","mode":0,"time":"2020-01-01T00:00:31Z","ept":0.005,"lat":-16.0,"lon":-179.7}"#).unwrap());
+    assert_eq!(stats.summary().fixes, 3);
+    assert_eq!(stats.summary().bounds.unwrap().east, -179.8);
+}
+#[test]
+fn kalman_filter() {
+    use chrono::TimeZone;
+    use kalman::KalmanFilter;
+    use types::Position;
+    let start = chrono::Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let fix = |secs: f64, pos: Position, err: f64, speed: Option<(f64, f64)>| -> TpvResponse {
+        let time = start + chrono::Duration::milliseconds((secs * 1000.0) as i64);
+        let mut tpv = json!({
+            "class": "TPV", "mode": 3, "time": time.to_rfc3339(), "ept": 0.005,
+            "lat": pos.lat, "lon": pos.lon, "alt": pos.alt.unwrap(),
+            "epx": err, "epy": err, "epv": err * 1.5
+        });
+        if let Some((speed, track)) = speed {
+            tpv["speed"] = json!(speed);
+            tpv["track"] = json!(track);
+            tpv["climb"] = json!(0.0);
+            tpv["eps"] = json!(0.5);
+            tpv["epc"] = json!(0.5);
+        }
+        serde_json::from_value(tpv).unwrap()
+    };
+    // a stationary receiver, jittering about by up to 10m
+    let here = Position { lat: 59.345, lon: 18.055, alt: Some(40.0) };
+    let mut seed = 12345u32;
+    let mut jitter = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as f64 / 65536.0 * 20.0 - 10.0
+    };
+    let mut kf = KalmanFilter::new();
+    let (mut raw_sq, mut smooth_sq) = (0.0, 0.0);
+    for i in 0..120 {
+        let pos = here.destination((jitter() + 10.0) * 18.0, jitter().abs());
+        let pos = Position { alt: Some(40.0 + jitter()), ..pos };
+        let est = kf.update(&fix(i as f64, pos, 10.0, Some((0.0, 0.0)))).unwrap();
+        if i >= 60 {
+            raw_sq += pos.distance_to(&here).powi(2);
+            smooth_sq += est.position.distance_to(&here).powi(2);
+        }
+    }
+    assert!(smooth_sq < raw_sq / 9.0, "{} vs {}", smooth_sq, raw_sq);
+    let est = kf.estimate().unwrap();
+    assert!((est.position.alt.unwrap() - 40.0).abs() < 2.0, "{:?}", est);
+    assert!(est.position_std_dev().east < 10.0 / 1.96);
+    assert!(est.velocity.east.hypot(est.velocity.north) < 0.2);
+
+    // a noisy fix barely moves the estimate; a precise one moves it a lot
+    let away = here.destination(0.0, 30.0);
+    let mut noisy = kf.clone();
+    let moved = noisy.update(&fix(120.0, Position { alt: Some(40.0), ..away }, 1000.0, None)).unwrap();
+    assert!(moved.position.distance_to(&est.position) < 1.0);
+    let moved = kf.update(&fix(120.0, Position { alt: Some(40.0), ..away }, 0.5, None)).unwrap();
+    assert!(moved.position.distance_to(&away) < 2.0, "{:?}", moved);
+    // old and positionless fixes are ignored
+    assert!(kf.update(&fix(100.0, here, 1.0, None)).is_none());
+    assert!(kf.update(&serde_json::from_str(r#"{"class":"TPV","mode":1,"time":"2020-01-01T00:03:00Z"}"#).unwrap()).is_none());
+    assert!(kf.handle(&Response::Tpv(fix(121.0, Position { alt: Some(40.0), ..away }, 5.0, None))).is_some());
+
+    // driving east at 20m/s for 20km, past the point where the frame moves
+    let mut kf = KalmanFilter::new();
+    let mut last = None;
+    for i in 0..1000 {
+        let truth = Position { alt: Some(40.0), ..here.destination(90.0, 20.0 * i as f64) };
+        let pos = Position { alt: Some(40.0), ..truth.destination((jitter() + 10.0) * 18.0, jitter().abs() / 2.0) };
+        last = kf.update(&fix(i as f64, pos, 5.0, Some((20.0, 90.0))));
+        let est = last.unwrap();
+        assert!(est.position.distance_to(&truth) < 10.0, "{}: {:?}", i, est);
+    }
+    let est = last.unwrap();
+    assert!((est.velocity.east - 20.0).abs() < 0.5 && est.velocity.north.abs() < 0.5, "{:?}", est.velocity);
+    // dead reckoning
+    let ahead = kf.predict(est.time + chrono::Duration::seconds(5)).unwrap();
+    let moved = est.position.distance_to(&ahead.position);
+    assert!((moved - 100.0).abs() < 3.0, "{}", moved);
+    assert!(ahead.position_std_dev().east > est.position_std_dev().east);
+    assert_eq!(kf.predict(start).unwrap(), est);
+    assert_eq!(kf.estimate().unwrap(), est);
+    let c = est.covariance;
+    for (i, row) in c.iter().enumerate() {
+        for (j, x) in row.iter().enumerate() {
+            assert_eq!(*x, c[j][i]);
+        }
+    }
+
+    // far north, with much bigger errors east-west than north-south: when the
+    // frame moves, the covariance turns with it, so it's the same in ECEF
+    use coords::{Enu, LocalFrame};
+    let in_ecef = |frame: &LocalFrame, c: &[[f64; 6]; 6], at: usize| {
+        let units = [Enu { east: 1.0, north: 0.0, up: 0.0 }, Enu { east: 0.0, north: 1.0, up: 0.0 }, Enu { east: 0.0, north: 0.0, up: 1.0 }];
+        let m: Vec<[f64; 3]> = units.iter().map(|u| { let v = frame.rotate_to_ecef(*u); [v.x, v.y, v.z] }).collect();
+        let mut ret = [[0.0; 3]; 3];
+        for (i, row) in ret.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..3).flat_map(|k| (0..3).map(move |l| (k, l))).map(|(k, l)| m[k][i] * c[at + k][at + l] * m[l][j]).sum();
+            }
+        }
+        ret
+    };
+    let north = Position { lat: 80.0, lon: 0.0, alt: Some(0.0) };
+    let skewed = |secs: f64, pos: Position, err: f64| -> TpvResponse {
+        let mut tpv = serde_json::to_value(fix(secs, pos, err, Some((20.0, 90.0)))).unwrap();
+        tpv["epx"] = json!(err * 50.0);
+        serde_json::from_value(tpv).unwrap()
+    };
+    let mut kf = KalmanFilter::new();
+    for i in 0..500 {
+        kf.update(&skewed(i as f64, north.destination(90.0, 20.0 * i as f64), 1.0)).unwrap();
+    }
+    let before = kf.predict(start + chrono::Duration::seconds(501)).unwrap();
+    let mut after = kf.update(&fix(501.0, north.destination(90.0, 10020.0), 1e6, None)).unwrap();
+    after.position.alt = Some(0.0);
+    let (old_frame, new_frame) = (LocalFrame::new(north), LocalFrame::new(after.position));
+    assert!(after.covariance[0][1].abs() > 1e-3, "{:?}", after.covariance);
+    for &at in [0, 3].iter() {
+        let (b, a) = (in_ecef(&old_frame, &before.covariance, at), in_ecef(&new_frame, &after.covariance, at));
+        let scale = b.iter().flat_map(|r| r.iter()).fold(0.0f64, |m, x| m.max(x.abs()));
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((a[i][j] - b[i][j]).abs() < scale * 1e-4, "{}: {:?} vs {:?}", at, a, b);
+            }
+        }
+    }
+
+    // altitudes are heights above the ellipsoid
+    kf.reset();
+    let mut tpv = serde_json::to_value(fix(0.0, here, 5.0, None)).unwrap();
+    tpv["altMSL"] = json!(40.0);
+    tpv["geoidSep"] = json!(22.5);
+    let est = kf.update(&serde_json::from_value(tpv).unwrap()).unwrap();
+    assert!((est.position.alt.unwrap() - 62.5).abs() < 1e-6, "{:?}", est);
+
+    // a 2D fix to start with leaves the altitude unknown
+    kf.reset();
+    assert!(kf.estimate().is_none());
+    let est = kf.update(&serde_json::from_str(r#"{"class":"TPV","mode":2,"time":"2020-01-01T00:00:00Z","ept":0.005,"lat":59.345,"lon":18.055}"#).unwrap()).unwrap();
+    assert_eq!(est.position.alt, None);
+    assert!((est.position.lat - 59.345).abs() < 1e-9);
+    let est = kf.update(&fix(1.0, here, 5.0, None)).unwrap();
+    assert!((est.position.alt.unwrap() - 40.0).abs() < 1e-6);
+
+    // the stockholm walk: standing still for the first few fixes, the jitter
+    // is smoothed out
+    let walk = stockholm_walk();
+    let mut kf = KalmanFilter::new();
+    let mut smoothed = vec![];
+    for tpv in walk.iter() {
+        if let Some(est) = kf.update(tpv) {
+            smoothed.push(est.position);
+        }
+    }
+    assert_eq!(smoothed.len(), 674);
+    let path = |ps: &[Position]| ps.windows(2).map(|w| w[0].distance_to(&w[1])).sum::<f64>();
+    let raw: Vec<Position> = walk[1..].iter().map(|t| t.position().unwrap()).collect();
+    assert!(path(&smoothed[..5]) < path(&raw[..5]) / 2.0, "{} vs {}", path(&smoothed[..5]), path(&raw[..5]));
+    assert!(smoothed.iter().zip(raw.iter()).all(|(s, r)| s.distance_to(r) < 40.0));
+}
+#[test]
+fn position_averaging() {
+    use survey::PositionAverager;
+    use types::Position;
+    let antenna = Position { lat: 51.4779, lon: -0.0015, alt: Some(45.0) };
+    let fix = |mode: u8, pos: Position, err: f64| -> TpvResponse {
+        serde_json::from_value(json!({
+            "class": "TPV", "mode": mode, "time": "2020-01-01T00:00:00Z", "ept": 0.005,
+            "lat": pos.lat, "lon": pos.lon, "alt": pos.alt.unwrap(),
+            "epx": err, "epy": err, "epv": err * 1.5
+        })).unwrap()
+    };
+    // roughly normal noise, from the sum of a few uniform variables
+    let mut seed = 42u32;
+    let mut uniform = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as f64 / 65536.0 - 0.5
+    };
+    let mut normal = || (0..12).map(|_| uniform()).sum::<f64>();
+    let mut avg = PositionAverager::new();
+    assert!(avg.result().is_none());
+    let sigma = 2.0;
+    for i in 0..600 {
+        let (e, n, u) = (normal() * sigma, normal() * sigma, normal() * sigma * 1.5);
+        let mut pos = antenna.destination(90.0, e).destination(0.0, n);
+        pos.alt = Some(45.0 + u);
+        avg.update(&fix(3, pos, 4.0));
+        // every so often, a multipath outlier
+        if i % 100 == 50 {
+            let mut bad = antenna.destination(45.0, 40.0);
+            bad.alt = Some(45.0);
+            assert!(!avg.update(&fix(3, bad, 4.0)));
+        }
+        if i == 30 {
+            let r = avg.result().unwrap();
+            assert_eq!(r.drift, None);
+            assert!(!r.is_converged(1.0));
+        }
+    }
+    // 2D fixes aren't used
+    assert!(!avg.update(&fix(2, antenna, 4.0)));
+    assert!(!avg.handle(&Response::Tpv(serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap())));
+    let r = avg.result().unwrap();
+    // all the outliers, and (at 3 sigma) a few unlucky good fixes
+    assert!(r.rejected >= 6 && r.rejected <= 12, "{:?}", r);
+    assert_eq!(r.samples + r.rejected, 606);
+    assert!(r.position.distance_to(&antenna) < 0.3, "{:?}", r);
+    assert!((r.position.alt.unwrap() - 45.0).abs() < 0.5, "{:?}", r);
+    assert!((r.std_dev.east - sigma).abs() < 0.2 && (r.std_dev.north - sigma).abs() < 0.2, "{:?}", r);
+    assert!((r.std_dev.up - sigma * 1.5).abs() < 0.3, "{:?}", r);
+    // for a circular normal distribution, CEP50 is 1.1774 sigma
+    assert!((r.cep50 - 1.1774 * sigma).abs() < 0.25, "{:?}", r);
+    assert!(r.cep95 > r.cep50 * 2.0 && r.r95 > r.cep95);
+    assert!(r.mean_error < 0.3);
+    assert!(r.drift.unwrap() < 0.3);
+    assert!(r.is_converged(0.5));
+    assert!(!r.is_converged(0.01));
+
+    // better fixes count for more
+    let mut avg = PositionAverager::new();
+    let east = Position { alt: Some(45.0), ..antenna.destination(90.0, 10.0) };
+    avg.update(&fix(3, antenna, 1.0));
+    avg.update(&fix(3, east, 10.0));
+    let r = avg.result().unwrap();
+    let off = antenna.distance_to(&r.position);
+    assert!((off - 10.0 / 101.0).abs() < 0.01, "{}", off);
+    avg.reset();
+    assert!(avg.result().is_none());
+}
+/// A writer that counts how many times it's been flushed.
+#[derive(Default)]
+struct FlushCounter {
+    data: Vec<u8>,
+    flushes: usize
+}
+impl Write for FlushCounter {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+#[test]
+fn gpx_writer() {
+    use gpx::GpxWriter;
+    let walk = stockholm_walk();
+    let sky: Response = serde_json::from_str(r#"{"class":"SKY","device":"/dev/ttyUSB0","hdop":3.3,"vdop":4.5,"pdop":5.6,"satellites":[{"PRN":5,"el":70,"az":125,"ss":39,"used":true},{"PRN":24,"el":37,"az":83,"ss":43,"used":true},{"PRN":1,"el":27,"az":307,"ss":0,"used":false}]}"#).unwrap();
+    let mut gpx = GpxWriter::with_name(FlushCounter::default(), "Stockholm <walk> & back").unwrap();
+    for tpv in walk[..3].iter() {
+        gpx.handle(&Response::Tpv(tpv.clone())).unwrap();
+    }
+    // written and flushed as we go
+    let so_far = String::from_utf8(gpx.get_ref().data.clone()).unwrap();
+    assert_eq!(so_far.matches("<trkpt ").count(), 2);
+    assert!(so_far.ends_with("</trkpt>\n"));
+    let flushes = gpx.get_ref().flushes;
+    gpx.handle(&sky).unwrap();
+    gpx.handle(&Response::Tpv(walk[3].clone())).unwrap();
+    assert_eq!(gpx.get_ref().flushes, flushes + 1);
+    // losing the fix ends the segment
+    gpx.handle(&Response::Tpv(serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap())).unwrap();
+    gpx.handle(&Response::Tpv(serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap())).unwrap();
+    for tpv in walk[4..].iter() {
+        gpx.write_tpv(tpv).unwrap();
+    }
+    let out = String::from_utf8(gpx.finish().unwrap().data).unwrap();
+    assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\" creator=\"unbounded-gpsd\""));
+    assert!(out.contains("<name>Stockholm &lt;walk&gt; &amp; back</name>"));
+    assert!(out.ends_with("  </trkseg>\n </trk>\n</gpx>\n"));
+    assert_eq!(out.matches("<trkpt ").count(), 674);
+    assert_eq!(out.matches("</trkpt>").count(), 674);
+    assert_eq!(out.matches("<trkseg>").count(), 2);
+    assert_eq!(out.matches("</trkseg>").count(), 2);
+    // the first points come before any SKY
+    let first = &out[out.find("<trkpt ").unwrap()..out.find("</trkpt>").unwrap()];
+    assert_eq!(first, "<trkpt lat=\"59.345015\" lon=\"18.054896666666664\">
+    <ele>78.2</ele>
+    <time>2004-12-14T10:44:27.591Z</time>
+    <fix>3d</fix>
+    <extensions>
+     <gpxtpx:TrackPointExtension>
+      <gpxtpx:speed>0.07528630718</gpxtpx:speed>
+      <gpxtpx:course>320.93</gpxtpx:course>
+     </gpxtpx:TrackPointExtension>
+    </extensions>
+   ");
+    assert!(out.contains("    <fix>3d</fix>\n    <sat>2</sat>\n    <hdop>3.3</hdop>\n    <vdop>4.5</vdop>\n    <pdop>5.6</pdop>\n"));
+    assert_eq!(out.matches("<fix>2d</fix>").count(), 39);
+    assert_eq!(out.matches("<ele>").count(), 674 - 39);
+
+    // newer fields, and a DGPS fix
+    let mut gpx = GpxWriter::new(vec![]).unwrap();
+    gpx.handle(&serde_json::from_str(r#"{"class":"SKY","uSat":9,"satellites":[]}"#).unwrap()).unwrap();
+    gpx.write_tpv(&serde_json::from_str(r#"{"class":"TPV","mode":3,"status":2,"lat":1.5,"lon":-2.25,"alt":10.0,"altMSL":12.5,"geoidSep":-2.5,"dgpsAge":3.0,"dgpsSta":12}"#).unwrap()).unwrap();
+    let out = String::from_utf8(gpx.finish().unwrap()).unwrap();
+    assert!(!out.contains("<name>"));
+    assert!(out.contains("   <trkpt lat=\"1.5\" lon=\"-2.25\">\n    <ele>12.5</ele>\n    <geoidheight>-2.5</geoidheight>\n    <fix>dgps</fix>\n    <sat>9</sat>\n    <ageofdgpsdata>3</ageofdgpsdata>\n    <dgpsid>12</dgpsid>\n   </trkpt>\n"), "{}", out);
+    // an empty track is still valid
+    let out = String::from_utf8(GpxWriter::new(vec![]).unwrap().finish().unwrap()).unwrap();
+    assert!(out.ends_with(">\n <trk>\n </trk>\n</gpx>\n"));
+}
+#[test]
+fn gpx_reader() {
+    use gpx::{GpxReader, GpxWriter};
+    use types::FixMode;
+    // what the writer writes, the reader reads back
+    let walk = stockholm_walk();
+    let mut gpx = GpxWriter::new(vec![]).unwrap();
+    gpx.handle(&serde_json::from_str(r#"{"class":"SKY","hdop":1.5,"uSat":7,"satellites":[]}"#).unwrap()).unwrap();
+    for tpv in walk.iter() {
+        gpx.write_tpv(tpv).unwrap();
+    }
+    let data = gpx.finish().unwrap();
+    let read: Vec<Response> = GpxReader::new(&data[..]).collect::<Result<_, _>>().unwrap();
+    assert_eq!(read.len(), 675);
+    match read[0] {
+        Response::Sky(ref sky) => {
+            assert_eq!(sky.hdop, Some(1.5));
+            assert_eq!(sky.u_sat, Some(7));
+            assert_eq!(sky.time, walk[1].time());
+        },
+        ref x => panic!("expected a SKY, got {:?}", x)
+    }
+    for (orig, resp) in walk[1..].iter().zip(read[1..].iter()) {
+        let tpv = match *resp {
+            Response::Tpv(ref t) => t,
+            ref x => panic!("expected a TPV, got {:?}", x)
+        };
+        assert_eq!(tpv.mode(), orig.mode());
+        assert_eq!(tpv.time(), orig.time());
+        assert_eq!(tpv.position(), orig.position());
+        assert_eq!(tpv.speed(), orig.speed());
+        assert_eq!(tpv.track(), orig.track());
+        assert_eq!(tpv.climb().is_some(), orig.mode() == FixMode::Fix3D);
+    }
+
+    // speed, course and climb are interpolated; segments are separated by
+    // TPVs without a fix, and so are points without one
+    let gpx = r#"<?xml version="1.0"?>
+<!-- a comment, with <tags> in it -->
+<gpx version="1.0" xmlns="http://www.topografix.com/GPX/1/0">
+ <metadata><time>2020-01-01T00:00:00Z</time></metadata>
+ <trk><name><![CDATA[north & then <east>]]></name>
+  <trkseg>
+   <trkpt lat="10.0" lon='20.0'><ele>100</ele><time>2020-01-01T12:00:00Z</time></trkpt>
+   <trkpt lat="10.001" lon="20.0"><ele>102</ele><time>2020-01-01T12:00:10Z</time></trkpt>
+   <trkpt lat="10.002" lon="20.0"><ele>104</ele><time>2020-01-01T12:00:20Z</time><speed>11.5</speed></trkpt>
+   <trkpt lat="10.002" lon="20.0"><time>2020-01-01T12:00:25Z</time><fix>none</fix></trkpt>
+   <trkpt lat="10.003" lon="20.0"><ele>104</ele></trkpt>
+  </trkseg>
+  <trkseg>
+   <trkpt lat="10.002" lon="20.001" desc="a &quot;quoted&quot; > sign"><time>2020-01-01T12:01:00+01:00</time><fix>2d</fix></trkpt>
+   <trkpt lat="10.002" lon="20.002"><time>2020-01-01T11:01:10Z</time><fix>dgps</fix><dgpsid>7</dgpsid></trkpt>
+   <trkpt lat="10.002" lon="20.003"><time>2020-01-01T11:02:00Z</time></trkpt>
+  </trkseg>
+ </trk>
+</gpx>"#;
+    let read: Vec<TpvResponse> = GpxReader::new(gpx.as_bytes())
+        .map(|r| match r.unwrap() {
+            Response::Tpv(t) => t,
+            x => panic!("expected a TPV, got {:?}", x)
+        })
+        .collect();
+    let modes: Vec<FixMode> = read.iter().map(|t| t.mode()).collect();
+    assert_eq!(modes, vec![
+        FixMode::Fix3D, FixMode::Fix3D, FixMode::Fix3D, FixMode::NoFix,
+        FixMode::Fix2D, FixMode::Fix2D, FixMode::Fix2D
+    ]);
+    let north = Position { lat: 10.0, lon: 20.0, alt: None }.distance_to(&Position { lat: 10.001, lon: 20.0, alt: None });
+    // from the next point, at the start of a segment
+    assert!((read[0].speed().unwrap() - north / 10.0).abs() < 1e-9);
+    assert!(read[0].track().unwrap().abs() < 1e-6);
+    assert!((read[0].climb().unwrap() - 0.2).abs() < 1e-9);
+    // from both sides in the middle
+    assert!((read[1].speed().unwrap() - (north + read[2].position().unwrap().distance_to(&read[1].position().unwrap())) / 20.0).abs() < 1e-9);
+    assert!((read[1].climb().unwrap() - 0.2).abs() < 1e-9);
+    // recorded speeds are used as they are
+    assert_eq!(read[2].speed(), Some(11.5));
+    assert_eq!(read[2].alt(), Some(104.0));
+    assert_eq!(read[3].time().unwrap().to_rfc3339(), "2020-01-01T12:00:25+00:00");
+    // (the point without a time is skipped)
+    assert_eq!(read[4].time().unwrap().to_rfc3339(), "2020-01-01T11:01:00+00:00");
+    assert!((read[4].track().unwrap() - 90.0).abs() < 0.01);
+    assert_eq!(read[5].details().status, Some(FixStatus::Dgps));
+    assert_eq!(read[5].details().dgps_station, Some(7));
+    // (evenly spaced points, ten and then fifty seconds apart)
+    assert!((read[6].speed().unwrap() * 50.0 * 2.0 - read[5].speed().unwrap() * 60.0).abs() < 1e-9);
+
+    // an empty file is fine, but a broken one isn't
+    assert_eq!(GpxReader::new(&b""[..]).count(), 0);
+    let bad = r#"<gpx><trk><trkseg><trkpt lat="ten" lon="20"><time>2020-01-01T12:00:00Z</time></trkpt></trkseg></trk></gpx>"#;
+    let mut reader = GpxReader::new(bad.as_bytes());
+    match reader.next() {
+        Some(Err(Error::MalformedGpx(ref s))) => assert_eq!(s, "invalid latitude 'ten'"),
+        x => panic!("expected an error, got {:?}", x)
+    }
+    assert!(reader.next().is_none());
+    assert!(GpxReader::new(&b"<gpx><trk"[..]).next().unwrap().is_err());
+}
+#[test]
+fn geojson_export() {
+    use geojson::{feature_collection, GeoJsonWriter};
+    let mut responses: Vec<Response> = stockholm_walk().into_iter().map(Response::Tpv).collect();
+    responses.insert(100, serde_json::from_str(r#"{"class":"SKY","hdop":3.3,"vdop":4.5,"uSat":6,"satellites":[]}"#).unwrap());
+    let doc = feature_collection(responses.iter());
+    assert_eq!(doc["type"], "FeatureCollection");
+    let features = doc["features"].as_array().unwrap();
+    // a point for each fix, then the line
+    assert_eq!(features.len(), 675);
+    let first = &features[0];
+    assert_eq!(first["geometry"], json!({ "type": "Point", "coordinates": [18.054896666666664, 59.345015, 78.2] }));
+    assert_eq!(first["properties"]["time"], "2004-12-14T10:44:27.591Z");
+    assert_eq!(first["properties"]["mode"], 3);
+    assert_eq!(first["properties"]["track"], 320.93);
+    assert!(first["properties"].get("lat").is_none());
+    assert!(first["properties"].get("class").is_none());
+    assert!(first["properties"].get("hdop").is_none());
+    let later = &features[100]["properties"];
+    assert_eq!((later["hdop"].clone(), later["vdop"].clone(), later["uSat"].clone()), (json!(3.3), json!(4.5), json!(6)));
+    let line = &features[674];
+    assert_eq!(line["geometry"]["type"], "LineString");
+    // some of the fixes are 2D, so the line doesn't have altitudes
+    let coords = line["geometry"]["coordinates"].as_array().unwrap();
+    assert_eq!(coords.len(), 674);
+    assert_eq!(coords[0], json!([18.054896666666664, 59.345015]));
+    assert_eq!(line["properties"]["start"], "2004-12-14T10:44:27.591Z");
+    assert_eq!(line["properties"]["end"], features[673]["properties"]["time"]);
+    // without altHAE, the height comes from altMSL and geoidSep
+    let msl: Response = serde_json::from_str(r#"{"class":"TPV","mode":3,"time":"2020-01-01T00:00:00Z","ept":0.005,"lat":59.3,"lon":18.0,"alt":78.2,"altMSL":78.2,"geoidSep":23.25,"speed":0.0,"climb":0.0}"#).unwrap();
+    let one = feature_collection(Some(&msl));
+    assert_eq!(one["features"][0]["geometry"]["coordinates"], json!([18.0, 59.3, 101.45]));
+
+    // the streaming writer writes the same thing
+    let mut writer = GeoJsonWriter::new(FlushCounter::default()).unwrap();
+    for resp in responses[..10].iter() {
+        writer.handle(resp).unwrap();
+    }
+    let so_far = String::from_utf8(writer.get_ref().data.clone()).unwrap();
+    assert_eq!(so_far.lines().count(), 10);
+    assert_eq!(writer.get_ref().flushes, 10);
+    for resp in responses[10..].iter() {
+        writer.handle(resp).unwrap();
+    }
+    let out = writer.finish().unwrap().data;
+    // (parsing both, as serde_json doesn't always parse floats back exactly)
+    let reparse = |s: &str| serde_json::from_str::<serde_json::Value>(s).unwrap();
+    assert_eq!(reparse(::std::str::from_utf8(&out).unwrap()), reparse(&doc.to_string()));
+
+    // losing the fix ends a line; lines over the antimeridian are split
+    let tpv = |lon: f64, lat: f64| -> Response {
+        serde_json::from_value(json!({ "class": "TPV", "mode": 3, "time": "2020-01-01T00:00:00Z", "ept": 0.005,
+            "lat": lat, "lon": lon, "altHAE": lat * 10.0, "alt": 1.0, "speed": 0.0, "climb": 0.0 })).unwrap()
+    };
+    let nofix: Response = serde_json::from_str(r#"{"class":"TPV","mode":1,"time":"2020-01-01T00:00:00Z"}"#).unwrap();
+    let mut writer = GeoJsonWriter::new(vec![]).unwrap();
+    writer.set_points(false);
+    for resp in [tpv(170.0, 0.0), tpv(171.0, 0.0), nofix.clone(), nofix, tpv(179.5, 10.0), tpv(-179.5, 12.0), tpv(-179.0, 12.0)].iter() {
+        writer.handle(resp).unwrap();
+    }
+    let doc: serde_json::Value = serde_json::from_slice(&writer.finish().unwrap()).unwrap();
+    assert_eq!(doc["features"], json!([
+        {
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": [[170.0, 0.0, 0.0], [171.0, 0.0, 0.0]] },
+            "properties": { "start": "2020-01-01T00:00:00Z", "end": "2020-01-01T00:00:00Z" }
+        },
+        {
+            "type": "Feature",
+            "geometry": { "type": "MultiLineString", "coordinates": [
+                [[179.5, 10.0, 100.0], [180.0, 11.0, 110.0]],
+                [[-180.0, 11.0, 110.0], [-179.5, 12.0, 120.0], [-179.0, 12.0, 120.0]]
+            ] },
+            "properties": { "start": "2020-01-01T00:00:00Z", "end": "2020-01-01T00:00:00Z" }
+        }
+    ]));
+    // an empty collection is still valid
+    let out = GeoJsonWriter::new(vec![]).unwrap().finish().unwrap();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&out).unwrap(), json!({ "type": "FeatureCollection", "features": [] }));
+}
+#[test]
+fn kml_export() {
+    use kml::{write_kml, AltitudeMode, KmlWriter};
+    let walk = stockholm_walk();
+    let mut responses: Vec<Response> = walk.iter().cloned().map(Response::Tpv).collect();
+    responses.insert(0, serde_json::from_str(r#"{"class":"SKY","hdop":3.3,"uSat":6,"satellites":[]}"#).unwrap());
+    let out = String::from_utf8(write_kml(vec![], AltitudeMode::Absolute, responses.iter()).unwrap()).unwrap();
+    assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\""));
+    assert!(out.ends_with("    </gx:Track>\n   </gx:MultiTrack>\n  </Placemark>\n </Document>\n</kml>\n"));
+    assert_eq!(out.matches("<gx:Track>").count(), 1);
+    assert!(out.contains("     <altitudeMode>absolute</altitudeMode>\n     <when>2004-12-14T10:44:27.591Z</when>\n"));
+    assert_eq!(out.matches("<when>").count(), 674);
+    assert_eq!(out.matches("<gx:coord>").count(), 674);
+    assert!(out.contains("<gx:coord>18.054896666666664 59.345015 78.2</gx:coord>"));
+    // 2D fixes keep the altitude from before
+    assert!(!out.contains(" 0</gx:coord>"));
+    assert!(out.contains("       <gx:SimpleArrayData name=\"speed\">\n        <gx:value>0.07528630718</gx:value>\n"));
+    assert!(out.contains("       <gx:SimpleArrayData name=\"hdop\">\n        <gx:value>3.3</gx:value>\n"));
+    assert_eq!(out.matches("<gx:value>").count(), 674 * 4);
+
+    // long tracks are written in pieces, and losing the fix ends a track
+    let mut kml = KmlWriter::with_name(FlushCounter::default(), "Stockholm <walk>").unwrap();
+    kml.set_max_points(100);
+    for tpv in walk[..150].iter() {
+        kml.write_tpv(tpv).unwrap();
+    }
+    let so_far = String::from_utf8(kml.get_ref().data.clone()).unwrap();
+    assert_eq!(so_far.matches("<when>").count(), 100);
+    assert!(so_far.ends_with("    </gx:Track>\n"));
+    kml.handle(&serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap()).unwrap();
+    for tpv in walk[150..].iter() {
+        kml.write_tpv(tpv).unwrap();
+    }
+    let out = String::from_utf8(kml.finish().unwrap().data).unwrap();
+    assert_eq!(out.matches("<name>Stockholm &lt;walk&gt;</name>").count(), 2);
+    assert!(out.contains("<altitudeMode>clampToGround</altitudeMode>"));
+    // 149 fixes, as 100 + 49; then 525, as 100 + 99 * 4 + 29, with each
+    // piece after the first starting with the point the last one ended with
+    assert_eq!(out.matches("<gx:Track>").count(), 2 + 6);
+    assert_eq!(out.matches("<when>").count(), 674 + 1 + 5);
+    let tracks: Vec<&str> = out.split("<gx:Track>").skip(1).collect();
+    assert_eq!(tracks[0].matches("<when>").count(), 100);
+    assert_eq!(tracks[1].matches("<when>").count(), 50);
+    let last_coord = |t: &str| t[t.rfind("<gx:coord>").unwrap()..].lines().next().unwrap().to_owned();
+    let first_coord = |t: &str| t[t.find("<gx:coord>").unwrap()..].lines().next().unwrap().to_owned();
+    assert_eq!(first_coord(tracks[1]), last_coord(tracks[0]));
+    assert_ne!(first_coord(tracks[2]), last_coord(tracks[1]));
+    assert_eq!(first_coord(tracks[3]), last_coord(tracks[2]));
+
+    // losing the fix just after a piece leaves nothing more to write
+    let mut kml = KmlWriter::new(vec![]).unwrap();
+    kml.set_max_points(100);
+    for tpv in walk[1..101].iter() {
+        kml.write_tpv(tpv).unwrap();
+    }
+    kml.handle(&serde_json::from_str(r#"{"class":"TPV","mode":1}"#).unwrap()).unwrap();
+    kml.write_tpv(&walk[101]).unwrap();
+    let out = String::from_utf8(kml.finish().unwrap()).unwrap();
+    assert_eq!(out.matches("<gx:Track>").count(), 2);
+    assert_eq!(out.matches("<when>").count(), 101);
+}
+#[test]
+fn csv_export() {
+    use csv::{parse_columns, Column, CsvWriter, SkyColumn, TimeFormat};
+    let columns = parse_columns("time, mode,lat lon altHAE epx nSat uSat snr snrUsed hdop device");
+    assert_eq!(columns[1], Column::Tpv("mode".into()));
+    assert_eq!(columns[6], Column::Sky(SkyColumn::Seen));
+    assert_eq!(columns.len(), 12);
+    let parse = |s: &str| -> Response { serde_json::from_str(s).unwrap() };
+    let responses = [
+        parse(r#"{"class":"TPV","device":"/dev/a,b","mode":3,"time":"2020-01-01T00:00:00Z","ept":0.005,"lat":51.5,"lon":-0.125,"altHAE":45.5,"alt":0.0,"epx":3.5,"speed":0.0,"climb":0.0}"#),
+        // the SKY for the same epoch comes after its TPV
+        parse(r#"{"class":"SKY","time":"2020-01-01T00:00:00Z","hdop":1.2,"satellites":[{"PRN":1,"ss":40.5,"used":true},{"PRN":2,"ss":30.3,"used":true},{"PRN":3,"ss":19.2,"used":false},{"PRN":4,"ss":0,"used":false}]}"#),
+        parse(r#"{"class":"TPV","mode":2,"time":"2020-01-01T00:00:01.25Z","ept":0.005,"lat":51.5,"lon":-0.125,"speed":1.0}"#),
+        // and the next epoch's before it
+        parse(r#"{"class":"SKY","time":"2020-01-01T00:00:02Z","nSat":9,"uSat":7,"hdop":0.9,"satellites":[]}"#),
+        parse(r#"{"class":"TPV","mode":1,"time":"2020-01-01T00:00:02Z"}"#),
+        parse(r#"{"class":"TPV"}"#)
+    ];
+    let mut csv = CsvWriter::new(vec![], columns.clone());
+    for resp in responses.iter() {
+        csv.handle(resp).unwrap();
+    }
+    // the last TPV's epoch isn't over yet
+    assert_eq!(String::from_utf8(csv.get_ref().clone()).unwrap().lines().count(), 4);
+    let out = String::from_utf8(csv.finish().unwrap()).unwrap();
+    assert_eq!(out, "\
+time,mode,lat,lon,altHAE,epx,nSat,uSat,snr,snrUsed,hdop,device
+2020-01-01T00:00:00Z,3,51.5,-0.125,45.5,3.5,4,2,30,35.4,1.2,\"/dev/a,b\"
+2020-01-01T00:00:01.250Z,2,51.5,-0.125,,,4,2,30,35.4,1.2,
+2020-01-01T00:00:02Z,1,,,,,9,7,,,0.9,
+,,,,,,9,7,,,0.9,
+");
+
+    // TSV, with times since the epoch
+    let mut tsv = CsvWriter::new(vec![], parse_columns("time lat device"));
+    tsv.set_delimiter('\t');
+    tsv.set_time_format(TimeFormat::UnixEpoch);
+    tsv.set_header(false);
+    for resp in responses[..3].iter() {
+        tsv.handle(resp).unwrap();
+    }
+    let out = String::from_utf8(tsv.finish().unwrap()).unwrap();
+    assert_eq!(out, "1577836800\t51.5\t/dev/a,b\n1577836801.25\t51.5\t\n");
+
+    // a whole walk, written as it goes
+    let walk = stockholm_walk();
+    let mut csv = CsvWriter::new(Vec::new(), parse_columns("time mode lat lon alt speed track"));
+    csv.set_time_format(TimeFormat::UnixEpoch);
+    for tpv in walk.iter() {
+        csv.write_tpv(tpv).unwrap();
+    }
+    let out = String::from_utf8(csv.finish().unwrap()).unwrap();
+    assert_eq!(out.lines().count(), 676);
+    assert_eq!(out.lines().nth(2).unwrap(), "1103021067.591,3,59.345015,18.054896666666664,78.2,0.07528630718,320.93");
+    // an empty file just has the header
+    let out = CsvWriter::new(vec![], parse_columns("time,lat")).finish().unwrap();
+    assert_eq!(out, b"time,lat\n");
+}
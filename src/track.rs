@@ -0,0 +1,236 @@
+//! Statistics about a track: how far, how fast, how long, how high.
+//!
+//! Feed a `TrackStats` the TPVs from a `GpsdConnection` (or a recording), and
+//! ask it for a `TrackSummary` whenever you like. TPVs without a fix are
+//! ignored.
+//!
+//! A receiver that isn't moving still reports positions that wander about by
+//! a few meters, and simply adding up the distances between fixes would count
+//! all of that as travel. To avoid this, movement is only counted once the
+//! position has got further from the last counted point than gpsd's 95%
+//! horizontal error estimate (`epx`/`epy`), and climbs and descents once the
+//! altitude has changed by more than the vertical one (`epv`). Fixes without
+//! error estimates use the value set by `set_default_error` (0 by default, so
+//! that every movement counts).
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use types::{Position, Response, TpvResponse};
+
+/// A bounding box around some positions.
+///
+/// If the box crosses the antimeridian, `west` is greater than `east`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    /// Southernmost latitude, in degrees.
+    pub south: f64,
+    /// Westernmost longitude, in degrees.
+    pub west: f64,
+    /// Northernmost latitude, in degrees.
+    pub north: f64,
+    /// Easternmost longitude, in degrees.
+    pub east: f64
+}
+impl BoundingBox {
+    /// Does the box contain this position?
+    pub fn contains(&self, pos: &Position) -> bool {
+        let lon_ok = if self.west <= self.east {
+            pos.lon >= self.west && pos.lon <= self.east
+        }
+        else {
+            pos.lon >= self.west || pos.lon <= self.east
+        };
+        lon_ok && pos.lat >= self.south && pos.lat <= self.north
+    }
+}
+/// A summary of a track, from `TrackStats::summary`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrackSummary {
+    /// Number of fixes used.
+    pub fixes: u64,
+    /// Time of the first fix.
+    pub start: Option<DateTime<Utc>>,
+    /// Time of the last fix.
+    pub end: Option<DateTime<Utc>>,
+    /// Horizontal distance travelled, in meters.
+    pub distance: f64,
+    /// Time spent moving.
+    pub moving_time: Duration,
+    /// Time spent stopped.
+    pub stopped_time: Duration,
+    /// Highest speed reported, in meters per second.
+    pub max_speed: f64,
+    /// Average speed while moving (`distance` over `moving_time`), in meters
+    /// per second.
+    pub avg_speed: f64,
+    /// Total climb, in meters.
+    pub elevation_gain: f64,
+    /// Total descent, in meters.
+    pub elevation_loss: f64,
+    /// Lowest altitude, in meters.
+    pub min_alt: Option<f64>,
+    /// Highest altitude, in meters.
+    pub max_alt: Option<f64>,
+    /// Bounding box of all of the fixes used.
+    pub bounds: Option<BoundingBox>
+}
+/// Accumulates statistics about a track from a stream of TPVs.
+#[derive(Debug, Clone)]
+pub struct TrackStats {
+    stop_speed: f64,
+    default_error: f64,
+    summary: TrackSummary,
+    /// The last point counted towards the distance.
+    anchor: Option<Position>,
+    /// The last altitude counted towards the gain or loss.
+    alt_anchor: Option<f64>,
+    /// Time of the last fix.
+    last_time: Option<DateTime<Utc>>,
+    /// Longitude range in [-180, 180), and in [0, 360), to work out the
+    /// narrower bounding box.
+    lons: Option<(f64, f64, f64, f64)>
+}
+impl Default for TrackStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl TrackStats {
+    /// Make a new accumulator, with nothing in it yet.
+    pub fn new() -> Self {
+        TrackStats {
+            stop_speed: 0.5,
+            default_error: 0.0,
+            summary: TrackSummary::default(),
+            anchor: None,
+            alt_anchor: None,
+            last_time: None,
+            lons: None
+        }
+    }
+    /// Count time as stopped when the speed is below `speed` meters per
+    /// second (0.5 by default), or below the speed error estimate.
+    pub fn set_stop_speed(&mut self, speed: f64) {
+        self.stop_speed = speed;
+    }
+    /// Use `error` meters as the error estimate for fixes that don't have
+    /// one. This is 0 by default.
+    pub fn set_default_error(&mut self, error: f64) {
+        self.default_error = error;
+    }
+    /// Start again, forgetting everything seen so far.
+    pub fn reset(&mut self) {
+        *self = TrackStats {
+            stop_speed: self.stop_speed,
+            default_error: self.default_error,
+            ..TrackStats::new()
+        };
+    }
+    /// Add a response to the track. Anything other than a TPV is ignored.
+    pub fn handle(&mut self, resp: &Response) {
+        if let Response::Tpv(ref tpv) = *resp {
+            self.update(tpv);
+        }
+    }
+    /// Add a TPV to the track. TPVs without a position, or whose mode says
+    /// there's no fix, are ignored; a position with no mode at all counts.
+    pub fn update(&mut self, tpv: &TpvResponse) {
+        let pos = match tpv.position() {
+            Some(p) => p,
+            None => return
+        };
+        let mode = match *tpv {
+            TpvResponse::LatLonOnly { mode, .. } | TpvResponse::Dustbin { mode, .. } => mode,
+            _ => Some(tpv.mode())
+        };
+        if !mode.map_or(true, |m| m.has_fix()) {
+            return;
+        }
+        let errors = tpv.errors();
+        let stop_speed = self.stop_speed;
+        let s = &mut self.summary;
+        s.fixes += 1;
+
+        let h_err = match (errors.lat, errors.lon) {
+            (None, None) => self.default_error,
+            (lat, lon) => lat.unwrap_or(0.0).max(lon.unwrap_or(0.0))
+        };
+        match self.anchor {
+            Some(anchor) => {
+                let dist = anchor.distance_to(&pos);
+                if dist > h_err {
+                    s.distance += dist;
+                    self.anchor = Some(pos);
+                }
+            },
+            None => self.anchor = Some(pos)
+        }
+
+        if let Some(alt) = pos.alt {
+            let v_err = errors.alt.unwrap_or(self.default_error);
+            match self.alt_anchor {
+                Some(anchor) if (alt - anchor).abs() > v_err => {
+                    if alt > anchor {
+                        s.elevation_gain += alt - anchor;
+                    }
+                    else {
+                        s.elevation_loss += anchor - alt;
+                    }
+                    self.alt_anchor = Some(alt);
+                },
+                Some(_) => {},
+                None => self.alt_anchor = Some(alt)
+            }
+            s.min_alt = Some(s.min_alt.map_or(alt, |m| m.min(alt)));
+            s.max_alt = Some(s.max_alt.map_or(alt, |m| m.max(alt)));
+        }
+
+        let speed = tpv.speed();
+        let speed_err = errors.speed.unwrap_or(0.0);
+        let moving = speed.is_some_and(|v| v >= stop_speed && v > speed_err);
+        if let Some(speed) = speed {
+            if speed > speed_err && speed > s.max_speed {
+                s.max_speed = speed;
+            }
+        }
+        if let Some(time) = tpv.time() {
+            if let Some(last) = self.last_time {
+                if let Ok(dt) = (time - last).to_std() {
+                    if moving {
+                        s.moving_time += dt;
+                    }
+                    else {
+                        s.stopped_time += dt;
+                    }
+                }
+            }
+            if s.start.is_none() {
+                s.start = Some(time);
+            }
+            s.end = Some(time);
+            self.last_time = Some(time);
+        }
+        let secs = s.moving_time.as_secs_f64();
+        s.avg_speed = if secs > 0.0 { s.distance / secs } else { 0.0 };
+
+        let shifted = if pos.lon < 0.0 { pos.lon + 360.0 } else { pos.lon };
+        let (w, e, sw, se) = match self.lons {
+            Some((w, e, sw, se)) => (w.min(pos.lon), e.max(pos.lon), sw.min(shifted), se.max(shifted)),
+            None => (pos.lon, pos.lon, shifted, shifted)
+        };
+        self.lons = Some((w, e, sw, se));
+        let (west, east) = if se - sw < e - w {
+            (if sw >= 180.0 { sw - 360.0 } else { sw }, if se >= 180.0 { se - 360.0 } else { se })
+        }
+        else {
+            (w, e)
+        };
+        s.bounds = Some(match s.bounds {
+            Some(b) => BoundingBox { south: b.south.min(pos.lat), north: b.north.max(pos.lat), west, east },
+            None => BoundingBox { south: pos.lat, north: pos.lat, west, east }
+        });
+    }
+    /// The statistics so far.
+    pub fn summary(&self) -> TrackSummary {
+        self.summary.clone()
+    }
+}
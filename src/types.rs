@@ -2,13 +2,71 @@
 //!
 //! For further information (or where documentation may be sparse), refer to the
 //! GPSD API documentation [here](http://www.catb.org/gpsd/gpsd_json.html).
+//!
+//! Objects keep any fields this library doesn't know about in an `extra` map,
+//! so that serializing them again reproduces what gpsd sent.
 use chrono::*;
+use serde_json::{Map, Value};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, DeserializeOwned, Error as DeError, MapAccess, Unexpected, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde::ser::{Error as SerError, SerializeMap, SerializeSeq};
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use raw::RawFrame;
+use nmea::NmeaSentence;
 
 fn serde_true() -> bool { true }
 fn serde_false() -> bool { false }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
+/// Format a timestamp the way gpsd does: to the millisecond, unless it's more
+/// precise than that.
+fn gpsd_time(time: &DateTime<Utc>) -> String {
+    let format = if time.nanosecond() % 1_000_000 == 0 {
+        SecondsFormat::Millis
+    }
+    else {
+        SecondsFormat::AutoSi
+    };
+    time.to_rfc3339_opts(format, true)
+}
+fn serialize_time<S: Serializer>(time: &DateTime<Utc>, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_str(&gpsd_time(time))
+}
+fn serialize_opt_time<S: Serializer>(time: &Option<DateTime<Utc>>, ser: S) -> Result<S::Ok, S::Error> {
+    match *time {
+        Some(ref t) => serialize_time(t, ser),
+        None => ser.serialize_none()
+    }
+}
+/// Serialize a list of objects nested in a POLL or DEVICES response. gpsd
+/// gives each of these its own `class`, written first.
+fn serialize_tagged<T: Serialize, S: Serializer>(class: &str, items: &[T], ser: S) -> Result<S::Ok, S::Error> {
+    let mut seq = ser.serialize_seq(Some(items.len()))?;
+    for item in items {
+        // Going via a string, rather than `to_value`, keeps `f32`s short.
+        let mut obj: Map<String, Value> = ::serde_json::to_string(item)
+            .and_then(|s| ::serde_json::from_str(&s))
+            .map_err(S::Error::custom)?;
+        obj.shift_remove("class");
+        let mut tagged = Map::with_capacity(obj.len() + 1);
+        tagged.insert("class".into(), Value::String(class.into()));
+        tagged.extend(obj);
+        seq.serialize_element(&tagged)?;
+    }
+    seq.end()
+}
+fn serialize_poll_tpv<S: Serializer>(items: &[TpvResponse], ser: S) -> Result<S::Ok, S::Error> {
+    serialize_tagged("TPV", items, ser)
+}
+fn serialize_poll_sky<S: Serializer>(items: &[SkyResponse], ser: S) -> Result<S::Ok, S::Error> {
+    serialize_tagged("SKY", items, ser)
+}
+fn serialize_devices<S: Serializer>(items: &[DeviceObject], ser: S) -> Result<S::Ok, S::Error> {
+    serialize_tagged("DEVICE", items, ser)
+}
+
+#[derive(Debug, Clone)]
 /// A time-position-velocity (TPV) report.
 ///
 /// The API here splits the TPV object that GPSD sends into various variants, in
@@ -17,6 +75,13 @@ fn serde_false() -> bool { false }
 ///
 /// Basically, the aim here is to reduce the amount of Option unwrapping
 /// you have to do, as gpsd specifies that all these fields are optional.
+/// If you don't care which variant you've got, the accessor methods (`lat()`,
+/// `position()`, `errors()` and friends) work on all of them.
+///
+/// The variant is chosen from the reported fix mode and the fields that are
+/// actually present. Fields that don't exist in the chosen variant (for
+/// example, an `epv` sent alongside a 2D fix) are kept in its `extra` map, so
+/// nothing gpsd sent is lost.
 ///
 /// The field documentation is exactly the same across variants; it may be omitted
 /// for brevity.
@@ -27,44 +92,44 @@ pub enum TpvResponse {
         device: Option<String>,
         /// Timestamp.
         time: DateTime<Utc>,
-        /// Fix type: 0 = unknown, 1 = no fix, 2 = 2D fix, 3 = 3D fix.
-        mode: u8,
-        /// Estimated timestamp error (seconds, 95% confidence).
-        #[serde(rename = "ept")]
+        /// Fix type.
+        mode: FixMode,
+        /// Estimated timestamp error (seconds, 95% confidence). Sent as `ept`.
         time_err: f64,
         /// Latitude in degrees: +/- signifies North/South. Present when mode is 2 or 3.
         lat: f64,
         /// Latitude error estimate in meters, 95% confidence. Present if mode
-        /// is 2 or 3 and DOPs can be calculated from the satellite view.
-        #[serde(rename = "epy")]
+        /// is 2 or 3 and DOPs can be calculated from the satellite view. Sent
+        /// as `epy`.
         lat_err: Option<f64>,
         /// Longitude in degrees: +/- signifies East/West. Present when mode is 2 or 3.
         lon: f64,
         /// Longitude error estimate in meters, 95% confidence. Present if mode
-        /// is 2 or 3 and DOPs can be calculated from the satellite view.
-        #[serde(rename = "epx")]
+        /// is 2 or 3 and DOPs can be calculated from the satellite view. Sent
+        /// as `epx`.
         lon_err: Option<f64>,
         /// Altitude in meters. Present if mode is 3.
         alt: f64,
         /// Estimated vertical error in meters, 95% confidence. Present if mode
-        /// is 3 and DOPs can be calculated from the satellite view.
-        #[serde(rename = "epv")]
+        /// is 3 and DOPs can be calculated from the satellite view. Sent as
+        /// `epv`.
         alt_err: Option<f64>,
         /// Course over ground, degrees from true north.
         track: Option<f64>,
-        /// Direction error estimate in degrees, 95% confidence.
-        #[serde(rename = "epd")]
+        /// Direction error estimate in degrees, 95% confidence. Sent as `epd`.
         track_err: Option<f64>,
         /// Speed over ground, meters per second.
         speed: f64,
-        /// Speed error estinmate in meters/sec, 95% confidence.
-        #[serde(rename = "eps")]
+        /// Speed error estinmate in meters/sec, 95% confidence. Sent as `eps`.
         speed_err: Option<f64>,
         /// Climb (positive) or sink (negative) rate, meters per second.
         climb: f64,
-        /// Climb/sink error estimate in meters/sec, 95% confidence.
-        #[serde(rename = "epc")]
-        climb_err: Option<f64>
+        /// Climb/sink error estimate in meters/sec, 95% confidence. Sent as `epc`.
+        climb_err: Option<f64>,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
     /// 2D GPS fix, with speed data.
     Fix2D {
@@ -72,27 +137,26 @@ pub enum TpvResponse {
         device: Option<String>,
         /// Timestamp.
         time: DateTime<Utc>,
-        /// Fix type: 0 = unknown, 1 = no fix, 2 = 2D fix, 3 = 3D fix.
-        mode: u8,
+        /// Fix type.
+        mode: FixMode,
         /// Estimated timestamp error (seconds, 95% confidence).
-        #[serde(rename = "ept")]
         time_err: f64,
         /// Latitude in degrees: +/- signifies North/South. Present when mode is 2 or 3.
         lat: f64,
-        #[serde(rename = "epy")]
         lat_err: Option<f64>,
         /// Longitude in degrees: +/- signifies East/West. Present when mode is 2 or 3.
         lon: f64,
-        #[serde(rename = "epx")]
         lon_err: Option<f64>,
         /// Course over ground, degrees from true north.
         track: Option<f64>,
-        #[serde(rename = "epd")]
         track_err: Option<f64>,
         /// Speed over ground, meters per second.
         speed: f64,
-        #[serde(rename = "eps")]
         speed_err: Option<f64>,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
     /// Fix with lat/lon, and an unknown smattering of fields.
     /// You'll get this variant if a fix is obtained (lat/lon available), but GPSD
@@ -105,35 +169,32 @@ pub enum TpvResponse {
         device: Option<String>,
         /// Timestamp.
         time: DateTime<Utc>,
-        /// Fix type: 0 = unknown, 1 = no fix, 2 = 2D fix, 3 = 3D fix.
-        mode: u8,
+        /// Fix type, if gpsd sent one.
+        mode: Option<FixMode>,
         /// Estimated timestamp error (seconds, 95% confidence).
-        #[serde(rename = "ept")]
         time_err: f64,
         /// Latitude in degrees: +/- signifies North/South. Present when mode is 2 or 3.
         lat: f64,
-        #[serde(rename = "epy")]
         lat_err: Option<f64>,
         /// Longitude in degrees: +/- signifies East/West. Present when mode is 2 or 3.
         lon: f64,
-        #[serde(rename = "epx")]
         lon_err: Option<f64>,
         /// Altitude in meters. Present if mode is 3.
         alt: Option<f64>,
-        #[serde(rename = "epv")]
         alt_err: Option<f64>,
         /// Course over ground, degrees from true north.
         track: Option<f64>,
-        #[serde(rename = "epd")]
         track_err: Option<f64>,
         /// Speed over ground, meters per second.
         speed: Option<f64>,
-        #[serde(rename = "eps")]
         speed_err: Option<f64>,
         /// Climb (positive) or sink (negative) rate, meters per second.
         climb: Option<f64>,
-        #[serde(rename = "epc")]
         climb_err: Option<f64>,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
     /// No fix.
     NoFix {
@@ -141,17 +202,26 @@ pub enum TpvResponse {
         device: Option<String>,
         /// Timestamp.
         time: DateTime<Utc>,
-        /// Fix type: 0 = unknown, 1 = no fix, 2 = 2D fix, 3 = 3D fix.
-        mode: u8
+        /// Fix type.
+        mode: FixMode,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
-    /// Possibly no useful data whatsoever.
+    /// Possibly no useful data whatsoever: nothing but some combination of the
+    /// device, timestamp, fix mode, and `details`.
     Nothing {
         /// Name of originating device.
         device: Option<String>,
         /// Timestamp.
         time: Option<DateTime<Utc>>,
-        /// Fix type: 0 = unknown, 1 = no fix, 2 = 2D fix, 3 = 3D fix.
-        mode: Option<u8>
+        /// Fix type.
+        mode: Option<FixMode>,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
     /// Something else! You'll get this variant if GPSD sent data that doesn't
     /// exactly fit into any of the categories above.
@@ -161,56 +231,1077 @@ pub enum TpvResponse {
     Dustbin {
         device: Option<String>,
         time: Option<DateTime<Utc>>,
-        mode: Option<u8>,
-        #[serde(rename = "ept")]
+        mode: Option<FixMode>,
         time_err: Option<f64>,
         lat: Option<f64>,
-        #[serde(rename = "epy")]
         lat_err: Option<f64>,
         lon: Option<f64>,
-        #[serde(rename = "epx")]
         lon_err: Option<f64>,
         alt: Option<f64>,
-        #[serde(rename = "epv")]
         alt_err: Option<f64>,
         track: Option<f64>,
-        #[serde(rename = "epd")]
         track_err: Option<f64>,
         speed: Option<f64>,
-        #[serde(rename = "eps")]
         speed_err: Option<f64>,
         climb: Option<f64>,
-        #[serde(rename = "epc")]
         climb_err: Option<f64>,
+        /// Fields added in newer versions of gpsd.
+        details: TpvDetails,
+        /// Any fields not otherwise recognised.
+        extra: Map<String, Value>
     },
 }
 impl Default for TpvResponse {
     fn default() -> TpvResponse {
-        TpvResponse::Nothing { device: None, time: None, mode: None }
+        TpvResponse::Nothing {
+            device: None,
+            time: None,
+            mode: None,
+            details: Default::default(),
+            extra: Map::new()
+        }
+    }
+}
+/// A TPV object, with every field optional, as read off the wire.
+///
+/// This is an intermediate step in deserializing a `TpvResponse`: the object is
+/// parsed into one of these in a single pass, then `classify` picks the right
+/// variant.
+#[derive(Debug, Default)]
+pub(crate) struct TpvFields {
+    pub device: Option<String>,
+    pub time: Option<DateTime<Utc>>,
+    pub mode: Option<FixMode>,
+    pub time_err: Option<f64>,
+    pub lat: Option<f64>,
+    pub lat_err: Option<f64>,
+    pub lon: Option<f64>,
+    pub lon_err: Option<f64>,
+    pub alt: Option<f64>,
+    pub alt_err: Option<f64>,
+    pub track: Option<f64>,
+    pub track_err: Option<f64>,
+    pub speed: Option<f64>,
+    pub speed_err: Option<f64>,
+    pub climb: Option<f64>,
+    pub climb_err: Option<f64>,
+    pub details: TpvDetails,
+    pub extra: Map<String, Value>
+}
+/// Deserialize the value of the TPV field `key`, naming the field if that fails.
+fn tpv_field<'de, A, T>(map: &mut A, key: &str) -> Result<T, A::Error>
+    where A: MapAccess<'de>, T: Deserialize<'de> {
+    map.next_value()
+        .map_err(|e| A::Error::custom(format_args!("invalid TPV field `{}`: {}", key, e)))
+}
+/// Move a field that doesn't fit the chosen variant into `extra`.
+fn spill<T: Serialize>(extra: &mut Map<String, Value>, key: &str, val: Option<T>) {
+    if let Some(v) = val {
+        extra.insert(key.into(), ::serde_json::to_value(v).unwrap_or(Value::Null));
+    }
+}
+impl TpvFields {
+    /// Pick the right `TpvResponse` variant for these fields.
+    pub fn classify(self) -> TpvResponse {
+        use self::TpvResponse::*;
+        let TpvFields {
+            device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+            track, track_err, speed, speed_err, climb, climb_err, details, mut extra
+        } = self;
+        match (time, mode, time_err, lat, lon) {
+            (Some(time), Some(mode), Some(time_err), Some(lat), Some(lon)) if mode.has_fix() => {
+                match (alt, speed, climb) {
+                    (Some(alt), Some(speed), Some(climb)) if mode == FixMode::Fix3D => {
+                        return Fix3D {
+                            device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                            track, track_err, speed, speed_err, climb, climb_err, details, extra
+                        };
+                    },
+                    (_, Some(speed), _) => {
+                        spill(&mut extra, "alt", alt);
+                        spill(&mut extra, "epv", alt_err);
+                        spill(&mut extra, "climb", climb);
+                        spill(&mut extra, "epc", climb_err);
+                        return Fix2D {
+                            device, time, mode, time_err, lat, lat_err, lon, lon_err,
+                            track, track_err, speed, speed_err, details, extra
+                        };
+                    },
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+        if let (Some(time), Some(time_err), Some(lat), Some(lon)) = (time, time_err, lat, lon) {
+            return LatLonOnly {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err,
+                alt, alt_err, track, track_err, speed, speed_err, climb, climb_err, details, extra
+            };
+        }
+        let no_position = lat.is_none() && lon.is_none() && alt.is_none() &&
+            track.is_none() && speed.is_none() && climb.is_none();
+        let no_errors = time_err.is_none() && lat_err.is_none() && lon_err.is_none() &&
+            alt_err.is_none() && track_err.is_none() && speed_err.is_none() && climb_err.is_none();
+        match (time, mode) {
+            (Some(time), Some(mode)) if no_position && !mode.has_fix() => {
+                spill(&mut extra, "ept", time_err);
+                spill(&mut extra, "epy", lat_err);
+                spill(&mut extra, "epx", lon_err);
+                spill(&mut extra, "epv", alt_err);
+                spill(&mut extra, "epd", track_err);
+                spill(&mut extra, "eps", speed_err);
+                spill(&mut extra, "epc", climb_err);
+                NoFix { device, time, mode, details, extra }
+            },
+            _ if no_position && no_errors => Nothing { device, time, mode, details, extra },
+            _ => Dustbin {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            }
+        }
+    }
+}
+/// Take a field that was moved into `extra` by `spill` back out again.
+fn unspill<T: DeserializeOwned>(extra: &mut Map<String, Value>, key: &str) -> Option<T> {
+    extra.remove(key).and_then(|v| ::serde_json::from_value(v).ok())
+}
+impl From<TpvResponse> for TpvFields {
+    /// Flatten a `TpvResponse` back out, so it can be modified and classified
+    /// again.
+    fn from(tpv: TpvResponse) -> TpvFields {
+        use self::TpvResponse::*;
+        match tpv {
+            Fix3D {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            } => TpvFields {
+                device, time: Some(time), mode: Some(mode), time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err, alt: Some(alt), alt_err,
+                track, track_err, speed: Some(speed), speed_err, climb: Some(climb), climb_err,
+                details, extra
+            },
+            Fix2D {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err,
+                track, track_err, speed, speed_err, details, mut extra
+            } => TpvFields {
+                device, time: Some(time), mode: Some(mode), time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err,
+                alt: unspill(&mut extra, "alt"),
+                alt_err: unspill(&mut extra, "epv"),
+                track, track_err, speed: Some(speed), speed_err,
+                climb: unspill(&mut extra, "climb"),
+                climb_err: unspill(&mut extra, "epc"),
+                details, extra
+            },
+            LatLonOnly {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            } => TpvFields {
+                device, time: Some(time), mode, time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            },
+            NoFix { device, time, mode, details, mut extra } => TpvFields {
+                device, time: Some(time), mode: Some(mode),
+                time_err: unspill(&mut extra, "ept"),
+                lat_err: unspill(&mut extra, "epy"),
+                lon_err: unspill(&mut extra, "epx"),
+                alt_err: unspill(&mut extra, "epv"),
+                track_err: unspill(&mut extra, "epd"),
+                speed_err: unspill(&mut extra, "eps"),
+                climb_err: unspill(&mut extra, "epc"),
+                details, extra,
+                ..Default::default()
+            },
+            Nothing { device, time, mode, details, extra } => TpvFields {
+                device, time, mode, details, extra,
+                ..Default::default()
+            },
+            Dustbin {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            } => TpvFields {
+                device, time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra
+            }
+        }
+    }
+}
+/// Every TPV field name we know about.
+const TPV_KEYS: &[&str] = &[
+    "device", "time", "mode", "ept", "lat", "epy", "lon", "epx", "alt", "epv",
+    "track", "epd", "speed", "eps", "climb", "epc", "status", "altHAE", "altMSL",
+    "geoidSep", "datum", "eph", "sep", "magtrack", "magvar", "dgpsAge", "dgpsSta",
+    "ecefx", "ecefy", "ecefz", "ecefvx", "ecefvy", "ecefvz", "ecefpAcc",
+    "ecefvAcc", "velN", "velE", "velD", "leapseconds", "depth", "wanglem",
+    "wangler", "wanglet", "wspeedr", "wspeedt"
+];
+/// A key in a TPV object. Known keys are matched against `TPV_KEYS`, which
+/// saves allocating a `String` for each of them.
+enum TpvKey {
+    Known(&'static str),
+    Other(String)
+}
+impl<'de> Deserialize<'de> for TpvKey {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = TpvKey;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a TPV field name")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<TpvKey, E> {
+                Ok(match TPV_KEYS.iter().find(|k| **k == v) {
+                    Some(k) => TpvKey::Known(k),
+                    None => TpvKey::Other(v.into())
+                })
+            }
+        }
+        de.deserialize_identifier(KeyVisitor)
+    }
+}
+struct TpvVisitor;
+impl<'de> Visitor<'de> for TpvVisitor {
+    type Value = TpvFields;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a TPV object")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TpvFields, A::Error> {
+        let mut ret = TpvFields::default();
+        while let Some(key) = map.next_key::<TpvKey>()? {
+            let key = match key {
+                TpvKey::Known(k) => k,
+                TpvKey::Other(k) => {
+                    let val = tpv_field(&mut map, &k)?;
+                    ret.extra.insert(k, val);
+                    continue;
+                }
+            };
+            match key {
+                "device" => ret.device = tpv_field(&mut map, key)?,
+                "time" => ret.time = tpv_field(&mut map, key)?,
+                "mode" => ret.mode = tpv_field(&mut map, key)?,
+                "ept" => ret.time_err = tpv_field(&mut map, key)?,
+                "lat" => ret.lat = tpv_field(&mut map, key)?,
+                "epy" => ret.lat_err = tpv_field(&mut map, key)?,
+                "lon" => ret.lon = tpv_field(&mut map, key)?,
+                "epx" => ret.lon_err = tpv_field(&mut map, key)?,
+                "alt" => ret.alt = tpv_field(&mut map, key)?,
+                "epv" => ret.alt_err = tpv_field(&mut map, key)?,
+                "track" => ret.track = tpv_field(&mut map, key)?,
+                "epd" => ret.track_err = tpv_field(&mut map, key)?,
+                "speed" => ret.speed = tpv_field(&mut map, key)?,
+                "eps" => ret.speed_err = tpv_field(&mut map, key)?,
+                "climb" => ret.climb = tpv_field(&mut map, key)?,
+                "epc" => ret.climb_err = tpv_field(&mut map, key)?,
+                _ => {
+                    if !ret.details.visit_field(key, &mut map)? {
+                        let val = tpv_field(&mut map, key)?;
+                        ret.extra.insert(key.into(), val);
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+impl<'de> Deserialize<'de> for TpvResponse {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_map(TpvVisitor).map(TpvFields::classify)
+    }
+}
+/// Serialize `val` under `key`, if it's present.
+fn opt_entry<M: SerializeMap, T: Serialize>(map: &mut M, key: &str, val: &Option<T>) -> Result<(), M::Error> {
+    match *val {
+        Some(ref v) => map.serialize_entry(key, v),
+        None => Ok(())
+    }
+}
+/// A borrowed view of a `TpvResponse` with every field optional, for
+/// serializing it without taking it apart.
+struct TpvFieldsRef<'a> {
+    device: Option<&'a String>,
+    time: Option<&'a DateTime<Utc>>,
+    mode: Option<FixMode>,
+    time_err: Option<f64>,
+    lat: Option<f64>,
+    lat_err: Option<f64>,
+    lon: Option<f64>,
+    lon_err: Option<f64>,
+    alt: Option<f64>,
+    alt_err: Option<f64>,
+    track: Option<f64>,
+    track_err: Option<f64>,
+    speed: Option<f64>,
+    speed_err: Option<f64>,
+    climb: Option<f64>,
+    climb_err: Option<f64>,
+    details: &'a TpvDetails,
+    extra: &'a Map<String, Value>,
+    /// Keys in `extra` that `spill` put there, and which are written as the
+    /// fields above instead.
+    spilled: &'static [&'static str]
+}
+/// Read a field that was moved into `extra` by `spill`, leaving it there.
+fn spilled(extra: &Map<String, Value>, key: &str) -> Option<f64> {
+    extra.get(key).and_then(Value::as_f64)
+}
+impl<'a> From<&'a TpvResponse> for TpvFieldsRef<'a> {
+    fn from(tpv: &'a TpvResponse) -> TpvFieldsRef<'a> {
+        use self::TpvResponse::*;
+        match *tpv {
+            Fix3D {
+                ref device, ref time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, ref details, ref extra
+            } => TpvFieldsRef {
+                device: device.as_ref(), time: Some(time), mode: Some(mode), time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err, alt: Some(alt), alt_err,
+                track, track_err, speed: Some(speed), speed_err, climb: Some(climb), climb_err,
+                details, extra, spilled: &[]
+            },
+            Fix2D {
+                ref device, ref time, mode, time_err, lat, lat_err, lon, lon_err,
+                track, track_err, speed, speed_err, ref details, ref extra
+            } => TpvFieldsRef {
+                device: device.as_ref(), time: Some(time), mode: Some(mode), time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err,
+                alt: spilled(extra, "alt"),
+                alt_err: spilled(extra, "epv"),
+                track, track_err, speed: Some(speed), speed_err,
+                climb: spilled(extra, "climb"),
+                climb_err: spilled(extra, "epc"),
+                details, extra, spilled: &["alt", "epv", "climb", "epc"]
+            },
+            LatLonOnly {
+                ref device, ref time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, ref details, ref extra
+            } => TpvFieldsRef {
+                device: device.as_ref(), time: Some(time), mode, time_err: Some(time_err),
+                lat: Some(lat), lat_err, lon: Some(lon), lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, details, extra, spilled: &[]
+            },
+            NoFix { ref device, ref time, mode, ref details, ref extra } => TpvFieldsRef {
+                device: device.as_ref(), time: Some(time), mode: Some(mode),
+                time_err: spilled(extra, "ept"),
+                lat: None,
+                lat_err: spilled(extra, "epy"),
+                lon: None,
+                lon_err: spilled(extra, "epx"),
+                alt: None,
+                alt_err: spilled(extra, "epv"),
+                track: None,
+                track_err: spilled(extra, "epd"),
+                speed: None,
+                speed_err: spilled(extra, "eps"),
+                climb: None,
+                climb_err: spilled(extra, "epc"),
+                details, extra, spilled: &["ept", "epy", "epx", "epv", "epd", "eps", "epc"]
+            },
+            Nothing { ref device, ref time, mode, ref details, ref extra } => TpvFieldsRef {
+                device: device.as_ref(), time: time.as_ref(), mode,
+                time_err: None, lat: None, lat_err: None, lon: None, lon_err: None,
+                alt: None, alt_err: None, track: None, track_err: None,
+                speed: None, speed_err: None, climb: None, climb_err: None,
+                details, extra, spilled: &[]
+            },
+            Dustbin {
+                ref device, ref time, mode, time_err, lat, lat_err, lon, lon_err, alt, alt_err,
+                track, track_err, speed, speed_err, climb, climb_err, ref details, ref extra
+            } => TpvFieldsRef {
+                device: device.as_ref(), time: time.as_ref(), mode, time_err, lat, lat_err, lon, lon_err,
+                alt, alt_err, track, track_err, speed, speed_err, climb, climb_err, details, extra,
+                spilled: &[]
+            }
+        }
+    }
+}
+impl<'a> Serialize for TpvFieldsRef<'a> {
+    /// Writes the fields in the order gpsd does, followed by `extra`.
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let d = self.details;
+        let mut map = ser.serialize_map(None)?;
+        opt_entry(&mut map, "device", &self.device)?;
+        opt_entry(&mut map, "status", &d.status)?;
+        opt_entry(&mut map, "mode", &self.mode)?;
+        opt_entry(&mut map, "time", &self.time.map(gpsd_time))?;
+        opt_entry(&mut map, "leapseconds", &d.leap_seconds)?;
+        opt_entry(&mut map, "ept", &self.time_err)?;
+        opt_entry(&mut map, "lat", &self.lat)?;
+        opt_entry(&mut map, "lon", &self.lon)?;
+        opt_entry(&mut map, "altHAE", &d.alt_hae)?;
+        opt_entry(&mut map, "altMSL", &d.alt_msl)?;
+        opt_entry(&mut map, "alt", &self.alt)?;
+        opt_entry(&mut map, "epx", &self.lon_err)?;
+        opt_entry(&mut map, "epy", &self.lat_err)?;
+        opt_entry(&mut map, "epv", &self.alt_err)?;
+        opt_entry(&mut map, "track", &self.track)?;
+        opt_entry(&mut map, "magtrack", &d.mag_track)?;
+        opt_entry(&mut map, "magvar", &d.mag_var)?;
+        opt_entry(&mut map, "speed", &self.speed)?;
+        opt_entry(&mut map, "climb", &self.climb)?;
+        opt_entry(&mut map, "epd", &self.track_err)?;
+        opt_entry(&mut map, "eps", &self.speed_err)?;
+        opt_entry(&mut map, "epc", &self.climb_err)?;
+        opt_entry(&mut map, "ecefx", &d.ecef_x)?;
+        opt_entry(&mut map, "ecefy", &d.ecef_y)?;
+        opt_entry(&mut map, "ecefz", &d.ecef_z)?;
+        opt_entry(&mut map, "ecefvx", &d.ecef_vx)?;
+        opt_entry(&mut map, "ecefvy", &d.ecef_vy)?;
+        opt_entry(&mut map, "ecefvz", &d.ecef_vz)?;
+        opt_entry(&mut map, "ecefpAcc", &d.ecef_pos_err)?;
+        opt_entry(&mut map, "ecefvAcc", &d.ecef_vel_err)?;
+        opt_entry(&mut map, "velN", &d.vel_n)?;
+        opt_entry(&mut map, "velE", &d.vel_e)?;
+        opt_entry(&mut map, "velD", &d.vel_d)?;
+        opt_entry(&mut map, "geoidSep", &d.geoid_sep)?;
+        opt_entry(&mut map, "datum", &d.datum)?;
+        opt_entry(&mut map, "eph", &d.horiz_err)?;
+        opt_entry(&mut map, "sep", &d.sphere_err)?;
+        opt_entry(&mut map, "depth", &d.depth)?;
+        opt_entry(&mut map, "dgpsAge", &d.dgps_age)?;
+        opt_entry(&mut map, "dgpsSta", &d.dgps_station)?;
+        opt_entry(&mut map, "wanglem", &d.wind_angle_mag)?;
+        opt_entry(&mut map, "wangler", &d.wind_angle_rel)?;
+        opt_entry(&mut map, "wanglet", &d.wind_angle_true)?;
+        opt_entry(&mut map, "wspeedr", &d.wind_speed_rel)?;
+        opt_entry(&mut map, "wspeedt", &d.wind_speed_true)?;
+        for (key, val) in self.extra.iter() {
+            if !self.spilled.contains(&&key[..]) {
+                map.serialize_entry(key, val)?;
+            }
+        }
+        map.end()
+    }
+}
+impl Serialize for TpvResponse {
+    /// Serializes the TPV object as gpsd would send it, whatever the variant:
+    /// fields that were moved into `extra` go back where they came from.
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        TpvFieldsRef::from(self).serialize(ser)
+    }
+}
+impl TpvResponse {
+    /// Name of originating device.
+    pub fn device(&self) -> Option<&str> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { ref device, .. } | Fix2D { ref device, .. } |
+            LatLonOnly { ref device, .. } | NoFix { ref device, .. } |
+            Nothing { ref device, .. } | Dustbin { ref device, .. } => device.as_ref().map(|x| x as &str)
+        }
+    }
+    /// Name of originating device, for changing it.
+    pub fn device_mut(&mut self) -> &mut Option<String> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { ref mut device, .. } | Fix2D { ref mut device, .. } |
+            LatLonOnly { ref mut device, .. } | NoFix { ref mut device, .. } |
+            Nothing { ref mut device, .. } | Dustbin { ref mut device, .. } => device
+        }
+    }
+    /// Timestamp.
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { time, .. } | Fix2D { time, .. } |
+            LatLonOnly { time, .. } | NoFix { time, .. } => Some(time),
+            Nothing { time, .. } | Dustbin { time, .. } => time
+        }
+    }
+    /// Fix type. If gpsd didn't say, this is `FixMode::Unknown`.
+    pub fn mode(&self) -> FixMode {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { mode, .. } | Fix2D { mode, .. } | NoFix { mode, .. } => mode,
+            LatLonOnly { mode, .. } | Nothing { mode, .. } |
+            Dustbin { mode, .. } => mode.unwrap_or(FixMode::Unknown)
+        }
+    }
+    /// Latitude in degrees: +/- signifies North/South.
+    pub fn lat(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { lat, .. } | Fix2D { lat, .. } | LatLonOnly { lat, .. } => Some(lat),
+            Dustbin { lat, .. } => lat,
+            NoFix { .. } | Nothing { .. } => None
+        }
+    }
+    /// Longitude in degrees: +/- signifies East/West.
+    pub fn lon(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { lon, .. } | Fix2D { lon, .. } | LatLonOnly { lon, .. } => Some(lon),
+            Dustbin { lon, .. } => lon,
+            NoFix { .. } | Nothing { .. } => None
+        }
+    }
+    /// Altitude in meters. gpsd 3.14 and later may only send `altMSL`, which
+    /// is what `alt` used to mean, so that's used if there's no `alt`.
+    pub fn alt(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        let alt = match *self {
+            Fix3D { alt, .. } => Some(alt),
+            LatLonOnly { alt, .. } | Dustbin { alt, .. } => alt,
+            Fix2D { .. } | NoFix { .. } | Nothing { .. } => None
+        };
+        alt.or(self.details().alt_msl)
+    }
+    /// Course over ground, degrees from true north.
+    pub fn track(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { track, .. } | Fix2D { track, .. } |
+            LatLonOnly { track, .. } | Dustbin { track, .. } => track,
+            NoFix { .. } | Nothing { .. } => None
+        }
+    }
+    /// Speed over ground, meters per second.
+    pub fn speed(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { speed, .. } | Fix2D { speed, .. } => Some(speed),
+            LatLonOnly { speed, .. } | Dustbin { speed, .. } => speed,
+            NoFix { .. } | Nothing { .. } => None
+        }
+    }
+    /// Climb (positive) or sink (negative) rate, meters per second.
+    pub fn climb(&self) -> Option<f64> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { climb, .. } => Some(climb),
+            LatLonOnly { climb, .. } | Dustbin { climb, .. } => climb,
+            Fix2D { .. } | NoFix { .. } | Nothing { .. } => None
+        }
+    }
+    /// Error estimates, at 95% confidence.
+    pub fn errors(&self) -> TpvErrors {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { time_err, lat_err, lon_err, alt_err, track_err, speed_err, climb_err, .. } => TpvErrors {
+                time: Some(time_err),
+                lat: lat_err,
+                lon: lon_err,
+                alt: alt_err,
+                track: track_err,
+                speed: speed_err,
+                climb: climb_err
+            },
+            Fix2D { time_err, lat_err, lon_err, track_err, speed_err, .. } => TpvErrors {
+                time: Some(time_err),
+                lat: lat_err,
+                lon: lon_err,
+                track: track_err,
+                speed: speed_err,
+                ..Default::default()
+            },
+            LatLonOnly { time_err, lat_err, lon_err, alt_err, track_err, speed_err, climb_err, .. } => TpvErrors {
+                time: Some(time_err),
+                lat: lat_err,
+                lon: lon_err,
+                alt: alt_err,
+                track: track_err,
+                speed: speed_err,
+                climb: climb_err
+            },
+            Dustbin { time_err, lat_err, lon_err, alt_err, track_err, speed_err, climb_err, .. } => TpvErrors {
+                time: time_err,
+                lat: lat_err,
+                lon: lon_err,
+                alt: alt_err,
+                track: track_err,
+                speed: speed_err,
+                climb: climb_err
+            },
+            NoFix { .. } | Nothing { .. } => Default::default()
+        }
+    }
+    /// Fields added in newer versions of gpsd.
+    pub fn details(&self) -> &TpvDetails {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { ref details, .. } | Fix2D { ref details, .. } |
+            LatLonOnly { ref details, .. } | NoFix { ref details, .. } |
+            Nothing { ref details, .. } | Dustbin { ref details, .. } => details
+        }
+    }
+    /// Any fields not otherwise recognised.
+    pub fn extra(&self) -> &Map<String, Value> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { ref extra, .. } | Fix2D { ref extra, .. } |
+            LatLonOnly { ref extra, .. } | NoFix { ref extra, .. } |
+            Nothing { ref extra, .. } | Dustbin { ref extra, .. } => extra
+        }
+    }
+    /// Any fields not otherwise recognised, for changing them.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        use self::TpvResponse::*;
+        match *self {
+            Fix3D { ref mut extra, .. } | Fix2D { ref mut extra, .. } |
+            LatLonOnly { ref mut extra, .. } | NoFix { ref mut extra, .. } |
+            Nothing { ref mut extra, .. } | Dustbin { ref mut extra, .. } => extra
+        }
+    }
+    /// The position reported, if there is at least a latitude and longitude.
+    pub fn position(&self) -> Option<Position> {
+        Some(Position {
+            lat: self.lat()?,
+            lon: self.lon()?,
+            alt: self.alt()
+        })
+    }
+    /// The velocity reported, if there is at least a speed.
+    pub fn velocity(&self) -> Option<Velocity> {
+        Some(Velocity {
+            speed: self.speed()?,
+            track: self.track(),
+            climb: self.climb()
+        })
+    }
+}
+/// Error estimates from a TPV report, in the units of the corresponding
+/// fields, at 95% confidence.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TpvErrors {
+    /// Estimated timestamp error (seconds).
+    pub time: Option<f64>,
+    /// Latitude error estimate in meters.
+    pub lat: Option<f64>,
+    /// Longitude error estimate in meters.
+    pub lon: Option<f64>,
+    /// Estimated vertical error in meters.
+    pub alt: Option<f64>,
+    /// Direction error estimate in degrees.
+    pub track: Option<f64>,
+    /// Speed error estimate in meters/sec.
+    pub speed: Option<f64>,
+    /// Climb/sink error estimate in meters/sec.
+    pub climb: Option<f64>
+}
+/// A position, extracted from a TPV report with `TpvResponse::position`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Latitude in degrees: +/- signifies North/South.
+    pub lat: f64,
+    /// Longitude in degrees: +/- signifies East/West.
+    pub lon: f64,
+    /// Altitude in meters, if known.
+    pub alt: Option<f64>
+}
+/// A velocity, extracted from a TPV report with `TpvResponse::velocity`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Velocity {
+    /// Speed over ground, meters per second.
+    pub speed: f64,
+    /// Course over ground, degrees from true north, if known.
+    pub track: Option<f64>,
+    /// Climb (positive) or sink (negative) rate, meters per second, if known.
+    pub climb: Option<f64>
+}
+/// The type of fix in a TPV report.
+///
+/// This (de)serializes as the integer gpsd uses on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FixMode {
+    /// Mode not yet known (0).
+    Unknown,
+    /// No fix (1).
+    NoFix,
+    /// Two-dimensional fix (2).
+    Fix2D,
+    /// Three-dimensional fix (3).
+    Fix3D
+}
+impl FixMode {
+    /// Look up a fix mode by its wire value.
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(FixMode::Unknown),
+            1 => Some(FixMode::NoFix),
+            2 => Some(FixMode::Fix2D),
+            3 => Some(FixMode::Fix3D),
+            _ => None
+        }
+    }
+    /// Does this mode indicate a fix with at least a latitude and longitude?
+    pub fn has_fix(&self) -> bool {
+        *self >= FixMode::Fix2D
+    }
+}
+impl From<FixMode> for u8 {
+    fn from(v: FixMode) -> u8 {
+        match v {
+            FixMode::Unknown => 0,
+            FixMode::NoFix => 1,
+            FixMode::Fix2D => 2,
+            FixMode::Fix3D => 3
+        }
+    }
+}
+impl Serialize for FixMode {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_u8((*self).into())
+    }
+}
+impl<'de> Deserialize<'de> for FixMode {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let v = u8::deserialize(de)?;
+        FixMode::from_u8(v)
+            .ok_or_else(|| D::Error::invalid_value(Unexpected::Unsigned(v as u64), &"a fix mode from 0 to 3"))
+    }
+}
+/// GPS fix status, as reported in the `status` field of a TPV.
+///
+/// This is only reported by newer versions of gpsd, and refines the fix mode
+/// with information about whether (and how) the fix is augmented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixStatus {
+    /// Unknown status.
+    Unknown,
+    /// Normal GPS fix.
+    Normal,
+    /// Differential GPS fix.
+    Dgps,
+    /// RTK fixed solution.
+    RtkFixed,
+    /// RTK floating solution.
+    RtkFloat,
+    /// Dead reckoning.
+    DeadReckoning,
+    /// GNSS and dead reckoning combined.
+    GnssDeadReckoning,
+    /// Time only (surveyed in, fixed location).
+    TimeOnly,
+    /// Simulated.
+    Simulated,
+    /// P(Y) code fix.
+    PyCode,
+    /// A status value this library doesn't know about.
+    Other(u8)
+}
+impl From<u8> for FixStatus {
+    fn from(v: u8) -> Self {
+        use self::FixStatus::*;
+        match v {
+            0 => Unknown,
+            1 => Normal,
+            2 => Dgps,
+            3 => RtkFixed,
+            4 => RtkFloat,
+            5 => DeadReckoning,
+            6 => GnssDeadReckoning,
+            7 => TimeOnly,
+            8 => Simulated,
+            9 => PyCode,
+            x => Other(x)
+        }
+    }
+}
+impl From<FixStatus> for u8 {
+    fn from(v: FixStatus) -> u8 {
+        use self::FixStatus::*;
+        match v {
+            Unknown => 0,
+            Normal => 1,
+            Dgps => 2,
+            RtkFixed => 3,
+            RtkFloat => 4,
+            DeadReckoning => 5,
+            GnssDeadReckoning => 6,
+            TimeOnly => 7,
+            Simulated => 8,
+            PyCode => 9,
+            Other(x) => x
+        }
+    }
+}
+impl Serialize for FixStatus {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_u8((*self).into())
+    }
+}
+impl<'de> Deserialize<'de> for FixStatus {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        u8::deserialize(de).map(Into::into)
+    }
+}
+/// Fields of a TPV report that were added in newer versions of gpsd.
+///
+/// All of these are optional; older daemons won't send any of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TpvDetails {
+    /// GPS fix status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<FixStatus>,
+    /// Altitude, height above ellipsoid, in meters.
+    #[serde(rename = "altHAE", skip_serializing_if = "Option::is_none")]
+    pub alt_hae: Option<f64>,
+    /// MSL altitude in meters. The geoid used is rarely specified and is often
+    /// inaccurate.
+    #[serde(rename = "altMSL", skip_serializing_if = "Option::is_none")]
+    pub alt_msl: Option<f64>,
+    /// Geoid separation: the difference between the WGS84 reference
+    /// ellipsoid and the geoid (mean sea level) in meters.
+    #[serde(rename = "geoidSep", skip_serializing_if = "Option::is_none")]
+    pub geoid_sep: Option<f64>,
+    /// Current datum. Hopefully WGS84.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datum: Option<String>,
+    /// Estimated horizontal position (2D) error in meters. Also known as
+    /// Estimated Position Error (eph).
+    #[serde(rename = "eph", skip_serializing_if = "Option::is_none")]
+    pub horiz_err: Option<f64>,
+    /// Estimated spherical (3D) position error in meters.
+    #[serde(rename = "sep", skip_serializing_if = "Option::is_none")]
+    pub sphere_err: Option<f64>,
+    /// Course over ground, degrees magnetic.
+    #[serde(rename = "magtrack", skip_serializing_if = "Option::is_none")]
+    pub mag_track: Option<f64>,
+    /// Magnetic variation, degrees. Also known as the magnetic declination
+    /// (the direction of the horizontal component of the magnetic field
+    /// measured clockwise from north).
+    #[serde(rename = "magvar", skip_serializing_if = "Option::is_none")]
+    pub mag_var: Option<f64>,
+    /// Age of DGPS data, in seconds.
+    #[serde(rename = "dgpsAge", skip_serializing_if = "Option::is_none")]
+    pub dgps_age: Option<f64>,
+    /// Station of DGPS data.
+    #[serde(rename = "dgpsSta", skip_serializing_if = "Option::is_none")]
+    pub dgps_station: Option<u32>,
+    /// ECEF X position in meters.
+    #[serde(rename = "ecefx", skip_serializing_if = "Option::is_none")]
+    pub ecef_x: Option<f64>,
+    /// ECEF Y position in meters.
+    #[serde(rename = "ecefy", skip_serializing_if = "Option::is_none")]
+    pub ecef_y: Option<f64>,
+    /// ECEF Z position in meters.
+    #[serde(rename = "ecefz", skip_serializing_if = "Option::is_none")]
+    pub ecef_z: Option<f64>,
+    /// ECEF X velocity in meters per second.
+    #[serde(rename = "ecefvx", skip_serializing_if = "Option::is_none")]
+    pub ecef_vx: Option<f64>,
+    /// ECEF Y velocity in meters per second.
+    #[serde(rename = "ecefvy", skip_serializing_if = "Option::is_none")]
+    pub ecef_vy: Option<f64>,
+    /// ECEF Z velocity in meters per second.
+    #[serde(rename = "ecefvz", skip_serializing_if = "Option::is_none")]
+    pub ecef_vz: Option<f64>,
+    /// ECEF position error in meters. Certainty unknown.
+    #[serde(rename = "ecefpAcc", skip_serializing_if = "Option::is_none")]
+    pub ecef_pos_err: Option<f64>,
+    /// ECEF velocity error in meters per second. Certainty unknown.
+    #[serde(rename = "ecefvAcc", skip_serializing_if = "Option::is_none")]
+    pub ecef_vel_err: Option<f64>,
+    /// North velocity component in meters per second.
+    #[serde(rename = "velN", skip_serializing_if = "Option::is_none")]
+    pub vel_n: Option<f64>,
+    /// East velocity component in meters per second.
+    #[serde(rename = "velE", skip_serializing_if = "Option::is_none")]
+    pub vel_e: Option<f64>,
+    /// Down velocity component in meters per second.
+    #[serde(rename = "velD", skip_serializing_if = "Option::is_none")]
+    pub vel_d: Option<f64>,
+    /// Current leap seconds.
+    #[serde(rename = "leapseconds", skip_serializing_if = "Option::is_none")]
+    pub leap_seconds: Option<i32>,
+    /// Depth in meters. Probably depth below the keel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<f64>,
+    /// Wind angle magnetic in degrees.
+    #[serde(rename = "wanglem", skip_serializing_if = "Option::is_none")]
+    pub wind_angle_mag: Option<f64>,
+    /// Wind angle relative in degrees.
+    #[serde(rename = "wangler", skip_serializing_if = "Option::is_none")]
+    pub wind_angle_rel: Option<f64>,
+    /// Wind angle true in degrees.
+    #[serde(rename = "wanglet", skip_serializing_if = "Option::is_none")]
+    pub wind_angle_true: Option<f64>,
+    /// Wind speed relative in meters per second.
+    #[serde(rename = "wspeedr", skip_serializing_if = "Option::is_none")]
+    pub wind_speed_rel: Option<f64>,
+    /// Wind speed true in meters per second.
+    #[serde(rename = "wspeedt", skip_serializing_if = "Option::is_none")]
+    pub wind_speed_true: Option<f64>
+}
+/// A GNSS constellation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GnssSystem {
+    /// GPS (United States).
+    Gps,
+    /// Satellite-based augmentation systems (WAAS, EGNOS, MSAS, ...).
+    Sbas,
+    /// Galileo (European Union).
+    Galileo,
+    /// BeiDou (China).
+    BeiDou,
+    /// Indoor Messaging System (Japan).
+    Imes,
+    /// Quasi-Zenith Satellite System (Japan).
+    Qzss,
+    /// GLONASS (Russia).
+    Glonass,
+    /// NavIC, also known as IRNSS (India).
+    NavIc
+}
+impl GnssSystem {
+    /// Look up a system by its gpsd `gnssid`.
+    pub fn from_gnssid(id: u8) -> Option<Self> {
+        use self::GnssSystem::*;
+        match id {
+            0 => Some(Gps),
+            1 => Some(Sbas),
+            2 => Some(Galileo),
+            3 => Some(BeiDou),
+            4 => Some(Imes),
+            5 => Some(Qzss),
+            6 => Some(Glonass),
+            7 => Some(NavIc),
+            _ => None
+        }
+    }
+    /// Guess the system from a gpsd PRN, for daemons that don't send a
+    /// `gnssid`.
+    ///
+    /// This uses the PRN ranges gpsd documents: 1-63 GPS, 64-96 GLONASS,
+    /// 100-164 SBAS, 173-182 IMES, 193-200 QZSS, 201-237 and 401-437 BeiDou,
+    /// 301-336 Galileo. NavIC has no PRN range, so is never returned.
+    pub fn from_prn(prn: u16) -> Option<Self> {
+        use self::GnssSystem::*;
+        match prn {
+            1..=63 => Some(Gps),
+            64..=96 => Some(Glonass),
+            100..=164 => Some(Sbas),
+            173..=182 => Some(Imes),
+            193..=200 => Some(Qzss),
+            201..=237 | 401..=437 => Some(BeiDou),
+            301..=336 => Some(Galileo),
+            _ => None
+        }
+    }
+}
+impl TpvDetails {
+    /// Deserialize the value of the TPV field `key` into this struct, returning
+    /// `false` if it isn't one of ours.
+    fn visit_field<'de, A: MapAccess<'de>>(&mut self, key: &str, map: &mut A) -> Result<bool, A::Error> {
+        match key {
+            "status" => self.status = tpv_field(map, key)?,
+            "altHAE" => self.alt_hae = tpv_field(map, key)?,
+            "altMSL" => self.alt_msl = tpv_field(map, key)?,
+            "geoidSep" => self.geoid_sep = tpv_field(map, key)?,
+            "datum" => self.datum = tpv_field(map, key)?,
+            "eph" => self.horiz_err = tpv_field(map, key)?,
+            "sep" => self.sphere_err = tpv_field(map, key)?,
+            "magtrack" => self.mag_track = tpv_field(map, key)?,
+            "magvar" => self.mag_var = tpv_field(map, key)?,
+            "dgpsAge" => self.dgps_age = tpv_field(map, key)?,
+            "dgpsSta" => self.dgps_station = tpv_field(map, key)?,
+            "ecefx" => self.ecef_x = tpv_field(map, key)?,
+            "ecefy" => self.ecef_y = tpv_field(map, key)?,
+            "ecefz" => self.ecef_z = tpv_field(map, key)?,
+            "ecefvx" => self.ecef_vx = tpv_field(map, key)?,
+            "ecefvy" => self.ecef_vy = tpv_field(map, key)?,
+            "ecefvz" => self.ecef_vz = tpv_field(map, key)?,
+            "ecefpAcc" => self.ecef_pos_err = tpv_field(map, key)?,
+            "ecefvAcc" => self.ecef_vel_err = tpv_field(map, key)?,
+            "velN" => self.vel_n = tpv_field(map, key)?,
+            "velE" => self.vel_e = tpv_field(map, key)?,
+            "velD" => self.vel_d = tpv_field(map, key)?,
+            "leapseconds" => self.leap_seconds = tpv_field(map, key)?,
+            "depth" => self.depth = tpv_field(map, key)?,
+            "wanglem" => self.wind_angle_mag = tpv_field(map, key)?,
+            "wangler" => self.wind_angle_rel = tpv_field(map, key)?,
+            "wanglet" => self.wind_angle_true = tpv_field(map, key)?,
+            "wspeedr" => self.wind_speed_rel = tpv_field(map, key)?,
+            "wspeedt" => self.wind_speed_true = tpv_field(map, key)?,
+            _ => return Ok(false)
+        }
+        Ok(true)
     }
 }
 /// A single satellite.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SatelliteObject {
     #[serde(rename = "PRN")]
     /// PRN ID of the satellite. 1-63 are GNSS satellites, 64-96 are GLONASS
     /// satellites, 100-164 are SBAS satellites
     pub prn: u16,
-    #[serde(rename = "az")]
-    /// Azimuth, degrees from true north.
-    pub azimuth: f32,
     #[serde(rename = "el")]
     /// Elevation in degrees.
-    pub elevation: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<f32>,
+    #[serde(rename = "az")]
+    /// Azimuth, degrees from true north.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azimuth: Option<f32>,
     #[serde(rename = "ss")]
     /// Signal strength in dB.
-    pub signal_strength: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal_strength: Option<f32>,
+    #[serde(default = "serde_false")]
     /// Used in current solution? (SBAS/WAAS/EGNOS satellites may be flagged
     /// used if the solution has corrections from them, but not all drivers make
     /// this information available.)
-    pub used: bool
+    pub used: bool,
+    #[serde(rename = "gnssid")]
+    /// The GNSS ID, as defined by u-blox, not NMEA. See `GnssSystem`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gnss_id: Option<u8>,
+    #[serde(rename = "svid")]
+    /// The satellite ID within its constellation. As defined by u-blox, not
+    /// NMEA.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sv_id: Option<u16>,
+    #[serde(rename = "sigid")]
+    /// The signal ID of this signal. As defined by u-blox, not NMEA.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sig_id: Option<u8>,
+    #[serde(rename = "freqid")]
+    /// For GLONASS satellites only: the frequency ID of the signal. As defined
+    /// by u-blox, range 0 to 13. The frequency slot is `freq_id - 7`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freq_id: Option<u8>,
+    /// The health of this satellite. 0 is unknown, 1 is OK, and 2 is
+    /// unhealthy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<u8>,
+    #[serde(rename = "pr")]
+    /// Pseudorange, in meters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pseudorange: Option<f64>,
+    #[serde(rename = "prRate")]
+    /// Pseudorange rate of change, in meters per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_rate: Option<f64>,
+    #[serde(rename = "prRes")]
+    /// Pseudorange residue, in meters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_res: Option<f64>,
+    #[serde(rename = "qual")]
+    /// Quality indicator, 0 to 7:
+    ///
+    /// - 0: no signal
+    /// - 1: searching signal
+    /// - 2: signal acquired
+    /// - 3: signal detected but unusable
+    /// - 4: code locked and time synchronized
+    /// - 5-7: code and carrier locked and time synchronized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Any fields not otherwise recognised.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
 }
-#[derive(Serialize, Deserialize, Debug)]
+impl SatelliteObject {
+    /// Which constellation this satellite belongs to, from its `gnss_id` if
+    /// present and its PRN otherwise.
+    pub fn system(&self) -> Option<GnssSystem> {
+        match self.gnss_id {
+            Some(id) => GnssSystem::from_gnssid(id),
+            None => GnssSystem::from_prn(self.prn)
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// A sky view report (SKY) of GPS satellite positions.
 ///
 /// If there is no GPS device available, or no skyview has been reported yet,
@@ -232,27 +1323,200 @@ pub struct SatelliteObject {
 /// covariance calculation.
 pub struct SkyResponse {
     /// Name of originating device.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub device: Option<String>,
     /// Timestamp.
+    #[serde(serialize_with = "serialize_opt_time", skip_serializing_if = "Option::is_none")]
     pub time: Option<DateTime<Utc>>,
     /// Longitudinal d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub xdop: Option<f32>,
     /// Latitutinal d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ydop: Option<f32>,
     /// Altitude d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub vdop: Option<f32>,
     /// Time d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tdop: Option<f32>,
     /// Horizontal d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub hdop: Option<f32>,
-    /// Spherical d.o.p.
-    pub pdop: Option<f32>,
     /// Hyperspherical d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gdop: Option<f32>,
-    /// Satellites in skyview.
-    pub satellites: Vec<SatelliteObject>
+    /// Spherical d.o.p.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdop: Option<f32>,
+    /// Number of satellite objects in `satellites`.
+    #[serde(rename = "nSat", skip_serializing_if = "Option::is_none")]
+    pub n_sat: Option<u32>,
+    /// Number of satellites used in the navigation solution.
+    #[serde(rename = "uSat", skip_serializing_if = "Option::is_none")]
+    pub u_sat: Option<u32>,
+    /// Satellites in skyview. Newer versions of gpsd may omit this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub satellites: Option<Vec<SatelliteObject>>,
+    /// Any fields not otherwise recognised.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
 }
-#[derive(Serialize, Deserialize, Debug)]
+/// Packet types gpsd has seen from a device, as reported in the `flags` field
+/// of a DEVICE object.
+///
+/// This (de)serializes as the integer bit vector gpsd uses on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DeviceFlags(u8);
+impl DeviceFlags {
+    /// GPS data seen.
+    pub const GPS: DeviceFlags = DeviceFlags(0x01);
+    /// RTCM2 data seen.
+    pub const RTCM2: DeviceFlags = DeviceFlags(0x02);
+    /// RTCM3 data seen.
+    pub const RTCM3: DeviceFlags = DeviceFlags(0x04);
+    /// AIS data seen.
+    pub const AIS: DeviceFlags = DeviceFlags(0x08);
+
+    /// No flags set.
+    pub fn empty() -> Self {
+        DeviceFlags(0)
+    }
+    /// Make a set of flags from the raw bit vector. Unknown bits are kept.
+    pub fn from_bits(bits: u8) -> Self {
+        DeviceFlags(bits)
+    }
+    /// The raw bit vector.
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+    /// Are no flags set?
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    /// Are all of the flags in `other` set?
+    pub fn contains(&self, other: DeviceFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Set the flags in `other`.
+    pub fn insert(&mut self, other: DeviceFlags) {
+        self.0 |= other.0;
+    }
+    /// Clear the flags in `other`.
+    pub fn remove(&mut self, other: DeviceFlags) {
+        self.0 &= !other.0;
+    }
+}
+impl BitOr for DeviceFlags {
+    type Output = DeviceFlags;
+    fn bitor(self, rhs: DeviceFlags) -> DeviceFlags {
+        DeviceFlags(self.0 | rhs.0)
+    }
+}
+impl BitOrAssign for DeviceFlags {
+    fn bitor_assign(&mut self, rhs: DeviceFlags) {
+        self.0 |= rhs.0;
+    }
+}
+impl BitAnd for DeviceFlags {
+    type Output = DeviceFlags;
+    fn bitand(self, rhs: DeviceFlags) -> DeviceFlags {
+        DeviceFlags(self.0 & rhs.0)
+    }
+}
+/// Serial port parity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity (`N`).
+    #[serde(rename = "N")]
+    None,
+    /// Odd parity (`O`).
+    #[serde(rename = "O")]
+    Odd,
+    /// Even parity (`E`).
+    #[serde(rename = "E")]
+    Even
+}
+/// Serial port stop bits.
+///
+/// This serializes as an integer. Some versions of gpsd send it as a string,
+/// so that's accepted too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit.
+    One,
+    /// Two stop bits.
+    Two
+}
+impl Serialize for StopBits {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_u8(match *self {
+            StopBits::One => 1,
+            StopBits::Two => 2
+        })
+    }
+}
+impl<'de> Deserialize<'de> for StopBits {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct StopBitsVisitor;
+        impl<'de> Visitor<'de> for StopBitsVisitor {
+            type Value = StopBits;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "1 or 2 stop bits")
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<StopBits, E> {
+                match v {
+                    1 => Ok(StopBits::One),
+                    2 => Ok(StopBits::Two),
+                    _ => Err(E::invalid_value(Unexpected::Unsigned(v), &self))
+                }
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<StopBits, E> {
+                match v {
+                    1 => Ok(StopBits::One),
+                    2 => Ok(StopBits::Two),
+                    _ => Err(E::invalid_value(Unexpected::Signed(v), &self))
+                }
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<StopBits, E> {
+                match v {
+                    "1" => Ok(StopBits::One),
+                    "2" => Ok(StopBits::Two),
+                    _ => Err(E::invalid_value(Unexpected::Str(v), &self))
+                }
+            }
+        }
+        de.deserialize_any(StopBitsVisitor)
+    }
+}
+/// Device mode: NMEA, or the device's alternate (usually binary) mode.
+///
+/// This (de)serializes as the integer gpsd uses on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeMode {
+    /// NMEA mode (0).
+    Nmea,
+    /// Alternate mode (1): binary, if the device has one.
+    Alternate
+}
+impl Serialize for NativeMode {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_u8(match *self {
+            NativeMode::Nmea => 0,
+            NativeMode::Alternate => 1
+        })
+    }
+}
+impl<'de> Deserialize<'de> for NativeMode {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        match u8::deserialize(de)? {
+            0 => Ok(NativeMode::Nmea),
+            1 => Ok(NativeMode::Alternate),
+            v => Err(D::Error::invalid_value(Unexpected::Unsigned(v as u64), &"0 or 1"))
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 /// Information about a device known to gpsd.
 ///
@@ -268,62 +1532,108 @@ pub enum DeviceObject {
         /// Name the device for which the control bits are being reported, or
         /// for which they are to be applied. This attribute may be omitted only
         /// when there is exactly one subscribed channel
+        #[serde(skip_serializing_if = "Option::is_none")]
         path: Option<String>,
-        /// Time the device was activated as an ISO8601 timestamp. If the device
-        /// is inactive this attribute is absent.
-        activated: DateTime<Utc>,
-        /// Bit vector of property flags. Currently defined flags are: describe
-        /// packet types seen so far (GPS, RTCM2, RTCM3, AIS). Won't be reported
-        /// if empty, e.g. before gpsd has seen identifiable packets from the
-        /// device.
-        ///
-        /// # Flags
-        ///
-        /// - 0x01: GPS data seen
-        /// - 0x02: RTCM2 data seen
-        /// - 0x04: RTCM3 data seen
-        /// - 0x08: AIS data seen
-        ///
-        /// Yes, I know manual bitflags suck. I'll fix it one day if you bug me.
-        flags: u8,
         /// GPSD's name for the device driver type. Won't be reported before
         /// gpsd has seen identifiable packets from the device.
         driver: String,
         /// Whatever version information the device returned.
+        #[serde(skip_serializing_if = "Option::is_none")]
         subtype: Option<String>,
-        /// Device speed in bits per second.
-        bps: Option<u32>,
-        /// N, O or E for no parity, odd, or even.
-        parity: Option<String>,
-        /// Stop bits (1 or 2).
-        stopbits: Option<String>,
+        /// Time the device was activated as an ISO8601 timestamp. If the device
+        /// is inactive this attribute is absent.
+        #[serde(serialize_with = "serialize_time")]
+        activated: DateTime<Utc>,
+        /// Property flags, describing the packet types seen so far (GPS, RTCM2,
+        /// RTCM3, AIS). Won't be reported if empty, e.g. before gpsd has seen
+        /// identifiable packets from the device.
+        flags: DeviceFlags,
         /// 0 means NMEA mode and 1 means alternate mode (binary if it has one,
         /// for SiRF and Evermore chipsets in particular). Attempting to set
         /// this mode on a non-GPS device will yield an error.
-        native: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        native: Option<NativeMode>,
+        /// Device speed in bits per second.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bps: Option<u32>,
+        /// Parity: none, odd, or even.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parity: Option<Parity>,
+        /// Stop bits (1 or 2).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stopbits: Option<StopBits>,
         /// Device cycle time in seconds.
+        #[serde(skip_serializing_if = "Option::is_none")]
         cycle: Option<f32>,
         /// Device minimum cycle time in seconds. Reported from ?DEVICE when
         /// (and only when) the rate is switchable. It is read-only and not
         /// settable.
-        minicycle: Option<f32>
+        #[serde(rename = "mincycle", skip_serializing_if = "Option::is_none")]
+        minicycle: Option<f32>,
+        /// Any fields not otherwise recognised.
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
     Active {
+        #[serde(skip_serializing_if = "Option::is_none")]
         path: Option<String>,
-        activated: DateTime<Utc>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         subtype: Option<String>,
+        #[serde(serialize_with = "serialize_time")]
+        activated: DateTime<Utc>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        native: Option<NativeMode>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         bps: Option<u32>,
-        parity: Option<String>,
-        stopbits: Option<String>,
-        native: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parity: Option<Parity>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stopbits: Option<StopBits>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         cycle: Option<f32>,
-        minicycle: Option<f32>
+        #[serde(rename = "mincycle", skip_serializing_if = "Option::is_none")]
+        minicycle: Option<f32>,
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
     Inactive {
-        path: Option<String>
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(flatten)]
+        extra: Map<String, Value>
+    }
+}
+impl DeviceObject {
+    /// Name of the device.
+    pub fn path(&self) -> Option<&str> {
+        match *self {
+            DeviceObject::ActiveSeenPackets { ref path, .. } | DeviceObject::Active { ref path, .. } |
+            DeviceObject::Inactive { ref path, .. } => path.as_ref().map(|x| x as &str)
+        }
+    }
+    /// Name of the device, for changing it.
+    pub fn path_mut(&mut self) -> &mut Option<String> {
+        match *self {
+            DeviceObject::ActiveSeenPackets { ref mut path, .. } | DeviceObject::Active { ref mut path, .. } |
+            DeviceObject::Inactive { ref mut path, .. } => path
+        }
+    }
+    /// Any fields not otherwise recognised.
+    pub fn extra(&self) -> &Map<String, Value> {
+        match *self {
+            DeviceObject::ActiveSeenPackets { ref extra, .. } | DeviceObject::Active { ref extra, .. } |
+            DeviceObject::Inactive { ref extra, .. } => extra
+        }
+    }
+    /// Any fields not otherwise recognised, for changing them.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        match *self {
+            DeviceObject::ActiveSeenPackets { ref mut extra, .. } | DeviceObject::Active { ref mut extra, .. } |
+            DeviceObject::Inactive { ref mut extra, .. } => extra
+        }
     }
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// Information about watcher mode parameters.
 pub struct WatchObject {
     #[serde(default = "serde_true")]
@@ -343,12 +1653,17 @@ pub struct WatchObject {
     /// are not dumped in raw mode. When this attribute is set to 2 for a
     /// channel that processes binary data, gpsd reports the received data
     /// verbatim without hex-dumping.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub raw: Option<u32>,
     #[serde(default = "serde_false")]
     /// If true, apply scaling divisors to output before dumping; default is
     /// false.
     pub scaled: bool,
     #[serde(default = "serde_false")]
+    /// If true, report how long gpsd took to process each packet, for
+    /// profiling. Default is false.
+    pub timing: bool,
+    #[serde(default = "serde_false")]
     /// If true, aggregate AIS type24 sentence parts. If false, report each part
     /// as a separate JSON object, leaving the client to match MMSIs and
     /// aggregate. Default is false. Applies only to AIS reports.
@@ -360,10 +1675,15 @@ pub struct WatchObject {
     /// If present, enable watching only of the specified device rather than all
     /// devices. Useful with raw and NMEA modes in which device responses aren't
     /// tagged. Has no effect when used with enable:false.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub device: Option<String>,
     /// URL of the remote daemon reporting the watch set. If empty, this is a
     /// WATCH response from the local daemon.
-    pub remote: Option<String>
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Any fields not otherwise recognised.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
 }
 impl Default for WatchObject {
     fn default() -> Self {
@@ -373,15 +1693,44 @@ impl Default for WatchObject {
             nmea: false,
             raw: None,
             scaled: false,
+            timing: false,
             split24: false,
             pps: false,
             device: None,
-            remote: None
+            remote: None,
+            extra: Map::new()
         }
     }
 }
-#[derive(Serialize, Deserialize, Debug)]
+/// Settings to change on a device, with `GpsdConnection::configure_device`.
+///
+/// Fields left as `None` aren't changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "class", rename = "DEVICE")]
+pub struct DeviceConfig {
+    /// Path of the device to configure. This may be omitted only when there is
+    /// exactly one subscribed channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Device speed in bits per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps: Option<u32>,
+    /// Parity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parity: Option<Parity>,
+    /// Stop bits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stopbits: Option<StopBits>,
+    /// NMEA or alternate (binary) mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub native: Option<NativeMode>,
+    /// Device cycle time in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycle: Option<f32>
+}
+#[derive(Serialize, Debug, Clone)]
 #[serde(tag = "class")]
+#[allow(clippy::large_enum_variant)]
 /// A response from GPSD.
 ///
 /// For single-struct variants, the documentation on the struct usually has
@@ -394,18 +1743,28 @@ pub enum Response {
     #[serde(rename = "POLL")]
     /// Data from the last-seen fixes on all active GPS devices.
     Poll {
+        #[serde(serialize_with = "serialize_time")]
         time: DateTime<Utc>,
         /// Count of active devices.
         active: u32,
+        #[serde(serialize_with = "serialize_poll_tpv")]
         tpv: Vec<TpvResponse>,
-        sky: Vec<SkyResponse>
+        #[serde(serialize_with = "serialize_poll_sky")]
+        sky: Vec<SkyResponse>,
+        /// Any fields not otherwise recognised.
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
     #[serde(rename = "DEVICE")]
     Device(DeviceObject),
     #[serde(rename = "DEVICES")]
     Devices {
+        #[serde(serialize_with = "serialize_devices")]
         devices: Vec<DeviceObject>,
-        remote: Option<String>
+        #[serde(skip_serializing_if = "Option::is_none")]
+        remote: Option<String>,
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
     #[serde(rename = "WATCH")]
     Watch(WatchObject),
@@ -415,11 +1774,214 @@ pub enum Response {
         rev: String,
         proto_major: u32,
         proto_minor: u32,
-        remote: Option<String>
+        #[serde(skip_serializing_if = "Option::is_none")]
+        remote: Option<String>,
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
     #[serde(rename = "ERROR")]
     Error {
-        message: String
+        message: String,
+        #[serde(flatten)]
+        extra: Map<String, Value>
     },
-    Raw(String)
+    /// A line of raw data, received in raw mode.
+    ///
+    /// This isn't JSON, so can't be serialized; use `to_gpsd_json`.
+    #[serde(skip_serializing)]
+    Raw(RawFrame),
+    /// An NMEA sentence, received in pseudo-NMEA mode.
+    ///
+    /// This isn't JSON, so can't be serialized; use `to_gpsd_json`.
+    #[serde(skip_serializing)]
+    Nmea(NmeaSentence)
+}
+/// The classes of object a `Response` can be deserialized from.
+#[derive(Deserialize, Clone, Copy)]
+enum Class {
+    #[serde(rename = "TPV")]
+    Tpv,
+    #[serde(rename = "SKY")]
+    Sky,
+    #[serde(rename = "POLL")]
+    Poll,
+    #[serde(rename = "DEVICE")]
+    Device,
+    #[serde(rename = "DEVICES")]
+    Devices,
+    #[serde(rename = "WATCH")]
+    Watch,
+    #[serde(rename = "VERSION")]
+    Version,
+    #[serde(rename = "ERROR")]
+    Error
+}
+/// Deserialize a list of objects nested in a POLL or DEVICES response. Each
+/// has its own `class`, which is implied by where it is, so isn't kept.
+fn deserialize_tagged<'de, T, D>(de: D, extra: fn(&mut T) -> &mut Map<String, Value>) -> Result<Vec<T>, D::Error>
+    where T: Deserialize<'de>, D: Deserializer<'de> {
+    let mut items = Vec::<T>::deserialize(de)?;
+    for item in items.iter_mut() {
+        extra(item).shift_remove("class");
+    }
+    Ok(items)
+}
+fn deserialize_poll_tpv<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<TpvResponse>, D::Error> {
+    deserialize_tagged(de, TpvResponse::extra_mut)
+}
+fn deserialize_poll_sky<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<SkyResponse>, D::Error> {
+    deserialize_tagged(de, |sky: &mut SkyResponse| &mut sky.extra)
+}
+fn deserialize_devices<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<DeviceObject>, D::Error> {
+    deserialize_tagged(de, DeviceObject::extra_mut)
+}
+/// The rest of a POLL object.
+#[derive(Deserialize)]
+struct PollBody {
+    time: DateTime<Utc>,
+    active: u32,
+    #[serde(deserialize_with = "deserialize_poll_tpv")]
+    tpv: Vec<TpvResponse>,
+    #[serde(deserialize_with = "deserialize_poll_sky")]
+    sky: Vec<SkyResponse>,
+    #[serde(flatten)]
+    extra: Map<String, Value>
+}
+/// The rest of a DEVICES object.
+#[derive(Deserialize)]
+struct DevicesBody {
+    #[serde(deserialize_with = "deserialize_devices")]
+    devices: Vec<DeviceObject>,
+    remote: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>
+}
+/// The rest of a VERSION object.
+#[derive(Deserialize)]
+struct VersionBody {
+    release: String,
+    rev: String,
+    proto_major: u32,
+    proto_minor: u32,
+    remote: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>
+}
+/// The rest of an ERROR object.
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>
+}
+/// Deserialize the rest of an object of class `class`.
+fn deserialize_class<'de, D: Deserializer<'de>>(class: Class, de: D) -> Result<Response, D::Error> {
+    Ok(match class {
+        Class::Tpv => Response::Tpv(TpvResponse::deserialize(de)?),
+        Class::Sky => Response::Sky(SkyResponse::deserialize(de)?),
+        Class::Poll => {
+            let PollBody { time, active, tpv, sky, extra } = PollBody::deserialize(de)?;
+            Response::Poll { time, active, tpv, sky, extra }
+        },
+        Class::Device => Response::Device(DeviceObject::deserialize(de)?),
+        Class::Devices => {
+            let DevicesBody { devices, remote, extra } = DevicesBody::deserialize(de)?;
+            Response::Devices { devices, remote, extra }
+        },
+        Class::Watch => Response::Watch(WatchObject::deserialize(de)?),
+        Class::Version => {
+            let VersionBody { release, rev, proto_major, proto_minor, remote, extra } = VersionBody::deserialize(de)?;
+            Response::Version { release, rev, proto_major, proto_minor, remote, extra }
+        },
+        Class::Error => {
+            let ErrorBody { message, extra } = ErrorBody::deserialize(de)?;
+            Response::Error { message, extra }
+        }
+    })
+}
+/// The first key of an object, which is `class` if it came from gpsd.
+enum FirstKey {
+    Class,
+    Other(String)
+}
+impl<'de> Deserialize<'de> for FirstKey {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = FirstKey;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a field name")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<FirstKey, E> {
+                Ok(if v == "class" { FirstKey::Class } else { FirstKey::Other(v.into()) })
+            }
+        }
+        de.deserialize_identifier(KeyVisitor)
+    }
+}
+struct ResponseVisitor;
+impl<'de> Visitor<'de> for ResponseVisitor {
+    type Value = Response;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a gpsd JSON object")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Response, A::Error> {
+        // gpsd always sends `class` first, so the rest of the object can be
+        // deserialized as it's read. Anything else has to be buffered until
+        // its `class` turns up.
+        let first = match map.next_key::<FirstKey>()? {
+            Some(FirstKey::Class) => {
+                let class = map.next_value::<Class>()?;
+                return deserialize_class(class, MapAccessDeserializer::new(map));
+            },
+            Some(FirstKey::Other(key)) => key,
+            None => return Err(A::Error::missing_field("class"))
+        };
+        let mut obj = Map::new();
+        obj.insert(first, map.next_value()?);
+        while let Some((key, val)) = map.next_entry::<String, Value>()? {
+            obj.insert(key, val);
+        }
+        let class = obj.shift_remove("class").ok_or_else(|| A::Error::missing_field("class"))?;
+        let class = Class::deserialize(class).map_err(A::Error::custom)?;
+        deserialize_class(class, Value::Object(obj)).map_err(A::Error::custom)
+    }
+}
+impl<'de> Deserialize<'de> for Response {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_map(ResponseVisitor)
+    }
+}
+impl Response {
+    /// The gpsd class of this response, e.g. `"TPV"`. Raw data and NMEA
+    /// sentences don't have one.
+    pub fn class(&self) -> Option<&'static str> {
+        match *self {
+            Response::Tpv(..) => Some("TPV"),
+            Response::Sky(..) => Some("SKY"),
+            Response::Poll { .. } => Some("POLL"),
+            Response::Device(..) => Some("DEVICE"),
+            Response::Devices { .. } => Some("DEVICES"),
+            Response::Watch(..) => Some("WATCH"),
+            Response::Version { .. } => Some("VERSION"),
+            Response::Error { .. } => Some("ERROR"),
+            Response::Raw(..) | Response::Nmea(..) => None
+        }
+    }
+    /// Format this response as the line gpsd would have sent for it, without
+    /// the trailing `\r\n`.
+    ///
+    /// JSON objects have their `class` first and their fields in the order
+    /// gpsd writes them, followed by any unrecognised fields in the order they
+    /// were received. Numbers are written in their shortest form, so a value
+    /// gpsd padded with zeroes (like `"alt":20.100`) comes out unpadded, but
+    /// is otherwise unchanged. Raw data and NMEA sentences are written out as
+    /// they were received.
+    pub fn to_gpsd_json(&self) -> String {
+        match *self {
+            Response::Raw(ref frame) => frame.to_string(),
+            Response::Nmea(ref sentence) => sentence.to_string(),
+            _ => ::serde_json::to_string(self).expect("JSON responses are always serializable")
+        }
+    }
 }